@@ -10,14 +10,6 @@ use sml_util::span::Span;
 use sml_util::Const;
 use std::{cell::Cell, collections::HashMap};
 
-pub struct Entry {
-    name: Symbol,
-    tyvars: Vec<usize>,
-    // usages:
-}
-
-pub struct Monomorphizer {}
-
 pub struct Rename<'a> {
     pub decls: Vec<Decl<'a>>,
     arena: &'a CoreArena<'a>,
@@ -104,11 +96,18 @@ impl<'a> Rename<'a> {
 
     pub fn visit_decl(&mut self, decl: &Decl<'a>) -> Decl<'a> {
         match decl {
-            Decl::Val(vars, Rule { pat, expr }) => {
+            Decl::Val(vars, Rule { pat, expr, span }) => {
                 let pat = self.visit_pat(&pat);
                 let expr = self.visit_expr(&expr);
 
-                Decl::Val(vars.clone(), Rule { pat, expr })
+                Decl::Val(
+                    vars.clone(),
+                    Rule {
+                        pat,
+                        expr,
+                        span: *span,
+                    },
+                )
             }
             Decl::Fun(vars, funs) => {
                 let funs = funs
@@ -209,6 +208,10 @@ impl<'a> Rename<'a> {
                 PatKind::App(con, None)
             }
             PatKind::Const(c) => PatKind::Const(*c),
+            PatKind::Layer(sym, pat) => {
+                let sym = self.register_val(*sym);
+                PatKind::Layer(sym, self.visit_pat(pat))
+            }
             PatKind::Record(fields) => PatKind::Record(SortedRecord::new_unchecked(
                 fields
                     .iter()
@@ -216,6 +219,9 @@ impl<'a> Rename<'a> {
                     .collect(),
             )),
             PatKind::Var(sym) => PatKind::Var(self.register_val(*sym)),
+            PatKind::Vector(pats) => {
+                PatKind::Vector(pats.iter().map(|p| self.visit_pat(p)).collect())
+            }
             PatKind::Wild => PatKind::Var(self.fresh()),
         };
         Pat::new(
@@ -244,7 +250,11 @@ impl<'a> Rename<'a> {
                         let pat = self.visit_pat(&rule.pat);
                         let expr = self.visit_expr(&rule.expr);
                         self.leave();
-                        Rule { pat, expr }
+                        Rule {
+                            pat,
+                            expr,
+                            span: rule.span,
+                        }
                     })
                     .collect();
 
@@ -292,6 +302,9 @@ impl<'a> Rename<'a> {
                 ExprKind::Seq(exprs.iter().map(|e| self.visit_expr(e)).collect())
             }
             ExprKind::Var(s) => ExprKind::Var(self.swap_value(*s).expect("BUG")),
+            ExprKind::Vector(exprs) => {
+                ExprKind::Vector(exprs.iter().map(|e| self.visit_expr(e)).collect())
+            }
         };
         Expr::new(
             self.arena.exprs.alloc(kind),
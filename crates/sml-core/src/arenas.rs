@@ -199,6 +199,7 @@ impl<'a> CoreArena<'a> {
             Rule {
                 pat: self.pat_var(var_name, expr.ty),
                 expr,
+                span: Span::dummy(),
             },
         );
         Expr::new(
@@ -224,6 +225,7 @@ impl<'a> CoreArena<'a> {
             Rule {
                 pat,
                 expr: self.expr_var(var_name, pat.ty),
+                span: Span::dummy(),
             },
         );
         Expr::new(
@@ -253,6 +255,7 @@ impl<'a> CoreArena<'a> {
             Rule {
                 pat,
                 expr: self.expr_var(var_name, pat.ty),
+                span: Span::dummy(),
             },
         );
         Expr::new(
@@ -290,6 +293,13 @@ impl<'ar> ExprArena<'ar> {
         Symbol::Gensym(x)
     }
 
+    /// Restart gensym numbering from zero. Symbols allocated before the reset
+    /// will collide with those allocated after it, so this is only safe once
+    /// the previously elaborated terms are no longer in use
+    pub fn reset_fresh(&self) {
+        self.fresh.set(0);
+    }
+
     pub fn tuple<I: IntoIterator<Item = Expr<'ar>>>(&self, iter: I) -> &'ar ExprKind<'ar> {
         let rows = iter
             .into_iter()
@@ -350,6 +360,7 @@ pub struct TypeArena<'ar> {
     _exn: &'ar Type<'ar>,
     _bool: &'ar Type<'ar>,
     _int: &'ar Type<'ar>,
+    _word: &'ar Type<'ar>,
     _str: &'ar Type<'ar>,
     _char: &'ar Type<'ar>,
     _unit: &'ar Type<'ar>,
@@ -360,6 +371,7 @@ impl<'ar> TypeArena<'ar> {
         let _exn = types.alloc(Type::Con(builtin::tycons::T_EXN, Vec::new()));
         let _bool = types.alloc(Type::Con(builtin::tycons::T_BOOL, Vec::new()));
         let _int = types.alloc(Type::Con(builtin::tycons::T_INT, Vec::new()));
+        let _word = types.alloc(Type::Con(builtin::tycons::T_WORD, Vec::new()));
         let _str = types.alloc(Type::Con(builtin::tycons::T_STRING, Vec::new()));
         let _char = types.alloc(Type::Con(builtin::tycons::T_CHAR, Vec::new()));
         let _unit = types.alloc(Type::Con(builtin::tycons::T_UNIT, Vec::new()));
@@ -371,6 +383,7 @@ impl<'ar> TypeArena<'ar> {
             _exn,
             _bool,
             _int,
+            _word,
             _str,
             _char,
             _unit,
@@ -392,6 +405,20 @@ impl<'ar> TypeArena<'ar> {
         self.vars.alloc(TypeVar::new(x, rank))
     }
 
+    /// The id that the next fresh type variable will be given. Every type
+    /// variable allocated so far has a smaller id
+    pub fn next_var_id(&self) -> usize {
+        self.fresh.get()
+    }
+
+    /// Create a fresh type variable restricted to the types in `class`
+    pub fn fresh_overloaded(&self, rank: usize, class: Overload) -> &'ar Type<'ar> {
+        let x = self.fresh.get();
+        self.fresh.set(x + 1);
+        let tvar = self.vars.alloc(TypeVar::overloaded(x, rank, class));
+        self.types.alloc(Type::Var(tvar))
+    }
+
     pub fn alloc_tuple<I: IntoIterator<Item = Type<'ar>>>(&self, iter: I) -> &'ar Type<'ar> {
         let rows = iter
             .into_iter()
@@ -436,6 +463,10 @@ impl<'ar> TypeArena<'ar> {
         self._int
     }
 
+    pub fn word(&self) -> &'ar Type<'ar> {
+        self._word
+    }
+
     pub fn bool(&self) -> &'ar Type<'ar> {
         self._bool
     }
@@ -454,6 +485,16 @@ impl<'ar> TypeArena<'ar> {
             .alloc(Type::Con(builtin::tycons::T_LIST, vec![ty]))
     }
 
+    pub fn option(&self, ty: &'ar Type<'ar>) -> &'ar Type<'ar> {
+        self.types
+            .alloc(Type::Con(builtin::tycons::T_OPTION, vec![ty]))
+    }
+
+    pub fn vector(&self, ty: &'ar Type<'ar>) -> &'ar Type<'ar> {
+        self.types
+            .alloc(Type::Con(builtin::tycons::T_VECTOR, vec![ty]))
+    }
+
     pub fn arrow(&self, dom: &'ar Type<'ar>, rng: &'ar Type<'ar>) -> &'ar Type<'ar> {
         self.types
             .alloc(Type::Con(builtin::tycons::T_ARROW, vec![dom, rng]))
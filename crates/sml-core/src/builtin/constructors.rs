@@ -68,4 +68,24 @@ pub const C_BIND: Constructor = Constructor {
     type_arity: 0,
 };
 
-pub const C_BUILTINS: [Constructor; 7] = [C_NIL, C_CONS, C_TRUE, C_FALSE, C_REF, C_MATCH, C_BIND];
+// datatype 'a option = NONE | SOME of 'a
+pub const C_NONE: Constructor = Constructor {
+    name: S_NONE,
+    tycon: S_OPTION,
+    type_id: TypeId(10),
+    tag: 0,
+    arity: 0,
+    type_arity: 2,
+};
+pub const C_SOME: Constructor = Constructor {
+    name: S_SOME,
+    tycon: S_OPTION,
+    type_id: TypeId(10),
+    tag: 1,
+    arity: 1,
+    type_arity: 2,
+};
+
+pub const C_BUILTINS: [Constructor; 9] = [
+    C_NIL, C_CONS, C_TRUE, C_FALSE, C_REF, C_MATCH, C_BIND, C_NONE, C_SOME,
+];
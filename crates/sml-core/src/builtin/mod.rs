@@ -3,6 +3,7 @@ pub mod tycons;
 
 use super::*;
 use crate::elaborate::*;
+use sml_util::interner::*;
 
 fn define_constructor<'arena>(
     ctx: &mut elaborate::Context<'arena>,
@@ -12,6 +13,20 @@ fn define_constructor<'arena>(
     ctx.define_value(con.name, Span::dummy(), sch, IdStatus::Con(con));
 }
 
+fn define_value<'arena>(ctx: &mut elaborate::Context<'arena>, name: Symbol, ty: &'arena Type<'arena>) {
+    ctx.define_value(name, Span::dummy(), Scheme::Mono(ty), IdStatus::Var);
+}
+
+fn define_poly<'arena>(
+    ctx: &mut elaborate::Context<'arena>,
+    name: Symbol,
+    vars: &[&'arena Type<'arena>],
+    ty: &'arena Type<'arena>,
+) {
+    let vars = vars.iter().map(|v| v.as_tyvar().id).collect();
+    ctx.define_value(name, Span::dummy(), Scheme::Poly(vars, ty), IdStatus::Var);
+}
+
 /// This is not pretty, but we have to handle builtins for elaboration somehow
 pub fn populate_context<'arena>(ctx: &mut elaborate::Context<'arena>) {
     // Build the initial type environment
@@ -65,4 +80,153 @@ pub fn populate_context<'arena>(ctx: &mut elaborate::Context<'arena>) {
             ctx.arena.types.arrow(reff, ctx.arena.types.reff(reff)),
         ),
     );
+
+    let opt = ctx.arena.types.fresh_var(0);
+    define_constructor(
+        ctx,
+        constructors::C_NONE,
+        Scheme::Poly(vec![opt.as_tyvar().id], ctx.arena.types.option(opt)),
+    );
+
+    let some = ctx.arena.types.fresh_var(0);
+    define_constructor(
+        ctx,
+        constructors::C_SOME,
+        Scheme::Poly(
+            vec![some.as_tyvar().id],
+            ctx.arena.types.arrow(some, ctx.arena.types.option(some)),
+        ),
+    );
+
+    // exception Match and exception Bind, raised by inexhaustive matches and
+    // `val` bindings respectively
+    for con in &[constructors::C_MATCH, constructors::C_BIND] {
+        let exn = Scheme::Mono(ctx.arena.types.exn());
+        ctx.define_value(con.name, Span::dummy(), exn, IdStatus::Exn(*con));
+    }
+
+    // Record the constructors of the builtin datatypes, so that they may be
+    // replicated just like user-defined ones
+    use constructors::*;
+    let datatypes = [
+        vec![C_NIL, C_CONS],
+        vec![C_TRUE, C_FALSE],
+        vec![C_REF],
+        vec![C_NONE, C_SOME],
+    ];
+    for cons in datatypes.iter() {
+        ctx.define_datatype(cons[0].type_id, cons);
+    }
+
+    // Monomorphic basis functions, which are only here so that programs using
+    // them can be type checked
+    let types = &ctx.arena.types;
+    let (int, char, string) = (types.int(), types.char(), types.string());
+    let basis = [
+        (S_CARET, types.arrow(types.tuple(vec![string, string]), string)),
+        (S_INT_TOSTRING, types.arrow(int, string)),
+        (S_INT_FROMSTRING, types.arrow(string, types.option(int))),
+        (S_CHAR_ORD, types.arrow(char, int)),
+        (S_CHAR_CHR, types.arrow(int, char)),
+        (S_STRING_SIZE, types.arrow(string, int)),
+        (S_STRING_SUB, types.arrow(types.tuple(vec![string, int]), char)),
+        (S_IDIV, types.arrow(types.tuple(vec![int, int]), int)),
+        (S_MOD, types.arrow(types.tuple(vec![int, int]), int)),
+    ];
+    for (name, ty) in basis.iter() {
+        define_value(ctx, *name, ty);
+    }
+
+    // val o : ('b -> 'c) * ('a -> 'b) -> 'a -> 'c
+    let types = &ctx.arena.types;
+    let (a, b, c) = (types.fresh_var(0), types.fresh_var(0), types.fresh_var(0));
+    let args = types.tuple(vec![types.arrow(b, c), types.arrow(a, b)]);
+    let compose = types.arrow(args, types.arrow(a, c));
+    define_poly(ctx, S_COMPOSE, &[a, b, c], compose);
+
+    // val @ : 'a list * 'a list -> 'a list
+    let types = &ctx.arena.types;
+    let a = types.fresh_var(0);
+    let append = types.arrow(
+        types.tuple(vec![types.list(a), types.list(a)]),
+        types.list(a),
+    );
+    define_poly(ctx, S_APPEND, &[a], append);
+
+    // val = : 'a * 'a -> bool
+    let types = &ctx.arena.types;
+    let a = types.fresh_var(0);
+    let eq = types.arrow(types.tuple(vec![a, a]), types.bool());
+    define_poly(ctx, S_EQUAL, &[a], eq);
+
+    // val List.map : ('a -> 'b) -> 'a list -> 'b list
+    let types = &ctx.arena.types;
+    let (a, b) = (types.fresh_var(0), types.fresh_var(0));
+    let map = types.arrow(types.arrow(a, b), types.arrow(types.list(a), types.list(b)));
+    define_poly(ctx, S_LIST_MAP, &[a, b], map);
+
+    // val List.foldl : ('a * 'b -> 'b) -> 'b -> 'a list -> 'b
+    let types = &ctx.arena.types;
+    let (a, b) = (types.fresh_var(0), types.fresh_var(0));
+    let foldl = types.arrow(
+        types.arrow(types.tuple(vec![a, b]), b),
+        types.arrow(b, types.arrow(types.list(a), b)),
+    );
+    define_poly(ctx, S_LIST_FOLDL, &[a, b], foldl);
+
+    // val List.length : 'a list -> int
+    let types = &ctx.arena.types;
+    let a = types.fresh_var(0);
+    let length = types.arrow(types.list(a), types.int());
+    define_poly(ctx, S_LIST_LENGTH, &[a], length);
+
+    // val List.rev : 'a list -> 'a list
+    let types = &ctx.arena.types;
+    let a = types.fresh_var(0);
+    let rev = types.arrow(types.list(a), types.list(a));
+    define_poly(ctx, S_LIST_REV, &[a], rev);
+
+    // val List.filter : ('a -> bool) -> 'a list -> 'a list
+    let types = &ctx.arena.types;
+    let a = types.fresh_var(0);
+    let filter = types.arrow(
+        types.arrow(a, types.bool()),
+        types.arrow(types.list(a), types.list(a)),
+    );
+    define_poly(ctx, S_LIST_FILTER, &[a], filter);
+
+    // val List.tabulate : int * (int -> 'a) -> 'a list
+    let types = &ctx.arena.types;
+    let a = types.fresh_var(0);
+    let tabulate = types.arrow(
+        types.tuple(vec![types.int(), types.arrow(types.int(), a)]),
+        types.list(a),
+    );
+    define_poly(ctx, S_LIST_TABULATE, &[a], tabulate);
+
+    // val before : 'a * unit -> 'a
+    let types = &ctx.arena.types;
+    let a = types.fresh_var(0);
+    let before = types.arrow(types.tuple(vec![a, types.unit()]), a);
+    define_poly(ctx, S_BEFORE, &[a], before);
+
+    // Overloaded arithmetic and comparison operators, whose type variable
+    // may only be instantiated to a type in its overload class
+    let types = &ctx.arena.types;
+    let num = types.fresh_overloaded(0, Overload::Num);
+    let arith = types.arrow(types.tuple(vec![num, num]), num);
+    for name in &[S_PLUS, S_MINUS, S_MUL] {
+        define_poly(ctx, *name, &[num], arith);
+    }
+    let unary = types.arrow(num, num);
+    for name in &[S_NEG, S_ABS] {
+        define_poly(ctx, *name, &[num], unary);
+    }
+
+    let types = &ctx.arena.types;
+    let numtxt = types.fresh_overloaded(0, Overload::NumText);
+    let cmp = types.arrow(types.tuple(vec![numtxt, numtxt]), types.bool());
+    for name in &[S_LT, S_GT, S_LE, S_GE] {
+        define_poly(ctx, *name, &[numtxt], cmp);
+    }
 }
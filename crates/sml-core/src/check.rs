@@ -9,6 +9,8 @@
 //! * No datbind or exnbind may bind `it`
 //! * No real constant may occur in a pattern
 //! * tyvarseqs across the same valbinds must be unique
+//! * The wildcard type `_` may only occur in type annotations, not in the
+//!   definition of a type, constructor, or exception
 
 use sml_frontend::ast::*;
 use sml_util::diagnostics::Diagnostic;
@@ -33,7 +35,6 @@ impl<'a> Check<'a> {
 
     fn check_pat(&mut self, pattern: &Pat) {
         use PatKind::*;
-        let mut vars = HashSet::new();
         let mut queue = VecDeque::new();
         queue.push_back(pattern);
         while let Some(pat) = queue.pop_front() {
@@ -43,7 +44,7 @@ impl<'a> Check<'a> {
                     queue.push_back(p);
                 }
                 Const(_) => {}
-                FlatApp(pats) | List(pats) => {
+                FlatApp(pats) | List(pats) | Vector(pats) => {
                     for pat in pats {
                         queue.push_back(pat)
                     }
@@ -51,19 +52,11 @@ impl<'a> Check<'a> {
                 Record(rows, _) => {
                     self.check_rows(rows, |c, p| c.check_pat(p));
                 }
-                Variable(sym) => {
-                    if !sym.builtin() && !vars.insert(*sym) {
-                        self.diags.push(
-                            Diagnostic::error(
-                                pattern.span,
-                                format!(
-                                    "duplicate variable in pattern: '{}'",
-                                    self.interner.get(*sym).unwrap_or("?")
-                                ),
-                            )
-                            .message(pat.span, "redefined here"),
-                        );
-                    }
+                // Variables bound more than once are reported during
+                // elaboration, which sees every pattern
+                Variable(_) => {}
+                Layer(_, p) => {
+                    queue.push_back(p);
                 }
                 Wild => {}
             }
@@ -137,7 +130,7 @@ impl<'a> Check<'a> {
                 }
                 self.check_expr(expr);
             }
-            List(exprs) => {
+            List(exprs) | Vector(exprs) => {
                 for expr in exprs {
                     self.check_expr(expr);
                 }
@@ -159,7 +152,7 @@ impl<'a> Check<'a> {
                     self.check_expr(expr);
                 }
             }
-            Var(_) => {}
+            Var(_) | Op(_) => {}
             While(_, _) => {
                 self.diags
                     .push(Diagnostic::error(expr.span, "`while` exprs not supported"));
@@ -186,7 +179,7 @@ impl<'a> Check<'a> {
         for db in datbinds {
             // check for duplicate tyvar or constructors
             self.check_tyvars(db.span, &db.tyvars);
-            self.check_rows(&db.constructors, |_, _| ());
+            self.check_variants(&db.constructors);
 
             for con in &db.constructors {
                 if BUILTIN_CONSTRUCTORS.contains(&con.label) {
@@ -203,24 +196,52 @@ impl<'a> Check<'a> {
     }
 
     fn check_variants(&mut self, datbinds: &[Variant]) {
-        self.check_rows(datbinds, |_, _| {});
+        self.check_rows(datbinds, |c, ty| {
+            if let Some(ty) = ty {
+                c.check_no_wild(ty);
+            }
+        });
+    }
+
+    /// Types in definitions must be fully specified, since there is nothing
+    /// to infer a wildcard from
+    fn check_no_wild(&mut self, ty: &Type) {
+        match &ty.data {
+            TypeKind::Wild => self.diags.push(Diagnostic::error(
+                ty.span,
+                "wildcard type `_` is only allowed in type annotations".to_string(),
+            )),
+            TypeKind::Var(_) => {}
+            TypeKind::Con(_, args) => {
+                for arg in args {
+                    self.check_no_wild(arg);
+                }
+            }
+            TypeKind::Record(rows) => {
+                for row in rows {
+                    self.check_no_wild(&row.data);
+                }
+            }
+        }
     }
 
-    fn check_valbinds(&mut self, sp: Span, tyvars: &[Symbol], pat: &Pat, expr: &Expr) {
+    fn check_valbinds(&mut self, sp: Span, tyvars: &[Symbol], vbs: &[Valbind]) {
         self.check_tyvars(sp, tyvars);
-        if let PatKind::Variable(s) = pat.data {
-            if BUILTIN_CONSTRUCTORS.contains(&s) {
-                self.diags.push(Diagnostic::error(
-                    pat.span,
-                    format!(
-                        "builtin data constructor '{}' cannot be rebound",
-                        self.interner.get(s).unwrap_or("?")
-                    ),
-                ));
+        for Valbind { pat, expr, .. } in vbs {
+            if let PatKind::Variable(s) = pat.data {
+                if BUILTIN_CONSTRUCTORS.contains(&s) {
+                    self.diags.push(Diagnostic::error(
+                        pat.span,
+                        format!(
+                            "builtin data constructor '{}' cannot be rebound",
+                            self.interner.get(s).unwrap_or("?")
+                        ),
+                    ));
+                }
             }
+            self.check_pat(pat);
+            self.check_expr(expr);
         }
-        self.check_pat(pat);
-        self.check_expr(expr);
     }
 
     fn check_funbinds(&mut self, sp: Span, tyvars: &[Symbol], fbs: &[Fun]) {
@@ -278,9 +299,14 @@ impl<'a> Check<'a> {
                     ));
                 }
             }
-            Type(_) => {}
+            Replicate(_, _) => {}
+            Type(tbs) => {
+                for tb in tbs {
+                    self.check_no_wild(&tb.ty);
+                }
+            }
             Function(tyvars, fbs) => self.check_funbinds(decl.span, tyvars, fbs),
-            Value(tyvars, pat, expr) => self.check_valbinds(decl.span, tyvars, pat, expr),
+            Value(tyvars, vbs) => self.check_valbinds(decl.span, tyvars, vbs),
             Exception(vars) => {
                 self.check_variants(vars);
                 if vars.len() >= 255 {
@@ -291,6 +317,16 @@ impl<'a> Check<'a> {
                 }
             }
             Fixity(_, _, _) => {}
+            Signature(_, specs, wheres) => {
+                for spec in specs {
+                    if let SpecKind::Manifest(tb) = &spec.data {
+                        self.check_no_wild(&tb.ty);
+                    }
+                }
+                for tb in wheres {
+                    self.check_no_wild(&tb.ty);
+                }
+            }
             Local(d1, d2) => {
                 self.check_decl(d1);
                 self.check_decl(d2);
@@ -1,13 +1,12 @@
 use crate::types::Type;
-use crate::{Decl, Expr, ExprKind, Pat, PatKind, Rule, SortedRecord};
-use sml_util::interner::Symbol;
+use crate::{Decl, Expr, ExprKind, Pat, PatKind, Row, Rule, SortedRecord};
 use sml_util::pretty_print::{PrettyPrinter, Print};
 
 use std::collections::HashMap;
 
 impl<T: Print> Print for &SortedRecord<T> {
     fn print<'a, 'b>(&self, pp: &'a mut PrettyPrinter<'b>) -> &'a mut PrettyPrinter<'b> {
-        if let Symbol::Tuple(_) = self[0].label {
+        if crate::is_tuple(self) {
             pp.text("(");
             for (idx, row) in self.iter().enumerate() {
                 pp.print(&row.data);
@@ -35,8 +34,19 @@ impl<'a> Print for Pat<'a> {
             PatKind::App(con, Some(pat)) => pp.print(&con.name).text(" ").print(pat),
             PatKind::App(con, None) => pp.print(&con.name),
             PatKind::Const(constant) => pp.print(&constant),
+            PatKind::Layer(sym, pat) => pp.print(sym).text(" as ").print(pat),
             PatKind::Record(record) => pp.print(&record),
             PatKind::Var(sym) => pp.print(sym),
+            PatKind::Vector(pats) => {
+                pp.text("#[");
+                for (idx, pat) in pats.iter().enumerate() {
+                    pp.print(pat);
+                    if idx != pats.len().saturating_sub(1) {
+                        pp.text(", ");
+                    }
+                }
+                pp.text("]")
+            }
             PatKind::Wild => pp.text("_"),
         }
     }
@@ -71,7 +81,7 @@ impl<'a> Print for Expr<'a> {
             Const(c) => pp.print(&c),
             Handle(tryy, sym, handler) => pp
                 .print(tryy)
-                .text("handle ")
+                .text(" handle ")
                 .print(sym)
                 .text(" with ")
                 .print(handler),
@@ -118,6 +128,16 @@ impl<'a> Print for Expr<'a> {
                 pp.text(")")
             }
             Var(s) => pp.print(s),
+            Vector(exprs) => {
+                pp.text("#[");
+                for (idx, expr) in exprs.iter().enumerate() {
+                    pp.print(expr);
+                    if idx != exprs.len().saturating_sub(1) {
+                        pp.text(", ");
+                    }
+                }
+                pp.text("]")
+            }
         }
     }
 }
@@ -148,7 +168,7 @@ impl<'a> Type<'a> {
                 }
             }
             Type::Record(fields) => {
-                if let Symbol::Tuple(_) = fields[0].label {
+                if crate::is_tuple(fields) {
                     for (idx, row) in fields.iter().enumerate() {
                         row.data.print_rename(pp, map);
                         if idx != fields.rows.len() - 1 {
@@ -158,7 +178,7 @@ impl<'a> Type<'a> {
                     pp
                 } else {
                     pp.text("{");
-                    for (idx, row) in fields.iter().enumerate() {
+                    for (idx, row) in sorted_labels(pp, fields).into_iter().enumerate() {
                         pp.print(&row.label).text(": ");
                         row.data.print_rename(pp, map);
                         if idx != fields.rows.len() - 1 {
@@ -192,7 +212,7 @@ impl<'a> Type<'a> {
                 Some(ty) => ty.print_rename(pp, map),
                 None => {
                     pp.text("{");
-                    for row in flex.constraints.iter() {
+                    for row in sorted_labels(pp, &flex.constraints) {
                         pp.print(&row.label).text(": ");
                         row.data.print_rename(pp, map);
                         pp.text(", ");
@@ -204,6 +224,16 @@ impl<'a> Type<'a> {
     }
 }
 
+/// Record types are kept sorted by `Symbol`, which orders labels by when
+/// they were interned. Print them in the order of `Interner::compare_labels`
+/// instead, so that the output doesn't depend on it
+fn sorted_labels<'r, T>(pp: &PrettyPrinter<'_>, rows: &'r [Row<T>]) -> Vec<&'r Row<T>> {
+    let interner = pp.interner();
+    let mut rows = rows.iter().collect::<Vec<_>>();
+    rows.sort_by(|a, b| interner.compare_labels(a.label, b.label));
+    rows
+}
+
 fn print_tyvars<'b, 'c>(
     ids: &[usize],
     map: &mut HashMap<usize, String>,
@@ -240,7 +270,7 @@ impl<'a> Print for Decl<'a> {
     fn print<'b, 'c>(&self, pp: &'b mut PrettyPrinter<'c>) -> &'b mut PrettyPrinter<'c> {
         let mut map = HashMap::new();
         match self {
-            Decl::Val(vars, Rule { pat, expr }) => {
+            Decl::Val(vars, Rule { pat, expr, .. }) => {
                 pp.line().text("val ");
                 print_tyvars(&vars, &mut map, pp)
                     .print(pat)
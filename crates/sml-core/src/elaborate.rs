@@ -3,18 +3,18 @@
 
 use crate::arenas::{CoreArena, TypeArena};
 use crate::builtin::{constructors, populate_context};
-use crate::types::{Constructor, Flex, Scheme, Tycon, Type, TypeVar};
+use crate::types::{ArityError, Constructor, Flex, Overload, Scheme, Tycon, Type, TypeVar};
 use crate::{
     Datatype, Decl, Expr, ExprId, ExprKind, Lambda, Pat, PatKind, Row, Rule, SortedRecord, TypeId,
 };
 use sml_frontend::ast;
 use sml_frontend::parser::precedence::{self, Fixity, Precedence, Query};
 use sml_util::diagnostics::{Diagnostic, Level};
-use sml_util::interner::{Interner, Symbol};
+use sml_util::interner::*;
 use sml_util::pretty_print::PrettyPrinter;
 use sml_util::span::Span;
 use sml_util::Const;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
 
 pub fn check_and_elaborate<'a>(
@@ -29,7 +29,7 @@ pub fn check_and_elaborate<'a>(
     let decls = ctx.elaborate_decl(decl);
 
     let mut diags = check.diags;
-    diags.extend(ctx.diagnostics(interner));
+    diags.extend(ctx.take_diagnostics(interner));
     (decls, diags)
 }
 
@@ -45,15 +45,34 @@ pub enum IdStatus {
 /// constructors, and without the indirection of going from names->id->def
 #[derive(Clone)]
 pub struct Cons<'a> {
-    name: Symbol,
-    scheme: Scheme<'a>,
+    pub name: Symbol,
+    pub scheme: Scheme<'a>,
+    pub con: Constructor,
+}
+
+impl<'a> Cons<'a> {
+    /// Instantiate the constructor's scheme with the datatype's type
+    /// arguments, returning the argument type if it takes one. This fails if
+    /// the number of arguments isn't the arity of the datatype
+    fn instantiate_arg(
+        &self,
+        arena: &'a TypeArena<'a>,
+        args: &[&'a Type<'a>],
+    ) -> Result<Option<&'a Type<'a>>, ArityError> {
+        let ty = self.scheme.instantiate_with(arena, args)?;
+        match self.con.arity {
+            0 => Ok(None),
+            _ => Ok(ty.de_arrow().map(|(arg, _)| arg)),
+        }
+    }
 }
 
 /// TyStr, a [`TypeStructure`] from the Defn. This is a component of the
 /// Type Environment, TE
 #[derive(Clone)]
 pub enum TypeStructure<'a> {
-    /// TyStr (t, VE), a datatype
+    /// TyStr (t, VE), a datatype. The constructors are stored in tag order,
+    /// so that the tag of each is its index
     Datatype(Tycon, Vec<Cons<'a>>),
     /// TyStr (_, VE), a definition. Rather than include a whole VE hashmap,
     /// we can include just a single entry
@@ -70,6 +89,45 @@ impl<'a> TypeStructure<'a> {
         }
     }
 
+    /// The constructors of a datatype in tag order, or nothing if this isn't
+    /// a datatype
+    pub fn constructors(&self) -> &[Cons<'a>] {
+        match self {
+            TypeStructure::Datatype(_, cons) => cons,
+            _ => &[],
+        }
+    }
+
+    /// The argument type of the constructor `name` when the datatype is
+    /// applied to `args`, or nothing if the constructor is nullary or isn't
+    /// one of this datatype's. This fails if the number of arguments isn't
+    /// the arity of the datatype
+    pub fn constructor_arg(
+        &self,
+        arena: &'a TypeArena<'a>,
+        name: Symbol,
+        args: &[&'a Type<'a>],
+    ) -> Result<Option<&'a Type<'a>>, ArityError> {
+        match self.constructors().iter().find(|c| c.name == name) {
+            Some(cons) => cons.instantiate_arg(arena, args),
+            None => Ok(None),
+        }
+    }
+
+    /// All of the constructors of a datatype in tag order, each paired with
+    /// its argument type when the datatype is applied to `args`. This fails
+    /// if the number of arguments isn't the arity of the datatype
+    pub fn constructor_args(
+        &self,
+        arena: &'a TypeArena<'a>,
+        args: &[&'a Type<'a>],
+    ) -> Result<Vec<(&Cons<'a>, Option<&'a Type<'a>>)>, ArityError> {
+        self.constructors()
+            .iter()
+            .map(|c| Ok((c, c.instantiate_arg(arena, args)?)))
+            .collect()
+    }
+
     pub fn apply(&self, arena: &'a TypeArena<'a>, args: Vec<&'a Type<'a>>) -> &'a Type<'a> {
         match self {
             TypeStructure::Tycon(con) | TypeStructure::Datatype(con, _) => {
@@ -94,6 +152,9 @@ struct PartialFun<'s, 'a> {
     ty: &'a Type<'a>,
 }
 
+/// A variable bound by a pattern, its type, and the span at which it's bound
+type Binding<'a> = (Symbol, &'a Type<'a>, Span);
+
 /// A partially elaborated ast::FnBinding
 struct PartialFnBinding<'s, 'a> {
     /// Function body
@@ -102,12 +163,14 @@ struct PartialFnBinding<'s, 'a> {
     pats: Vec<Pat<'a>>,
     /// A list of variables bound in `pats`, and fresh type variables
     /// to be associated with those symbols
-    bindings: Vec<(Symbol, &'a Type<'a>)>,
+    bindings: Vec<Binding<'a>>,
+    /// The clause's result type annotation `fun f x : ty = ...`, if any
+    annotation: Option<&'a Type<'a>>,
     span: Span,
 }
 
 /// An environment scope, that can hold a collection of type and expr bindings
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Namespace {
     parent: Option<usize>,
     depth: usize,
@@ -139,12 +202,77 @@ pub struct Context<'a> {
     /// polymorphic generalization
     pub tyvar_rank: usize,
 
-    /// Depth of nested `local` declarations
-    local_depth: usize,
+    /// Namespaces of `local` declarations whose body is currently being
+    /// elaborated. Anything defined directly in one of these namespaces is
+    /// exported to it's parent namespace instead
+    locals: Vec<usize>,
+
+    /// Functions in `fun` groups whose bodies are currently being elaborated,
+    /// along with the monomorphic type they are bound to until generalization,
+    /// and the type variables that were free in it when the group was bound
+    recursive: Vec<(Symbol, &'a Type<'a>, Vec<usize>)>,
 
     pub(crate) arena: &'a CoreArena<'a>,
     pub elab_errors: Vec<ElabError>,
     unification_errors: Vec<CantUnify<'a>>,
+
+    /// Exported top-level decls saved here
+    decls: Vec<Decl<'a>>,
+
+    /// Signature names, mapped to the [`Namespace`] holding their specifications
+    signatures: HashMap<Symbol, usize>,
+
+    /// Uses of overloaded identifiers in the current top-level declaration,
+    /// along with the overloaded type variable they were instantiated with
+    overloads: Vec<(&'a Type<'a>, Span, Symbol)>,
+
+    /// The source spans that introduced some of the type variables created
+    /// during elaboration, indexed by type variable id
+    tyvar_origins: HashMap<usize, Span>,
+
+    /// Whether overloaded identifiers whose type is still ambiguous at the
+    /// end of a top-level declaration default to `int`, as in the Definition,
+    /// or are reported as an error
+    pub overload_default_int: bool,
+
+    /// Current number of nested expressions, patterns and types being
+    /// elaborated
+    depth: usize,
+    /// Maximum nesting depth of expressions, patterns and types, past which
+    /// elaboration reports an error rather than overflowing the stack
+    pub max_depth: usize,
+    /// Whether the nesting limit has been reported for the children of the
+    /// innermost node, so that each of them isn't reported as well
+    too_deep: bool,
+    /// Diagnostics converted from the errors above, but not yet taken
+    diags: Vec<Diagnostic>,
+    /// Maximum number of types, and of values, that can be defined. A
+    /// [`TypeId`] or [`ExprId`] is a `u32` index, so this is at most
+    /// `u32::MAX`, past which ids would silently wrap around
+    pub(crate) max_ids: usize,
+}
+
+/// A snapshot of the state of a [`Context`], see [`Context::checkpoint`]
+pub struct Checkpoint {
+    /// Namespaces that definitions could be added to after the checkpoint
+    scopes: Vec<(usize, Namespace)>,
+    namespaces: usize,
+    current: usize,
+    types: usize,
+    values: usize,
+    tyvars: usize,
+    tyvar_rank: usize,
+    locals: usize,
+    recursive: usize,
+    elab_errors: usize,
+    unification_errors: usize,
+    overloads: usize,
+    diags: usize,
+    /// Type variables with this id or greater were created after the
+    /// checkpoint, so their origins are forgotten on rollback
+    next_tyvar: usize,
+    decls: usize,
+    signatures: HashMap<Symbol, usize>,
 }
 
 impl Namespace {
@@ -164,16 +292,40 @@ impl<'a> Context<'a> {
             namespaces: Vec::with_capacity(256),
             current: 0,
             tyvar_rank: 0,
-            local_depth: 0,
+            locals: Vec::new(),
+            recursive: Vec::new(),
             types: Vec::with_capacity(256),
             values: Vec::with_capacity(4096),
             elab_errors: Vec::default(),
             unification_errors: Vec::default(),
+            decls: Vec::default(),
+            signatures: HashMap::new(),
+            overloads: Vec::new(),
+            tyvar_origins: HashMap::new(),
+            overload_default_int: true,
+            depth: 0,
+            max_depth: 1000,
+            too_deep: false,
+            diags: Vec::new(),
+            max_ids: u32::MAX as usize,
             arena,
         };
         ctx.namespaces.push(Namespace::default());
         populate_context(&mut ctx);
-        ctx.elab_decl_fixity(&ast::Fixity::Infixr, 4, constructors::C_CONS.name);
+        // Fixities of the infix identifiers in the standard basis
+        ctx.elab_decl_fixity(&ast::Fixity::Infix, 7, S_MUL);
+        ctx.elab_decl_fixity(&ast::Fixity::Infix, 7, S_IDIV);
+        ctx.elab_decl_fixity(&ast::Fixity::Infix, 7, S_MOD);
+        ctx.elab_decl_fixity(&ast::Fixity::Infix, 6, S_PLUS);
+        ctx.elab_decl_fixity(&ast::Fixity::Infix, 6, S_MINUS);
+        ctx.elab_decl_fixity(&ast::Fixity::Infix, 6, S_CARET);
+        ctx.elab_decl_fixity(&ast::Fixity::Infixr, 5, constructors::C_CONS.name);
+        ctx.elab_decl_fixity(&ast::Fixity::Infixr, 5, S_APPEND);
+        for op in &[S_EQUAL, S_LT, S_GT, S_LE, S_GE] {
+            ctx.elab_decl_fixity(&ast::Fixity::Infix, 4, *op);
+        }
+        ctx.elab_decl_fixity(&ast::Fixity::Infixr, 3, S_COMPOSE);
+        ctx.elab_decl_fixity(&ast::Fixity::Infix, 0, S_BEFORE);
         ctx
     }
 
@@ -204,26 +356,41 @@ impl<'a> Context<'a> {
         r
     }
 
+    /// Return the index of the namespace that definitions in the current
+    /// scope are bound in, taking into account whether we are directly in the
+    /// body of a (possibly nested) `local` declaration
+    #[inline]
+    fn export_ns(&self) -> usize {
+        let mut id = self.current;
+        while self.locals.contains(&id) {
+            id = self.namespaces[id].parent.unwrap_or(id);
+        }
+        id
+    }
+
+    /// Apply `f` to the namespaces of the `local` declarations between the
+    /// current scope and [`Context::export_ns`]. A name exported from the body
+    /// of a `local` is removed from these, so that it isn't shadowed there by a
+    /// hidden binding of the same name
+    fn hide_locals<F: Fn(&mut Namespace)>(&mut self, f: F) {
+        let mut id = self.current;
+        while self.locals.contains(&id) {
+            f(&mut self.namespaces[id]);
+            id = self.namespaces[id].parent.unwrap_or(id);
+        }
+    }
+
     /// Return a mutable reference to the current namespace, taking into account
     /// whether we are in the body of a `local` declaration
     #[inline]
     pub fn current_ns_mut(&mut self) -> &mut Namespace {
-        if self.local_depth > 0 {
-            let id = self.namespaces[self.current].parent.unwrap_or(self.current);
-            &mut self.namespaces[id]
-        } else {
-            &mut self.namespaces[self.current]
-        }
+        let id = self.export_ns();
+        &mut self.namespaces[id]
     }
 
     #[inline]
     pub fn current_ns(&self) -> &Namespace {
-        if self.local_depth > 0 {
-            let id = self.namespaces[self.current].parent.unwrap_or(self.current);
-            &self.namespaces[id]
-        } else {
-            &self.namespaces[self.current]
-        }
+        &self.namespaces[self.export_ns()]
     }
 
     #[inline]
@@ -231,11 +398,23 @@ impl<'a> Context<'a> {
         self.current_ns().depth
     }
 
+    /// Return the index of the next entry of a table with `len` entries as an
+    /// id, panicking if it would exceed [`Context::max_ids`]
+    fn next_id(&self, len: usize, what: &str) -> u32 {
+        if len >= self.max_ids {
+            panic!("too many {} defined, the limit is {}", what, self.max_ids);
+        }
+        len as u32
+    }
+
     /// Globally define a type
     pub(crate) fn define_type(&mut self, sym: Symbol, tystr: TypeStructure<'a>) -> TypeId {
-        let id = TypeId(self.types.len() as u32);
+        let id = TypeId(self.next_id(self.types.len(), "types"));
         self.types.push(tystr);
         self.current_ns_mut().types.insert(sym, id);
+        self.hide_locals(|ns| {
+            ns.types.remove(&sym);
+        });
         id
     }
 
@@ -247,16 +426,48 @@ impl<'a> Context<'a> {
         scheme: Scheme<'a>,
         status: IdStatus,
     ) -> ExprId {
-        let id = ExprId(self.values.len() as u32);
+        let id = ExprId(self.next_id(self.values.len(), "values"));
         let scheme = self.check_scheme(span, scheme);
         self.values.push((scheme, status));
         self.current_ns_mut().values.insert(sym, id);
+        self.hide_locals(|ns| {
+            ns.values.remove(&sym);
+        });
         id
     }
 
+    /// Attach the constructors of an already defined datatype to its
+    /// [`TypeStructure`], so that the datatype can later be replicated.
+    ///
+    /// The constructors are sorted by tag, and the tags must then be exactly
+    /// `0..n`, since the match compiler relies on a datatype with `n`
+    /// constructors being covered by `n` distinct tags
+    pub(crate) fn define_datatype(&mut self, id: TypeId, cons: &[Constructor]) {
+        let mut cons = cons
+            .iter()
+            .filter_map(|con| {
+                let (scheme, _) = self.lookup_value(&con.name)?;
+                Some(Cons {
+                    name: con.name,
+                    scheme: scheme.clone(),
+                    con: *con,
+                })
+            })
+            .collect::<Vec<_>>();
+        cons.sort_by_key(|c| c.con.tag);
+        debug_assert!(
+            cons.iter().enumerate().all(
+                |(idx, c)| c.con.tag as usize == idx && c.con.type_arity as usize == cons.len()
+            ),
+            "constructor tags of a datatype must be contiguous and start at 0"
+        );
+        if let TypeStructure::Tycon(tycon) = self.types[id.0 as usize] {
+            self.types[id.0 as usize] = TypeStructure::Datatype(tycon, cons);
+        }
+    }
+
     fn check_scheme(&mut self, span: Span, scheme: Scheme<'a>) -> Scheme<'a> {
-        if self.local_depth > 0 {
-            // is scope_depth() - 1 correct, or do we modify by local_depth
+        if !self.locals.is_empty() {
             match scheme {
                 Scheme::Mono(ty) => self.check_type_names(span, ty, self.scope_depth()),
                 Scheme::Poly(_, ty) => self.check_type_names(span, ty, self.scope_depth()),
@@ -273,7 +484,8 @@ impl<'a> Context<'a> {
     /// This will panic if `sym` is not defined in the current namespace
     /// tree
     fn unbind_value(&mut self, sym: Symbol) {
-        let id = self.namespaces[self.current]
+        let id = *self
+            .current_ns()
             .values
             .get(&sym)
             .expect("error: redefine_value");
@@ -308,6 +520,32 @@ impl<'a> Context<'a> {
         Some(&self.types[self.lookup_type_id(sym)?.0 as usize])
     }
 
+    /// Iterate over the current namespace and all of its ancestors, starting
+    /// with the innermost
+    fn namespace_iter(&self) -> impl Iterator<Item = &Namespace> + '_ {
+        let namespaces = &self.namespaces;
+        let mut id = Some(self.current);
+        std::iter::from_fn(move || {
+            let ns = &namespaces[id?];
+            id = ns.parent;
+            Some(ns)
+        })
+    }
+
+    /// Collect the ids of the unresolved type variables that occur free in
+    /// the schemes of all values visible from the current scope
+    pub fn env_free_tyvars(&self) -> HashSet<usize> {
+        let mut free = HashSet::new();
+        let mut vars = Vec::new();
+        for ns in self.namespace_iter() {
+            for id in ns.values.values() {
+                self.values[id.0 as usize].0.free_vars(&mut vars);
+                free.extend(vars.drain(..));
+            }
+        }
+        free
+    }
+
     fn lookup_value(&self, sym: &Symbol) -> Option<&(Scheme<'a>, IdStatus)> {
         let mut ptr = &self.namespaces[self.current];
         loop {
@@ -318,6 +556,61 @@ impl<'a> Context<'a> {
         }
     }
 
+    /// If the head of the application spine `expr` is a reference to a
+    /// function whose body is currently being elaborated, return the
+    /// monomorphic type that the function is bound to, and its own type
+    /// variables
+    fn recursive_call(&self, mut expr: &ast::Expr) -> Option<(&'a Type<'a>, Vec<usize>)> {
+        while let ast::ExprKind::App(e1, _) = &expr.data {
+            expr = e1;
+        }
+        match &expr.data {
+            ast::ExprKind::Var(sym) | ast::ExprKind::Op(sym) => match self.lookup_value(sym) {
+                Some((Scheme::Mono(ty), IdStatus::Var)) => self
+                    .recursive
+                    .iter()
+                    .find(|(name, rec, _)| name == sym && std::ptr::eq(*rec, *ty))
+                    .map(|(_, rec, own)| (*rec, own.clone())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Mark the types of the functions in `names` as the ones being
+    /// elaborated recursively, returning the previous length of
+    /// `self.recursive` to truncate it back to
+    fn bind_recursive<I: Iterator<Item = (Symbol, &'a Type<'a>)>>(&mut self, names: I) -> usize {
+        let n = self.recursive.len();
+        for (name, ty) in names {
+            let mut own = Vec::new();
+            ty.free_vars(&mut own);
+            self.recursive.push((name, ty, own));
+        }
+        n
+    }
+
+    /// Does a mismatch between `a` and `b` involve one of the type variables
+    /// `own` of the function type `ty`, either directly or through the type it
+    /// has since been resolved to?
+    fn involves_own_tyvars(ty: &Type<'a>, own: &[usize], a: &Type<'a>, b: &Type<'a>) -> bool {
+        let mut found = false;
+        ty.visit(|t| {
+            if let Type::Var(tv) = t {
+                if !own.contains(&tv.id) {
+                    return;
+                }
+                match tv.ty() {
+                    Some(link) => link.visit(|u| {
+                        found |= std::ptr::eq(u, a) || std::ptr::eq(u, b);
+                    }),
+                    None => found |= a.occurs(tv.id) || b.occurs(tv.id),
+                }
+            }
+        });
+        found
+    }
+
     fn lookup_tyvar(&mut self, s: &Symbol, allow_unbound: bool) -> Option<&'a TypeVar<'a>> {
         for (sym, tv) in self.tyvars.iter().rev() {
             if sym == s {
@@ -337,14 +630,43 @@ impl<'a> Context<'a> {
         self.arena.types.fresh_var(self.tyvar_rank)
     }
 
+    /// Create a fresh type variable, remembering that it was introduced by
+    /// the source at `sp`, so that type errors involving it can point there
+    fn fresh_tyvar_at(&mut self, sp: Span) -> &'a Type<'a> {
+        let ty = self.fresh_tyvar();
+        if sp != Span::dummy() {
+            self.tyvar_origins.insert(ty.as_tyvar().id, sp);
+        }
+        ty
+    }
+
     pub(crate) fn fresh_var(&self) -> Symbol {
         self.arena.exprs.allocate_id()
     }
 
+    /// Restart the numbering of compiler-generated variables, so that
+    /// elaborating the same input again yields the same gensyms. This resets
+    /// the counter of [`crate::arenas::ExprArena::allocate_id`], which is
+    /// shared by every pass. See [`crate::arenas::ExprArena::reset_fresh`] for
+    /// when this is safe
+    pub fn reset_gensym(&mut self) {
+        self.arena.exprs.reset_fresh();
+    }
+
+    /// Report that `expr` is applied to an argument, but its type is known
+    /// not to be a function type
+    fn not_a_function(&mut self, expr: Expr<'a>) {
+        let err = CantUnify::single(expr.ty)
+            .span(expr.span)
+            .message("this expression is not a function");
+        self.unification_errors.push(err);
+    }
+
     fn const_ty(&self, c: &Const) -> &'a Type<'a> {
         match c {
             Const::Char(_) => self.arena.types.char(),
             Const::Int(_) => self.arena.types.int(),
+            Const::Word(_) => self.arena.types.word(),
             Const::String(_) => self.arena.types.string(),
             Const::Unit => self.arena.types.unit(),
         }
@@ -364,21 +686,43 @@ impl<'a> Context<'a> {
         }
     }
 
-    pub fn diagnostics(&mut self, interner: &Interner) -> Vec<Diagnostic> {
-        // let mut diags = std::mem::replace(&mut self.diags, Vec::new());
-        let mut diags = Vec::new();
+    /// Convert the errors reported so far into diagnostics, pending in
+    /// `self.diags` until they are taken
+    fn convert_errors(&mut self, interner: &Interner) {
         let mut pp = PrettyPrinter::new(interner);
-        diags.extend(
+        self.diags.extend(
             self.elab_errors
                 .drain(..)
                 .filter_map(|e| e.convert_err(&mut pp)),
         );
-        diags.extend(
+        let origins = &self.tyvar_origins;
+        self.diags.extend(
             self.unification_errors
                 .drain(..)
-                .filter_map(|e| e.convert_err(&mut pp)),
+                .filter_map(|e| e.convert_err(&mut pp, origins)),
         );
-        diags
+    }
+
+    pub fn diagnostics(&mut self, interner: &Interner) -> Vec<Diagnostic> {
+        self.convert_errors(interner);
+        std::mem::take(&mut self.diags)
+    }
+
+    /// Like [`Context::diagnostics`], but without exact duplicates of an
+    /// earlier diagnostic, such as the same error reported twice for one
+    /// expression
+    pub fn take_diagnostics(&mut self, interner: &Interner) -> Vec<Diagnostic> {
+        self.convert_errors(interner);
+        self.dedup_diagnostics();
+        std::mem::take(&mut self.diags)
+    }
+
+    /// Remove pending diagnostics that are identical to an earlier one, in
+    /// both span and message, keeping the first occurrence
+    pub fn dedup_diagnostics(&mut self) {
+        let mut seen = HashSet::with_capacity(self.diags.len());
+        self.diags
+            .retain(|d| seen.insert((d.primary.span, d.primary.info.clone(), d.info.clone())));
     }
 }
 
@@ -391,12 +735,23 @@ pub struct ElabError {
 
 pub enum ErrorKind {
     Unbound(Symbol),
+    /// A type variable that isn't a parameter of the datatype it's used in
+    UnboundTyvar(Symbol, Symbol),
     Rebound(Symbol),
     Escape(Symbol),
+    NoConstructors(Symbol),
+    NotDatatype(Symbol),
+    NotAbstract(Symbol),
+    Duplicate(Symbol),
+    /// A variable bound twice by a pattern, and the span of its first binding
+    DuplicateVar(Symbol, Span),
+    Rigid(Symbol),
+    Constructor(Symbol),
     Arity(usize, usize),
     Redundant,
     Inexhaustive,
     Generalize,
+    Overload(Symbol, Overload),
     Message,
 }
 
@@ -427,14 +782,35 @@ impl ElabError {
                 write!(&mut buffer, "unbound {}: ", self.message).ok()?;
                 pp.print(&sym).write_fmt(&mut buffer).ok()?;
             }
+            ErrorKind::UnboundTyvar(var, tycon) => {
+                write!(&mut buffer, "unbound type variable `'").ok()?;
+                pp.print(&var).write_fmt(&mut buffer).ok()?;
+                write!(&mut buffer, "` in {} `", self.message).ok()?;
+                pp.print(&tycon).write_fmt(&mut buffer).ok()?;
+                write!(&mut buffer, "`").ok()?;
+            }
             ErrorKind::Rebound(sym) => {
                 write!(&mut buffer, "rebound {}: ", self.message).ok()?;
                 pp.print(&sym).write_fmt(&mut buffer).ok()?;
                 return Some(Diagnostic::warn(self.sp, buffer));
             }
-            ErrorKind::Escape(sym) => {
+            ErrorKind::Escape(sym)
+            | ErrorKind::NoConstructors(sym)
+            | ErrorKind::NotDatatype(sym)
+            | ErrorKind::NotAbstract(sym)
+            | ErrorKind::Duplicate(sym)
+            | ErrorKind::Constructor(sym) => {
+                write!(&mut buffer, "{}: ", self.message).ok()?;
+                pp.print(&sym).write_fmt(&mut buffer).ok()?;
+            }
+            ErrorKind::DuplicateVar(sym, first) => {
                 write!(&mut buffer, "{}: ", self.message).ok()?;
                 pp.print(&sym).write_fmt(&mut buffer).ok()?;
+                return Some(Diagnostic::error(self.sp, buffer).message(first, "first bound here"));
+            }
+            ErrorKind::Rigid(sym) => {
+                write!(&mut buffer, "{}: '", self.message).ok()?;
+                pp.print(&sym).write_fmt(&mut buffer).ok()?;
             }
             ErrorKind::Arity(expect, got) => {
                 write!(
@@ -447,6 +823,11 @@ impl ElabError {
             ErrorKind::Generalize => {
                 return Some(Diagnostic::warn(self.sp, self.message));
             }
+            ErrorKind::Overload(sym, class) => {
+                write!(&mut buffer, "{}: ", self.message).ok()?;
+                pp.print(&sym).write_fmt(&mut buffer).ok()?;
+                write!(&mut buffer, ", candidate types are {}", class.describe()).ok()?;
+            }
             _ => {
                 buffer = self.message;
             }
@@ -459,6 +840,18 @@ impl ElabError {
     }
 }
 
+/// How the types of a [`CantUnify`] error are reported
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum MessageKind {
+    /// `ty1` was expected, but `ty2` was found
+    Mismatch,
+    /// Only `ty1` is wrong in itself, rather than mismatched with `ty2`
+    Single,
+    /// `ty1` is the type of the patterns of a `case` and `ty2` is the type of
+    /// its scrutinee, rather than the parts of them that failed to unify
+    Scrutinee,
+}
+
 struct CantUnify<'a> {
     pub ty1: &'a Type<'a>,
     pub ty2: &'a Type<'a>,
@@ -467,6 +860,7 @@ struct CantUnify<'a> {
     pub message: String,
     pub reason: String,
     pub originating: Option<Span>,
+    pub kind: MessageKind,
 }
 
 impl<'a> CantUnify<'a> {
@@ -479,6 +873,27 @@ impl<'a> CantUnify<'a> {
             message: String::new(),
             reason: String::new(),
             originating: None,
+            kind: MessageKind::Mismatch,
+        }
+    }
+
+    /// An error about a single type, which is reported as `message; it has
+    /// type ty`
+    pub fn single(ty: &'a Type<'a>) -> CantUnify<'a> {
+        CantUnify {
+            kind: MessageKind::Single,
+            ..CantUnify::new(ty, ty)
+        }
+    }
+
+    /// Report the mismatch between the whole type of the patterns of a
+    /// `case` and that of its scrutinee
+    pub fn scrutinee(self, pats: &'a Type<'a>, scrutinee: &'a Type<'a>) -> Self {
+        CantUnify {
+            ty1: pats,
+            ty2: scrutinee,
+            kind: MessageKind::Scrutinee,
+            ..self
         }
     }
 
@@ -503,27 +918,79 @@ impl<'a> CantUnify<'a> {
         self
     }
 
-    fn convert_err(self, pp: &mut PrettyPrinter<'_>) -> Option<Diagnostic> {
+    /// Both types are printed with the same renaming table, so that a type
+    /// variable has the same name in the expected and the found type
+    ///
+    /// Type variables that occur in either type and were introduced at a
+    /// known span in `origins` are pointed out with an additional note
+    fn convert_err(
+        self,
+        pp: &mut PrettyPrinter<'_>,
+        origins: &HashMap<usize, Span>,
+    ) -> Option<Diagnostic> {
         let mut map = HashMap::new();
 
         let mut buffer = String::new();
         let sp = self.originating?;
-        write!(
-            &mut buffer,
-            "Type unification: {}\n{}: ",
-            self.message, self.reason
-        )
-        .ok()?;
+        let (before, between) = match self.kind {
+            MessageKind::Single => {
+                write!(&mut buffer, "{}; it has type `", self.message).ok()?;
+                self.ty1
+                    .print_rename(pp, &mut map)
+                    .write_fmt(&mut buffer)
+                    .ok()?;
+                buffer.push('`');
+                return Some(Diagnostic::error(sp, buffer));
+            }
+            MessageKind::Scrutinee => (
+                "this pattern expects a value of type `".to_string(),
+                "`, but the scrutinee has type `",
+            ),
+            MessageKind::Mismatch => (
+                format!(
+                    "Type unification: {}\n{}: expected `",
+                    self.message, self.reason
+                ),
+                "`, found `",
+            ),
+        };
+        buffer.push_str(&before);
         self.ty1
             .print_rename(pp, &mut map)
             .write_fmt(&mut buffer)
             .ok()?;
-        write!(&mut buffer, ", ").ok()?;
+        buffer.push_str(between);
         self.ty2
             .print_rename(pp, &mut map)
             .write_fmt(&mut buffer)
             .ok()?;
-        Some(Diagnostic::error(sp, buffer))
+        buffer.push('`');
+        if self.kind == MessageKind::Scrutinee {
+            write!(&mut buffer, "\n{}", self.reason).ok()?;
+        }
+
+        let mut notes = map
+            .into_iter()
+            .filter_map(|(id, name)| Some((*origins.get(&id)?, name)))
+            .filter(|(origin, _)| origin.start != sp.start)
+            .collect::<Vec<_>>();
+        notes.sort_by(|a, b| a.1.cmp(&b.1));
+        let diag = notes
+            .into_iter()
+            .fold(Diagnostic::error(sp, buffer), |diag, (sp, name)| {
+                diag.message(sp, format!("`{}` introduced here", name))
+            });
+        Some(diag)
+    }
+
+    /// Describe the outermost structure of a type
+    fn head(ty: &Type<'_>) -> &'static str {
+        match ty {
+            Type::Var(_) => "a type variable",
+            Type::Con(crate::builtin::tycons::T_ARROW, _) => "a function type",
+            Type::Con(_, _) => "a type constructor",
+            Type::Record(_) | Type::Flex(_) => "a record type",
+        }
     }
 }
 
@@ -539,6 +1006,25 @@ impl<'a> Context<'a> {
                 return;
             }
         }
+        if let Some(class) = var.overload() {
+            match ty {
+                Type::Var(v2) => v2.restrict(class),
+                Type::Con(tc, _) if class.candidates().contains(tc) => {}
+                _ => {
+                    // Errors are printed after `var` has been bound, so show
+                    // an overloaded placeholder in its stead. We still bind
+                    // `var` below, so that the error is only reported once
+                    let mut err =
+                        f().reason(format!("Overloaded type must be {}", class.describe()));
+                    let placeholder = self.arena.types.fresh_overloaded(self.tyvar_rank, class);
+                    match err.ty1 {
+                        Type::Var(v) if v.id == var.id => err.ty1 = placeholder,
+                        _ => err.ty2 = placeholder,
+                    }
+                    self.unification_errors.push(err);
+                }
+            }
+        }
         if ty.occurs_check(var) {
             let err = f().reason("Cyclic type detected");
             self.unification_errors.push(err);
@@ -612,6 +1098,30 @@ impl<'a> Context<'a> {
         flex.unified.set(Some(rigid_ty));
     }
 
+    /// Unify two unresolved flexible records, by linking both of them to a new
+    /// flexible record containing the union of their constraints
+    fn two_flex<F>(&mut self, a: &Flex<'a>, b: &Flex<'a>, f: &F)
+    where
+        F: Fn(CantUnify<'a>) -> CantUnify<'a>,
+    {
+        if std::ptr::eq(a, b) {
+            return;
+        }
+        let mut rows = a.constraints.iter().copied().collect::<Vec<_>>();
+        for field in b.constraints.iter() {
+            match a.constraints.contains(&field.label) {
+                Some(row) => self.unify(row.data, field.data, f),
+                None => rows.push(*field),
+            }
+        }
+        let ty = self
+            .arena
+            .types
+            .alloc(Type::Flex(Flex::new(SortedRecord::new(rows))));
+        a.unified.set(Some(ty));
+        b.unified.set(Some(ty));
+    }
+
     fn unify<F>(&mut self, a: &'a Type<'a>, b: &'a Type<'a>, f: &F)
     where
         F: Fn(CantUnify<'a>) -> CantUnify<'a>,
@@ -645,36 +1155,60 @@ impl<'a> Context<'a> {
                     }
                 }
             }
+            (Type::Flex(flex), b) if flex.ty().is_some() => {
+                self.unify(flex.ty().unwrap(), b, f)
+            }
+            (a, Type::Flex(flex)) if flex.ty().is_some() => {
+                self.unify(a, flex.ty().unwrap(), f)
+            }
             (Type::Record(r1), Type::Record(r2)) => self.unify_records(r1, r2, a, b, f),
-            (Type::Flex(flex), Type::Record(rec)) => match flex.ty() {
-                Some(r1) => self.unify(r1, b, f),
-                None => self.one_flex(rec, flex, b, a, f),
-            },
-            (Type::Record(rec), Type::Flex(flex)) => match flex.ty() {
-                Some(r1) => self.unify(r1, b, f),
-                None => self.one_flex(rec, flex, a, b, f),
-            },
+            // The empty record is `unit`
+            (Type::Con(crate::builtin::tycons::T_UNIT, _), Type::Record(rec))
+            | (Type::Record(rec), Type::Con(crate::builtin::tycons::T_UNIT, _))
+                if rec.is_empty() => {}
+            (Type::Flex(flex), Type::Record(rec)) => self.one_flex(rec, flex, b, a, f),
+            (Type::Record(rec), Type::Flex(flex)) => self.one_flex(rec, flex, a, b, f),
+            (Type::Flex(f1), Type::Flex(f2)) => self.two_flex(f1, f2, f),
             (a, b) => {
-                let err = f(CantUnify::new(a, b)).reason("Can't unify these types");
+                let reason = format!(
+                    "Can't unify {} with {}",
+                    CantUnify::head(a),
+                    CantUnify::head(b)
+                );
+                let err = f(CantUnify::new(a, b)).reason(reason);
                 self.unification_errors.push(err);
             }
         }
     }
 
-    fn unify_list(&mut self, sp: Span, tys: &[&'a Type<'a>]) {
-        if tys.len() == 1 {
-            return;
-        }
-        let fst = &tys[0];
-        for ty in tys {
-            self.unify(ty, fst, &|c| {
-                c.span(sp).message("List has items of differing types")
+    /// Unify the type of each element of a list or vector (`what`) against
+    /// the type of the first element, reporting a mismatch at the span of the
+    /// offending element. The first (expected) type is always printed before
+    /// the found type. Returns the element type
+    fn unify_list(&mut self, what: &str, elems: &[(&'a Type<'a>, Span)]) -> &'a Type<'a> {
+        let fst = match elems.first() {
+            Some((ty, _)) => *ty,
+            None => return self.fresh_tyvar(),
+        };
+        for &(ty, sp) in elems.iter().skip(1) {
+            self.unify(fst, ty, &|c| {
+                c.span(sp).message(format!(
+                    "{} element doesn't have the same type as the first element",
+                    what
+                ))
             });
         }
+        fst
     }
 
     fn generalize(&self, ty: &'a Type<'a>) -> Scheme<'a> {
-        let ftv = ty.ftv_rank(self.tyvar_rank);
+        let mut ftv = ty.ftv_rank(self.tyvar_rank);
+        // Ranks should already rule these out, but a type variable that is
+        // free in the environment must never be quantified
+        if !ftv.is_empty() {
+            let env = self.env_free_tyvars();
+            ftv.retain(|id| !env.contains(id));
+        }
 
         match ftv.len() {
             0 => Scheme::Mono(ty),
@@ -686,7 +1220,21 @@ impl<'a> Context<'a> {
         match scheme {
             Scheme::Mono(ty) => (ty, Vec::new()),
             Scheme::Poly(vars, ty) => {
-                let fresh_vars: Vec<_> = (0..vars.len()).map(|_| self.fresh_tyvar()).collect();
+                let mut classes = HashMap::new();
+                ty.visit(|ty| {
+                    if let Type::Var(tv) = ty {
+                        if let Some(class) = tv.overload() {
+                            classes.insert(tv.id, class);
+                        }
+                    }
+                });
+                let fresh_vars: Vec<_> = vars
+                    .iter()
+                    .map(|id| match classes.get(id) {
+                        Some(class) => self.arena.types.fresh_overloaded(self.tyvar_rank, *class),
+                        None => self.fresh_tyvar(),
+                    })
+                    .collect();
                 let map = vars
                     .iter()
                     .copied()
@@ -714,7 +1262,40 @@ impl<'a> Context<'a> {
         }
     }
 
+    /// Call `f` one level deeper in the nesting of expressions, patterns and
+    /// types. Past `max_depth`, an error is reported at `sp` instead, once
+    /// for all of the children of a node, and `fallback` is returned
+    fn nested<T>(
+        &mut self,
+        sp: Span,
+        message: &str,
+        f: impl FnOnce(&mut Context<'a>) -> T,
+        fallback: impl FnOnce(&mut Context<'a>) -> T,
+    ) -> T {
+        if self.depth >= self.max_depth {
+            if !self.too_deep {
+                self.too_deep = true;
+                self.elab_errors.push(ElabError::new(sp, message));
+            }
+            return fallback(self);
+        }
+        self.depth += 1;
+        let t = f(self);
+        self.depth -= 1;
+        self.too_deep = false;
+        t
+    }
+
     fn elaborate_type(&mut self, ty: &ast::Type, allow_unbound: bool) -> &'a Type<'a> {
+        self.nested(
+            ty.span,
+            "type nesting too deep",
+            |ctx| ctx.elaborate_type_nested(ty, allow_unbound),
+            |ctx| ctx.fresh_tyvar(),
+        )
+    }
+
+    fn elaborate_type_nested(&mut self, ty: &ast::Type, allow_unbound: bool) -> &'a Type<'a> {
         use ast::TypeKind::*;
         match &ty.data {
             Var(s) => match self.lookup_tyvar(s, allow_unbound) {
@@ -727,6 +1308,7 @@ impl<'a> Context<'a> {
                     self.fresh_tyvar()
                 }
             },
+            Wild => self.fresh_tyvar_at(ty.span),
             Con(s, args) => {
                 let args = args
                     .iter()
@@ -755,17 +1337,99 @@ impl<'a> Context<'a> {
                 }
                 con.apply(&self.arena.types, args)
             }
-            Record(rows) => self.arena.types.alloc(Type::Record(SortedRecord::new(
-                rows.iter()
-                    .map(|row| self.elab_row(|f, r| f.elaborate_type(r, allow_unbound), row))
-                    .collect::<Vec<Row<_>>>(),
-            ))),
+            // `{}` is the same type as `unit`
+            Record(rows) if rows.is_empty() => self.arena.types.unit(),
+            Record(rows) => {
+                let mut labels = HashSet::new();
+                for row in rows {
+                    if !labels.insert(row.label) {
+                        self.elab_errors.push(
+                            ElabError::new(row.span, "duplicate label in record type")
+                                .kind(ErrorKind::Duplicate(row.label)),
+                        );
+                    }
+                }
+                self.arena.types.alloc(Type::Record(SortedRecord::new(
+                    rows.iter()
+                        .map(|row| self.elab_row(|f, r| f.elaborate_type(r, allow_unbound), row))
+                        .collect::<Vec<Row<_>>>(),
+                )))
+            }
         }
     }
 }
 
 impl<'a> Context<'a> {
     fn elab_if(&mut self, sp: Span, e1: Expr<'a>, e2: Expr<'a>, e3: Expr<'a>) -> Expr<'a> {
+        self.unify(self.arena.types.bool(), e1.ty, &|c| {
+            c.span(e1.span)
+                .message("conditional doesn't have type `bool`")
+        });
+        self.unify(e2.ty, e3.ty, &|c| {
+            c.span(e2.span)
+                .add_spans(e2.span, e3.span)
+                .message("branches of `if` expression don't have the same types")
+        });
+        self.build_if(sp, e1, e2, e3)
+    }
+
+    /// Elaborate a chain of `andalso` and `orelse` operators, which the parser
+    /// nests to the right. Each operand is elaborated and checked against
+    /// `bool` exactly once, and the desugared `if`s are then built from the
+    /// innermost outwards in a loop, so that a long chain doesn't recurse
+    /// through [`Context::elaborate_expr`] once per operator
+    fn elab_bool_chain(&mut self, expr: &ast::Expr) -> Expr<'a> {
+        fn message(andalso: bool) -> &'static str {
+            match andalso {
+                true => "operand of `andalso` doesn't have type `bool`",
+                false => "operand of `orelse` doesn't have type `bool`",
+            }
+        }
+
+        let bool = self.arena.types.bool();
+        let mut links = Vec::new();
+        let mut rest = expr;
+        loop {
+            let (e1, e2, andalso) = match &rest.data {
+                ast::ExprKind::Andalso(e1, e2) => (e1, e2, true),
+                ast::ExprKind::Orelse(e1, e2) => (e1, e2, false),
+                _ => break,
+            };
+            let e1 = self.elaborate_expr(e1);
+            self.unify(bool, e1.ty, &|c| c.span(e1.span).message(message(andalso)));
+            links.push((rest.span, e1, andalso));
+            rest = e2;
+        }
+
+        // The last operand is the right operand of the innermost operator
+        let last = self.elaborate_expr(rest);
+        let andalso = matches!(links.last(), Some((_, _, true)));
+        self.unify(bool, last.ty, &|c| {
+            c.span(last.span).message(message(andalso))
+        });
+
+        let mut acc = last;
+        while let Some((sp, e1, andalso)) = links.pop() {
+            let con = match andalso {
+                true => constructors::C_FALSE,
+                false => constructors::C_TRUE,
+            };
+            let lit = Expr::new(
+                self.arena.exprs.alloc(ExprKind::Con(con, Vec::default())),
+                bool,
+                Span::dummy(),
+            );
+            acc = match andalso {
+                true => self.build_if(sp, e1, acc, lit),
+                false => self.build_if(sp, e1, lit, acc),
+            };
+        }
+        acc
+    }
+
+    /// Build the core expression for `if e1 then e2 else e3`, without checking
+    /// the types of its operands
+    fn build_if(&mut self, sp: Span, e1: Expr<'a>, e2: Expr<'a>, e3: Expr<'a>) -> Expr<'a> {
         let tru = Rule {
             pat: Pat::new(
                 self.arena
@@ -775,6 +1439,7 @@ impl<'a> Context<'a> {
                 e2.span,
             ),
             expr: e2,
+            span: e2.span,
         };
         let fls = Rule {
             pat: Pat::new(
@@ -785,22 +1450,20 @@ impl<'a> Context<'a> {
                 e3.span,
             ),
             expr: e3,
+            span: e3.span,
         };
 
-        self.unify(e1.ty, self.arena.types.bool(), &|c| {
-            c.span(e1.span)
-                .message("conditional doesn't have type `bool`")
-        });
-        self.unify(e2.ty, e3.ty, &|c| {
-            c.span(e2.span)
-                .add_spans(e2.span, e3.span)
-                .message("branches of `if` expression don't have the same types")
-        });
-
         let var = self.fresh_var();
         let pat = self.arena.pat_var(var, self.arena.types.bool());
 
-        let decl = Decl::Val(Vec::new(), Rule { pat, expr: e1 });
+        let decl = Decl::Val(
+            Vec::new(),
+            Rule {
+                pat,
+                expr: e1,
+                span: e1.span,
+            },
+        );
         let body = Expr::new(
             self.arena
                 .exprs
@@ -818,64 +1481,101 @@ impl<'a> Context<'a> {
     fn elab_rule(&mut self, rule: &ast::Rule, bind: bool) -> Rule<'a> {
         let (pat, _) = self.elaborate_pat(&rule.pat, bind);
         let expr = self.elaborate_expr(&rule.expr);
-        Rule { pat, expr }
+        Rule {
+            pat,
+            expr,
+            span: rule.span,
+        }
     }
 
-    fn elab_rules(&mut self, sp: Span, rules: &[ast::Rule]) -> (Vec<Rule<'a>>, &'a Type<'a>) {
+    fn elab_rules(&mut self, rules: &[ast::Rule]) -> (Vec<Rule<'a>>, &'a Type<'a>) {
         self.with_scope(|ctx| {
             let rules = rules
                 .iter()
                 .map(|r| ctx.elab_rule(r, true))
                 .collect::<Vec<Rule>>();
 
-            let mut rtys = rules
+            let rtys = rules
                 .iter()
                 .map(|r| ctx.arena.types.arrow(r.pat.ty, r.expr.ty))
                 .collect::<Vec<_>>();
 
-            ctx.unify_list(sp, &rtys);
-            let fst = rtys.remove(0);
+            // Unify each arm against the first one, so that a mismatch is
+            // reported at the arm which actually diverges
+            let fst = rtys[0];
+            for (rule, ty) in rules.iter().zip(&rtys).skip(1) {
+                let arm = rule.pat.span.merge(rule.expr.span);
+                ctx.unify(fst, ty, &|c| {
+                    c.span(arm)
+                        .message("match arms have different types")
+                });
+            }
             (rules, fst)
         })
     }
 
     fn elaborate_expr(&mut self, expr: &ast::Expr) -> Expr<'a> {
-        match &expr.data {
-            ast::ExprKind::Andalso(e1, e2) => {
-                let e1 = self.elaborate_expr(e1);
-                let e2 = self.elaborate_expr(e2);
-                let fls = Expr::new(
-                    self.arena
-                        .exprs
-                        .alloc(ExprKind::Con(constructors::C_FALSE, Vec::default())),
-                    self.arena.types.bool(),
+        self.nested(
+            expr.span,
+            "expression nesting too deep",
+            |ctx| ctx.elaborate_expr_nested(expr),
+            |ctx| {
+                Expr::new(
+                    ctx.arena.exprs.fresh_var(),
+                    ctx.arena.types.fresh_var(ctx.tyvar_rank),
                     expr.span,
-                );
-                self.elab_if(expr.span, e1, e2, fls)
+                )
+            },
+        )
+    }
+
+    fn elaborate_expr_nested(&mut self, expr: &ast::Expr) -> Expr<'a> {
+        match &expr.data {
+            ast::ExprKind::Andalso(_, _) | ast::ExprKind::Orelse(_, _) => {
+                self.elab_bool_chain(expr)
             }
             ast::ExprKind::App(e1, e2) => {
+                let rec = self.recursive_call(e1);
                 let e1 = self.elaborate_expr(e1);
                 let e2 = self.elaborate_expr(e2);
 
-                let f = self.fresh_tyvar();
-                self.unify(e1.ty, self.arena.types.arrow(e2.ty, f), &|c| {
-                    c.span(expr.span)
-                        .add_spans(e1.span, e2.span)
-                        .message("can't unify function with argument types")
-                });
+                // Recursive uses of a function are monomorphic, so a mismatch
+                // involving one of the type variables that the function would
+                // be generalized over means it's used at a different type
+                let errors = self.unification_errors.len();
+
+                let f = self.fresh_tyvar_at(expr.span);
+                match e1.ty.resolve() {
+                    Type::Con(tc, _) if *tc != crate::builtin::tycons::T_ARROW => {
+                        self.not_a_function(e1)
+                    }
+                    Type::Record(_) | Type::Flex(_) => self.not_a_function(e1),
+                    _ => self.unify(e1.ty, self.arena.types.arrow(e2.ty, f), &|c| {
+                        c.span(expr.span)
+                            .add_spans(e1.span, e2.span)
+                            .message("can't unify function with argument types")
+                    }),
+                }
+                if let Some((ty, own)) = rec {
+                    for err in &mut self.unification_errors[errors..] {
+                        if Self::involves_own_tyvars(ty, &own, err.ty1, err.ty2) {
+                            err.message = "polymorphic recursion is not supported".into();
+                        }
+                    }
+                }
                 Expr::new(self.arena.exprs.alloc(ExprKind::App(e1, e2)), f, expr.span)
             }
             ast::ExprKind::Case(scrutinee, rules) => {
                 let casee = self.elaborate_expr(scrutinee);
 
-                let (rules, ty) = self.elab_rules(expr.span, rules);
+                let (rules, ty) = self.elab_rules(rules);
 
                 let (arg, res) = match ty.de_arrow() {
                     Some((a, r)) => (a, r),
                     None => {
                         let (dom, rng) = (self.fresh_tyvar(), self.fresh_tyvar());
                         let arr = self.arena.types.arrow(dom, rng);
-                        self.unify(ty, arr, &|c| {
+                        self.unify(arr, ty, &|c| {
                             c.span(expr.span)
                                 .message("something wrong with match rules")
                         });
@@ -883,9 +1583,8 @@ impl<'a> Context<'a> {
                     }
                 };
 
-                self.unify(casee.ty, arg, &|c| {
-                    c.span(scrutinee.span)
-                        .message("`case` expression and patterns don't have the same type")
+                self.unify(arg, casee.ty, &|c| {
+                    c.span(scrutinee.span).scrutinee(arg, casee.ty)
                 });
                 crate::match_compile::case(self, casee, res, rules, scrutinee.span)
             }
@@ -896,7 +1595,7 @@ impl<'a> Context<'a> {
             ast::ExprKind::Constraint(ex, ty) => {
                 let ex = self.elaborate_expr(ex);
                 let ty_ = self.elaborate_type(ty, false);
-                self.unify(ex.ty, ty_, &|c| {
+                self.unify(ty_, ex.ty, &|c| {
                     c.span(expr.span)
                         .add_spans(ex.span, ty.span)
                         .message("expression type and constraint don't match")
@@ -908,47 +1607,48 @@ impl<'a> Context<'a> {
                     Ok(p) => p,
                     Err(err) => {
                         match err {
-                            precedence::Error::EndsInfix => self.elab_errors.push(ElabError::new(
-                                expr.span,
-                                "application expr ends with an infix operator",
-                            )),
-                            precedence::Error::InfixInPrefix => {
+                            precedence::Error::EndsInfix(sp) => self.elab_errors.push(
+                                ElabError::new(sp, "application expr ends with an infix operator"),
+                            ),
+                            precedence::Error::InfixInPrefix(sp) => {
                                 self.elab_errors.push(ElabError::new(
-                                    expr.span,
+                                    sp,
                                     "application expr starts with an infix operator",
                                 ))
                             }
-                            precedence::Error::SamePrecedence => {
+                            precedence::Error::SamePrecedence(sp) => {
                                 self.elab_errors.push(ElabError::new(
-                                    expr.span,
+                                    sp,
                                     "application expr mixes operators of equal precedence",
                                 ))
                             }
-                            precedence::Error::InvalidOperator => {
+                            precedence::Error::InvalidOperator(sp) => {
                                 self.elab_errors.push(ElabError::new(
-                                    expr.span,
+                                    sp,
                                     "application expr doesn't contain infix operator",
                                 ))
                             }
                         }
-                        // Return a dummy variable so that we can continue elaboration
-                        ast::Expr::new(
-                            ast::ExprKind::Var(self.arena.exprs.allocate_id()),
-                            Span::dummy(),
-                        )
+                        // Return a dummy variable so that we can continue
+                        // elaboration, without reporting it as unbound
+                        return Expr::new(
+                            self.arena.exprs.fresh_var(),
+                            self.arena.types.fresh_var(self.tyvar_rank),
+                            expr.span,
+                        );
                     }
                 };
                 self.elaborate_expr(&p)
             }
             ast::ExprKind::Fn(rules) => {
-                let (rules, ty) = self.elab_rules(expr.span, rules);
+                let (rules, ty) = self.elab_rules(rules);
 
                 let (arg, res) = match ty.de_arrow() {
                     Some((a, r)) => (a, r),
                     None => {
                         let (dom, rng) = (self.fresh_tyvar(), self.fresh_tyvar());
                         let arr = self.arena.types.arrow(dom, rng);
-                        self.unify(ty, arr, &|c| {
+                        self.unify(arr, ty, &|c| {
                             c.span(expr.span)
                                 .message("something wrong with match rules")
                         });
@@ -972,14 +1672,14 @@ impl<'a> Context<'a> {
             }
             ast::ExprKind::Handle(tryy, rules) => {
                 let tryy = self.elaborate_expr(tryy);
-                let (rules, ty) = self.elab_rules(expr.span, rules);
+                let (rules, ty) = self.elab_rules(rules);
 
                 let (arg, res) = match ty.de_arrow() {
                     Some((a, r)) => (a, r),
                     None => {
                         let (dom, rng) = (self.fresh_tyvar(), self.fresh_tyvar());
                         let arr = self.arena.types.arrow(dom, rng);
-                        self.unify(ty, arr, &|c| {
+                        self.unify(arr, ty, &|c| {
                             c.span(expr.span)
                                 .message("something wrong with match rules")
                         });
@@ -991,14 +1691,14 @@ impl<'a> Context<'a> {
                     c.span(tryy.span)
                         .message("`try` expression and handler result types don't match")
                 });
-                self.unify(arg, self.arena.types.exn(), &|c| {
+                self.unify(self.arena.types.exn(), arg, &|c| {
                     c.span(expr.span)
                         .message("handler match rules don't have `exn` type")
                 });
                 // tryy handle case $gensym of |...
                 let gensym = self.fresh_var();
                 let scrutinee = self.arena.expr_var(gensym, arg);
-                let body = crate::match_compile::case(self, scrutinee, res, rules, expr.span);
+                let body = crate::match_compile::handler(self, scrutinee, res, rules, expr.span);
                 Expr::new(
                     self.arena.exprs.alloc(ExprKind::Handle(tryy, gensym, body)),
                     res,
@@ -1032,24 +1732,22 @@ impl<'a> Context<'a> {
                     .map(|ex| self.elaborate_expr(ex))
                     .collect::<Vec<_>>();
 
-                let tys = exprs.iter().map(|ex| ex.ty).collect::<Vec<_>>();
-                self.unify_list(expr.span, &tys);
+                let tys = exprs.iter().map(|ex| (ex.ty, ex.span)).collect::<Vec<_>>();
                 // Pick the first type, since that was what everything was unified against
-                let ty = self.arena.types.list(tys[0]);
+                let ty = self.unify_list("list", &tys);
+                let ty = self.arena.types.list(ty);
                 Expr::new(self.arena.exprs.alloc(ExprKind::List(exprs)), ty, expr.span)
             }
-            ast::ExprKind::Orelse(e1, e2) => {
-                let e1 = self.elaborate_expr(e1);
-                let e2 = self.elaborate_expr(e2);
+            ast::ExprKind::Vector(exprs) => {
+                let exprs = exprs
+                    .iter()
+                    .map(|ex| self.elaborate_expr(ex))
+                    .collect::<Vec<_>>();
 
-                let tru = Expr::new(
-                    self.arena
-                        .exprs
-                        .alloc(ExprKind::Con(constructors::C_TRUE, Vec::default())),
-                    self.arena.types.bool(),
-                    expr.span,
-                );
-                self.elab_if(expr.span, e1, tru, e2)
+                let tys = exprs.iter().map(|ex| (ex.ty, ex.span)).collect::<Vec<_>>();
+                let ty = self.unify_list("vector", &tys);
+                let ty = self.arena.types.vector(ty);
+                Expr::new(self.arena.exprs.alloc(ExprKind::Vector(exprs)), ty, expr.span)
             }
             ast::ExprKind::Primitive(prim) => {
                 let name = prim.sym;
@@ -1061,12 +1759,14 @@ impl<'a> Context<'a> {
                     expr.span,
                 )
             }
-            ast::ExprKind::Raise(expr) => {
+            ast::ExprKind::Raise(arg) => {
+                // `raise e` can appear in any position, so its result type
+                // is a fresh type variable that unifies with anything
                 let ty = self.fresh_tyvar();
-                let ex = self.elaborate_expr(expr);
-                self.unify(ex.ty, self.arena.types.exn(), &|c| {
-                    c.span(expr.span)
-                        .message("expression in `raise` is not an exception")
+                let ex = self.elaborate_expr(arg);
+                self.unify(self.arena.types.exn(), ex.ty, &|c| {
+                    c.span(arg.span)
+                        .message("argument to `raise` must have type `exn`")
                 });
                 Expr::new(self.arena.exprs.alloc(ExprKind::Raise(ex)), ty, expr.span)
             }
@@ -1104,27 +1804,45 @@ impl<'a> Context<'a> {
                     expr.span,
                 ))
             }
-            ast::ExprKind::Seq(exprs) => {
-                let exprs = exprs
-                    .iter()
-                    .map(|ex| self.elaborate_expr(ex))
-                    .collect::<Vec<_>>();
-                // exprs.len() >= 2, but we'll use saturating_sub just to be safe
-                for ex in &exprs[..exprs.len().saturating_sub(2)] {
-                    self.unify(ex.ty, self.arena.types.unit(), &|c| {
-                        c.span(expr.span)
-                            .message("expressions in a sequence must have type `unit`")
-                    });
-                }
-                let ty = exprs.last().unwrap().ty;
-                Expr::new(self.arena.exprs.alloc(ExprKind::Seq(exprs)), ty, expr.span)
-            }
-            ast::ExprKind::Var(sym) => match self.lookup_value(sym) {
+            ast::ExprKind::Seq(exprs) => match exprs.as_slice() {
+                // The parser only produces sequences of two or more
+                // expressions, but be defensive about hand-built ASTs
+                [] => Expr::new(
+                    self.arena.exprs.alloc(ExprKind::Const(Const::Unit)),
+                    self.arena.types.unit(),
+                    expr.span,
+                ),
+                [ex] => self.elaborate_expr(ex),
+                _ => {
+                    let exprs = exprs
+                        .iter()
+                        .map(|ex| self.elaborate_expr(ex))
+                        .collect::<Vec<_>>();
+                    // Every expression but the last must have type unit
+                    for ex in &exprs[..exprs.len() - 1] {
+                        self.unify(self.arena.types.unit(), ex.ty, &|c| {
+                            c.span(ex.span)
+                                .message("expressions in a sequence must have type `unit`")
+                        });
+                    }
+                    let ty = exprs[exprs.len() - 1].ty;
+                    Expr::new(self.arena.exprs.alloc(ExprKind::Seq(exprs)), ty, expr.span)
+                }
+            },
+            ast::ExprKind::Var(sym) | ast::ExprKind::Op(sym) => match self.lookup_value(sym) {
                 Some((scheme, con)) => {
                     let (ty, args) = self.instantiate(scheme);
+                    let con = *con;
+                    for arg in &args {
+                        if let Type::Var(tv) = arg {
+                            if tv.overload().is_some() {
+                                self.overloads.push((arg, expr.span, *sym));
+                            }
+                        }
+                    }
                     match con {
                         IdStatus::Con(c) | IdStatus::Exn(c) => Expr::new(
-                            self.arena.exprs.alloc(ExprKind::Con(*c, args)),
+                            self.arena.exprs.alloc(ExprKind::Con(c, args)),
                             ty,
                             expr.span,
                         ),
@@ -1150,11 +1868,7 @@ impl<'a> Context<'a> {
 }
 
 impl<'a> Context<'a> {
-    fn elaborate_pat(
-        &mut self,
-        pat: &ast::Pat,
-        bind: bool,
-    ) -> (Pat<'a>, Vec<(Symbol, &'a Type<'a>)>) {
+    fn elaborate_pat(&mut self, pat: &ast::Pat, bind: bool) -> (Pat<'a>, Vec<Binding<'a>>) {
         let mut bindings = Vec::new();
         (self.elaborate_pat_inner(pat, bind, &mut bindings), bindings)
     }
@@ -1179,11 +1893,41 @@ impl<'a> Context<'a> {
         })
     }
 
+    /// The name to bind a variable `sym` of a pattern to. A pattern may bind
+    /// each variable only once, so a repeated variable is reported, along
+    /// with its first binding, and renamed to a fresh variable
+    fn pattern_var(&mut self, sym: Symbol, sp: Span, bindings: &[Binding<'a>]) -> Symbol {
+        match bindings.iter().find(|(s, _, _)| *s == sym) {
+            Some((_, _, first)) => {
+                self.elab_errors.push(
+                    ElabError::new(sp, "duplicate variable in pattern")
+                        .kind(ErrorKind::DuplicateVar(sym, *first)),
+                );
+                self.fresh_var()
+            }
+            None => sym,
+        }
+    }
+
     fn elaborate_pat_inner(
         &mut self,
         pat: &ast::Pat,
         bind: bool,
-        bindings: &mut Vec<(Symbol, &'a Type<'a>)>,
+        bindings: &mut Vec<Binding<'a>>,
+    ) -> Pat<'a> {
+        self.nested(
+            pat.span,
+            "pattern nesting too deep",
+            |ctx| ctx.elaborate_pat_nested(pat, bind, bindings),
+            |ctx| Pat::new(ctx.arena.pats.wild(), ctx.fresh_tyvar(), pat.span),
+        )
+    }
+
+    fn elaborate_pat_nested(
+        &mut self,
+        pat: &ast::Pat,
+        bind: bool,
+        bindings: &mut Vec<Binding<'a>>,
     ) -> Pat<'a> {
         use ast::PatKind::*;
         match &pat.data {
@@ -1199,7 +1943,7 @@ impl<'a> Context<'a> {
                             None => {
                                 let (dom, rng) = (self.fresh_tyvar(), self.fresh_tyvar());
                                 let arr = self.arena.types.arrow(dom, rng);
-                                self.unify(inst, arr, &|c| {
+                                self.unify(arr, inst, &|c| {
                                     c.span(pat.span).message(
                                         "can't unify pattern application and argument types",
                                     )
@@ -1233,7 +1977,7 @@ impl<'a> Context<'a> {
             Ascribe(p, ty) => {
                 let p = self.elaborate_pat_inner(p, bind, bindings);
                 let ty_ = self.elaborate_type(ty, true);
-                self.unify(p.ty, ty_, &|c| {
+                self.unify(ty_, p.ty, &|c| {
                     c.span(pat.span)
                         .add_spans(p.span, ty.span)
                         .message("pattern type and constraint type don't match")
@@ -1244,30 +1988,57 @@ impl<'a> Context<'a> {
                 let ty = self.const_ty(c);
                 Pat::new(self.arena.pats.alloc(PatKind::Const(*c)), ty, pat.span)
             }
+            Layer(sym, p) => {
+                if let Some((_, IdStatus::Con(_))) | Some((_, IdStatus::Exn(_))) =
+                    self.lookup_value(sym)
+                {
+                    self.elab_errors.push(
+                        ElabError::new(pat.span, "constructor can't be bound by a layered pattern")
+                            .kind(ErrorKind::Constructor(*sym)),
+                    );
+                }
+                // The variable comes before the inner pattern in the source, so
+                // it is bound first, and its type is filled in afterwards
+                let name = self.pattern_var(*sym, pat.span, bindings);
+                let idx = bindings.len();
+                bindings.push((name, self.arena.types.unit(), pat.span));
+                let p = self.elaborate_pat_inner(p, bind, bindings);
+                bindings[idx].1 = p.ty;
+                if bind {
+                    self.define_value(name, pat.span, Scheme::Mono(p.ty), IdStatus::Var);
+                }
+                Pat::new(
+                    self.arena.pats.alloc(PatKind::Layer(name, p)),
+                    p.ty,
+                    pat.span,
+                )
+            }
             FlatApp(pats) => {
                 let p = match self.pat_precedence(pats.clone()) {
                     Ok(p) => p,
                     Err(err) => {
                         match err {
-                            precedence::Error::EndsInfix => self.elab_errors.push(ElabError::new(
-                                pat.span,
-                                "application pattern ends with an infix operator",
-                            )),
-                            precedence::Error::InfixInPrefix => {
+                            precedence::Error::EndsInfix(sp) => {
+                                self.elab_errors.push(ElabError::new(
+                                    sp,
+                                    "application pattern ends with an infix operator",
+                                ))
+                            }
+                            precedence::Error::InfixInPrefix(sp) => {
                                 self.elab_errors.push(ElabError::new(
-                                    pat.span,
+                                    sp,
                                     "application pattern starts with an infix operator",
                                 ))
                             }
-                            precedence::Error::SamePrecedence => {
+                            precedence::Error::SamePrecedence(sp) => {
                                 self.elab_errors.push(ElabError::new(
-                                    pat.span,
+                                    sp,
                                     "application pattern mixes operators of equal precedence",
                                 ))
                             }
-                            precedence::Error::InvalidOperator => {
+                            precedence::Error::InvalidOperator(sp) => {
                                 self.elab_errors.push(ElabError::new(
-                                    pat.span,
+                                    sp,
                                     "application pattern doesn't contain infix operator",
                                 ))
                             }
@@ -1285,9 +2056,23 @@ impl<'a> Context<'a> {
                     .map(|p| self.elaborate_pat_inner(p, bind, bindings))
                     .collect::<Vec<Pat>>();
 
-                let tys = pats.iter().map(|p| p.ty).collect::<Vec<_>>();
-                self.unify_list(pat.span, &tys);
-                self.delist(pats, self.arena.types.list(tys[0]), pat.span)
+                let tys = pats.iter().map(|p| (p.ty, p.span)).collect::<Vec<_>>();
+                let ty = self.unify_list("list", &tys);
+                self.delist(pats, self.arena.types.list(ty), pat.span)
+            }
+            Vector(pats) => {
+                let pats: Vec<Pat> = pats
+                    .iter()
+                    .map(|p| self.elaborate_pat_inner(p, bind, bindings))
+                    .collect::<Vec<Pat>>();
+
+                let tys = pats.iter().map(|p| (p.ty, p.span)).collect::<Vec<_>>();
+                let ty = self.unify_list("vector", &tys);
+                Pat::new(
+                    self.arena.pats.alloc(PatKind::Vector(pats)),
+                    self.arena.types.vector(ty),
+                    pat.span,
+                )
             }
             Record(rows, flex) => {
                 let pats: Vec<Row<Pat>> = rows
@@ -1321,50 +2106,76 @@ impl<'a> Context<'a> {
             }
             Variable(sym) => match self.lookup_value(sym) {
                 // Rule 35
+                Some((scheme, IdStatus::Exn(c))) | Some((scheme, IdStatus::Con(c)))
+                    if c.arity != 0 =>
+                {
+                    // Recover as if the argument were a wildcard
+                    let c = *c;
+                    let (ty, _) = self.instantiate(scheme);
+                    self.elab_errors.push(
+                        ElabError::new(pat.span, "constructor requires an argument in a pattern")
+                            .kind(ErrorKind::Constructor(*sym)),
+                    );
+                    match ty.de_arrow() {
+                        Some((arg, res)) => {
+                            let wild = Pat::new(self.arena.pats.wild(), arg, pat.span);
+                            Pat::new(
+                                self.arena.pats.alloc(PatKind::App(c, Some(wild))),
+                                res,
+                                pat.span,
+                            )
+                        }
+                        None => Pat::new(self.arena.pats.wild(), self.fresh_tyvar(), pat.span),
+                    }
+                }
                 Some((scheme, IdStatus::Exn(c))) | Some((scheme, IdStatus::Con(c))) => {
                     let (ty, args) = self.instantiate(scheme);
                     Pat::new(self.arena.pats.alloc(PatKind::App(*c, None)), ty, pat.span)
                 }
                 _ => {
                     // Rule 34
-                    let mut name = *sym;
-                    if bindings.iter().map(|(s, _)| s).any(|s| s == sym) {
-                        name = self.fresh_var();
-                        self.elab_errors.push(ElabError::new(
-                            pat.span,
-                            "duplicate variable in pattern, emitting bogus value",
-                        ));
-                    }
-                    let ty = self.fresh_tyvar();
+                    let name = self.pattern_var(*sym, pat.span, bindings);
+                    let ty = self.fresh_tyvar_at(pat.span);
                     if bind {
                         self.define_value(name, pat.span, Scheme::Mono(ty), IdStatus::Var);
                     }
 
-                    bindings.push((name, ty));
+                    bindings.push((name, ty, pat.span));
                     Pat::new(self.arena.pats.alloc(PatKind::Var(name)), ty, pat.span)
                 }
             },
-            Wild => Pat::new(self.arena.pats.wild(), self.fresh_tyvar(), pat.span),
+            Wild => {
+                let ty = self.fresh_tyvar_at(pat.span);
+                Pat::new(self.arena.pats.wild(), ty, pat.span)
+            }
         }
     }
 }
 
 impl<'a> Context<'a> {
+    /// Associativity is encoded by giving an operator slightly different left
+    /// and right binding powers. Precedence levels are doubled first, so that
+    /// the right binding power of one level never equals the left binding
+    /// power of the next, which the resolver would report as two operators of
+    /// the same precedence
     fn elab_decl_fixity(&mut self, fixity: &ast::Fixity, bp: u8, sym: Symbol) {
         let fix = match fixity {
-            ast::Fixity::Infix => Fixity::Infix(bp, bp + 1),
-            ast::Fixity::Infixr => Fixity::Infix(bp + 1, bp),
+            ast::Fixity::Infix => Fixity::Infix(2 * bp, 2 * bp + 1),
+            ast::Fixity::Infixr => Fixity::Infix(2 * bp + 1, 2 * bp),
             ast::Fixity::Nonfix => Fixity::Nonfix,
         };
         self.current_ns_mut().infix.insert(sym, fix);
+        self.hide_locals(|ns| {
+            ns.infix.remove(&sym);
+        });
     }
 
     fn elab_decl_local(&mut self, decls: &ast::Decl, body: &ast::Decl, elab: &mut Vec<Decl<'a>>) {
         self.with_scope(|ctx| {
             ctx.elaborate_decl_inner(decls, elab);
-            ctx.local_depth += 1;
+            ctx.locals.push(ctx.current);
             ctx.elaborate_decl_inner(body, elab);
-            ctx.local_depth -= 1;
+            ctx.locals.pop();
         })
     }
 
@@ -1374,33 +2185,160 @@ impl<'a> Context<'a> {
         }
     }
 
+    fn elab_typebind(&mut self, typebind: &ast::Typebind) -> Scheme<'a> {
+        if !typebind.tyvars.is_empty() {
+            self.with_tyvars(|ctx| {
+                for s in typebind.tyvars.iter() {
+                    let v = ctx.arena.types.fresh_type_var(ctx.tyvar_rank);
+                    ctx.tyvars.push((*s, v));
+                }
+                let ty = ctx.elaborate_type(&typebind.ty, false);
+                let s = match typebind.tyvars.len() {
+                    0 => Scheme::Mono(ty),
+                    _ => Scheme::Poly(
+                        typebind
+                            .tyvars
+                            .iter()
+                            .map(|tv| ctx.lookup_tyvar(tv, false).unwrap().id)
+                            .collect(),
+                        ty,
+                    ),
+                };
+
+                s
+            })
+        } else {
+            Scheme::Mono(self.elaborate_type(&typebind.ty, false))
+        }
+    }
+
     fn elab_decl_type(&mut self, tbs: &[ast::Typebind], _elab: &mut Vec<Decl<'a>>) {
-        for typebind in tbs {
-            let scheme = if !typebind.tyvars.is_empty() {
-                self.with_tyvars(|ctx| {
-                    for s in typebind.tyvars.iter() {
-                        let v = ctx.arena.types.fresh_type_var(ctx.tyvar_rank);
-                        ctx.tyvars.push((*s, v));
-                    }
-                    let ty = ctx.elaborate_type(&typebind.ty, false);
-                    let s = match typebind.tyvars.len() {
-                        0 => Scheme::Mono(ty),
-                        _ => Scheme::Poly(
-                            typebind
-                                .tyvars
-                                .iter()
-                                .map(|tv| ctx.lookup_tyvar(tv, false).unwrap().id)
-                                .collect(),
-                            ty,
-                        ),
-                    };
+        // Like `val`, the bindings of a `type` declaration are simultaneous
+        let schemes = tbs
+            .iter()
+            .map(|typebind| self.elab_typebind(typebind))
+            .collect::<Vec<_>>();
+        for (typebind, scheme) in tbs.iter().zip(schemes) {
+            self.define_type(typebind.tycon, TypeStructure::Scheme(scheme));
+        }
+    }
 
-                    s
-                })
-            } else {
-                Scheme::Mono(self.elaborate_type(&typebind.ty, false))
+    /// Refine an abstract type specification of the signature currently being
+    /// elaborated to a concrete type
+    fn elab_where_type(&mut self, typebind: &ast::Typebind) {
+        let id = match self.current_ns().types.get(&typebind.tycon) {
+            Some(id) => *id,
+            None => {
+                return self.elab_errors.push(
+                    ElabError::new(typebind.ty.span, "type")
+                        .kind(ErrorKind::Unbound(typebind.tycon)),
+                )
+            }
+        };
+        match &self.types[id.0 as usize] {
+            TypeStructure::Tycon(con) if con.arity != typebind.tyvars.len() => {
+                let arity = ErrorKind::Arity(con.arity, typebind.tyvars.len());
+                self.elab_errors
+                    .push(ElabError::new(typebind.ty.span, "`where type`").kind(arity));
+            }
+            TypeStructure::Tycon(_) => {
+                let scheme = self.elab_typebind(typebind);
+                self.types[id.0 as usize] = TypeStructure::Scheme(scheme);
+            }
+            _ => self.elab_errors.push(
+                ElabError::new(
+                    typebind.ty.span,
+                    "`where type` refines a type that isn't abstract",
+                )
+                .kind(ErrorKind::NotAbstract(typebind.tycon)),
+            ),
+        }
+    }
+
+    /// Identify the abstract type specifications `tycons` of the signature
+    /// currently being elaborated, by rebinding each of them to the first
+    fn elab_sharing_type(&mut self, sp: Span, tycons: &[Symbol]) {
+        let mut shared: Option<(TypeId, usize)> = None;
+        for tycon in tycons {
+            let id = match self.current_ns().types.get(tycon) {
+                Some(id) => *id,
+                None => {
+                    self.elab_errors
+                        .push(ElabError::new(sp, "type").kind(ErrorKind::Unbound(*tycon)));
+                    continue;
+                }
             };
-            self.define_type(typebind.tycon, TypeStructure::Scheme(scheme));
+            let arity = match &self.types[id.0 as usize] {
+                TypeStructure::Tycon(con) => con.arity,
+                _ => {
+                    self.elab_errors.push(
+                        ElabError::new(sp, "`sharing type` applies to a type that isn't abstract")
+                            .kind(ErrorKind::NotAbstract(*tycon)),
+                    );
+                    continue;
+                }
+            };
+            match shared {
+                None => shared = Some((id, arity)),
+                Some((_, expected)) if expected != arity => self.elab_errors.push(
+                    ElabError::new(sp, "`sharing type`").kind(ErrorKind::Arity(expected, arity)),
+                ),
+                Some((first, _)) => {
+                    self.current_ns_mut().types.insert(*tycon, first);
+                }
+            }
+        }
+    }
+
+    fn elab_decl_signature(&mut self, name: Symbol, specs: &[ast::Spec], wheres: &[ast::Typebind]) {
+        let ns = self.with_scope(|ctx| {
+            for spec in specs {
+                match &spec.data {
+                    ast::SpecKind::Type(tyvars, tycon) => {
+                        let con = Tycon::new(*tycon, tyvars.len(), ctx.scope_depth());
+                        ctx.define_type(*tycon, TypeStructure::Tycon(con));
+                    }
+                    ast::SpecKind::Manifest(typebind) => {
+                        let scheme = ctx.elab_typebind(typebind);
+                        ctx.define_type(typebind.tycon, TypeStructure::Scheme(scheme));
+                    }
+                    ast::SpecKind::Sharing(tycons) => ctx.elab_sharing_type(spec.span, tycons),
+                }
+            }
+            for typebind in wheres {
+                ctx.elab_where_type(typebind);
+            }
+            ctx.current
+        });
+        self.signatures.insert(name, ns);
+    }
+
+    /// Report any type variables in `ty` that aren't parameters of `db`,
+    /// returning true if there are none
+    fn datatype_tyvars_bound(&mut self, db: &ast::Datatype, ty: &ast::Type) -> bool {
+        use ast::TypeKind::*;
+        match &ty.data {
+            Var(s) if !db.tyvars.contains(s) => {
+                self.elab_errors.push(
+                    ElabError::new(ty.span, "datatype").kind(ErrorKind::UnboundTyvar(*s, db.tycon)),
+                );
+                false
+            }
+            Var(_) | Wild => true,
+            Con(_, args) => {
+                let mut ok = true;
+                for arg in args {
+                    ok &= self.datatype_tyvars_bound(db, arg);
+                }
+                ok
+            }
+            Record(rows) => {
+                let mut ok = true;
+                for row in rows {
+                    ok &= self.datatype_tyvars_bound(db, &row.data);
+                }
+                ok
+            }
         }
     }
 
@@ -1446,7 +2384,12 @@ impl<'a> Context<'a> {
 
             let ty = match &con.data {
                 Some(ty) => {
-                    let dom = self.elaborate_type(ty, false);
+                    // Only the datatype's own parameters are in scope, and not
+                    // any type variables bound by an enclosing declaration
+                    let dom = match self.datatype_tyvars_bound(db, ty) {
+                        true => self.elaborate_type(ty, false),
+                        false => self.fresh_tyvar(),
+                    };
                     constructors.push((cons, Some(dom)));
                     self.arena.types.arrow(dom, res)
                 }
@@ -1479,6 +2422,12 @@ impl<'a> Context<'a> {
         // we can construct data constructor arguments (e.g. recursive/mutually
         // recursive datatypes)
         for db in dbs {
+            if db.constructors.is_empty() {
+                self.elab_errors.push(
+                    ElabError::new(db.span, "datatype has no constructors")
+                        .kind(ErrorKind::NoConstructors(db.tycon)),
+                );
+            }
             let tycon = Tycon::new(db.tycon, db.tyvars.len(), self.scope_depth());
             self.define_type(db.tycon, TypeStructure::Tycon(tycon));
         }
@@ -1493,10 +2442,34 @@ impl<'a> Context<'a> {
                     ctx.elab_decl_conbind(db, elab)
                 })
             })
-            .collect();
+            .collect::<Vec<Datatype>>();
+        for dt in &dts {
+            if let Some(con) = dt.constructors.first() {
+                let cons = dt.constructors.iter().map(|(c, _)| *c).collect::<Vec<_>>();
+                self.define_datatype(con.0.type_id, &cons);
+            }
+        }
         elab.push(Decl::Datatype(dts));
     }
 
+    fn elab_decl_replicate(&mut self, sp: Span, tycon: Symbol, name: Symbol) {
+        match self.lookup_type(&name).cloned() {
+            Some(TypeStructure::Datatype(con, cons)) => {
+                for c in &cons {
+                    self.define_value(c.name, sp, c.scheme.clone(), IdStatus::Con(c.con));
+                }
+                self.define_type(tycon, TypeStructure::Datatype(con, cons));
+            }
+            Some(_) => self.elab_errors.push(
+                ElabError::new(sp, "replicated type is not a datatype")
+                    .kind(ErrorKind::NotDatatype(name)),
+            ),
+            None => self
+                .elab_errors
+                .push(ElabError::new(sp, "type").kind(ErrorKind::Unbound(name))),
+        }
+    }
+
     fn elab_decl_exception(&mut self, exns: &[ast::Variant], elab: &mut Vec<Decl<'a>>) {
         for exn in exns {
             let con = Constructor {
@@ -1546,13 +2519,14 @@ impl<'a> Context<'a> {
         let res_ty = self.fresh_tyvar();
         let mut clauses = Vec::new();
         for clause in fbs {
-            if let Some(ty) = &clause.res_ty {
+            let annotation = clause.res_ty.as_ref().map(|ty| {
                 let t = self.elaborate_type(&ty, false);
                 self.unify(res_ty, t, &|c| {
                     c.span(ty.span)
-                        .message("function clause with result constraint of different type")
+                        .message("result type annotation differs from that of an earlier clause")
                 });
-            }
+                t
+            });
 
             let mut pats = Vec::new();
             let mut bindings = Vec::new();
@@ -1579,6 +2553,7 @@ impl<'a> Context<'a> {
                 expr: &clause.expr,
                 pats,
                 bindings,
+                annotation,
                 span: clause.span,
             })
         }
@@ -1598,11 +2573,14 @@ impl<'a> Context<'a> {
         }
     }
 
+    /// Elaborate the clauses of a function, returning its lambda, the span of
+    /// all of its clauses, and whether its type may be generalized. The
+    /// function's final type is pushed onto `types`
     fn elab_decl_fnbind(
         &mut self,
         fun: PartialFun<'_, 'a>,
         types: &mut Vec<&'a Type<'a>>,
-    ) -> Lambda<'a> {
+    ) -> (Lambda<'a>, Span, bool) {
         let PartialFun {
             clauses,
             res_ty,
@@ -1619,6 +2597,7 @@ impl<'a> Context<'a> {
             pats,
             expr,
             bindings,
+            annotation,
             span,
         } in clauses
         {
@@ -1626,23 +2605,32 @@ impl<'a> Context<'a> {
             // to elaborate the body of the function clause
             self.tyvar_rank += 1;
             let expr = self.with_scope(move |ctx| {
-                for (var, tv) in bindings {
-                    ctx.define_value(var, span, Scheme::Mono(tv), IdStatus::Var);
+                for (var, tv, sp) in bindings {
+                    ctx.define_value(var, sp, Scheme::Mono(tv), IdStatus::Var);
                 }
                 ctx.elaborate_expr(&expr)
             });
             self.tyvar_rank -= 1;
-            // Unify function clause body with result type
-            self.unify(res_ty, expr.ty, &|c| {
-                c.span(span)
-                    .message("function clause body doesn't match with return type")
-            });
+            // Unify function clause body with result type. An annotated
+            // clause is checked against its own annotation, so that a clash
+            // between the annotations of two clauses is only reported once
+            match annotation {
+                Some(ty) => self.unify(ty, expr.ty, &|c| {
+                    c.span(expr.span)
+                        .message("function clause body doesn't match its result type annotation")
+                }),
+                None => self.unify(res_ty, expr.ty, &|c| {
+                    c.span(span)
+                        .message("function clause body doesn't match with return type")
+                }),
+            }
 
             let tuple_ty = self.arena.types.tuple(pats.iter().map(|pat| pat.ty));
             let tuple_pat = Pat::new(self.arena.pats.tuple(pats.iter().copied()), tuple_ty, span);
             let rule = Rule {
                 pat: tuple_pat,
                 expr,
+                span,
             };
 
             if pats.iter().any(|p| p.flexible()) {
@@ -1685,21 +2673,31 @@ impl<'a> Context<'a> {
                     )
                 });
 
-        // Rebind with final type. Unbind first so that generalization happens properly
-        self.unbind_value(fun.name);
-        let ty = self.arena.types.arrow(t, ty);
-        let sch = match dontgeneralize {
-            true => Scheme::Mono(ty),
-            false => self.generalize(ty),
-        };
-
-        types.push(ty);
-        self.define_value(fun.name, total_sp, sch, IdStatus::Var);
-
-        Lambda {
+        types.push(self.arena.types.arrow(t, ty));
+        let lambda = Lambda {
             arg: a,
             ty: t,
             body,
+        };
+        (lambda, total_sp, !dontgeneralize)
+    }
+
+    /// Explicitly scoped type variables must be generalized at the declaration
+    /// that binds them, so they can't be instantiated to a concrete type, or
+    /// be unified with each other
+    fn check_explicit_tyvars(&mut self, sp: Span, tyvars: &[(Symbol, &'a TypeVar<'a>)]) {
+        let mut seen = HashSet::new();
+        for (sym, tv) in tyvars {
+            let mut tv = *tv;
+            while let Some(Type::Var(next)) = tv.ty() {
+                tv = next;
+            }
+            if tv.ty().is_some() || !seen.insert(tv.id) {
+                self.elab_errors.push(
+                    ElabError::new(sp, "explicit type variable can't be instantiated")
+                        .kind(ErrorKind::Rigid(*sym)),
+                );
+            }
         }
     }
 
@@ -1710,12 +2708,21 @@ impl<'a> Context<'a> {
                 let f = ctx.arena.types.fresh_type_var(ctx.tyvar_rank);
                 ctx.tyvars.push((*sym, f));
             }
+            let explicit = ctx.tyvars[ctx.tyvars.len() - tyvars.len()..].to_vec();
 
             let mut info = Vec::new();
             // Check to make sure all of the function clauses are consistent within each
             // binding group
             for f in fbs {
                 let name = f[0].name;
+                if let Some((_, IdStatus::Con(_))) | Some((_, IdStatus::Exn(_))) =
+                    ctx.lookup_value(&name)
+                {
+                    ctx.elab_errors.push(
+                        ElabError::new(f.span, "constructor can't be rebound as a function")
+                            .kind(ErrorKind::Constructor(name)),
+                    );
+                }
                 let arity = f.iter().map(|fb| fb.pats.len()).max().unwrap_or(1);
                 let fns = ctx.elab_decl_fnbind_ty(name, arity, f);
 
@@ -1724,84 +2731,531 @@ impl<'a> Context<'a> {
             }
             ctx.tyvar_rank -= 1;
 
+            let n = ctx.bind_recursive(info.iter().map(|fun| (fun.name, fun.ty)));
+
             let mut types = Vec::new();
+            let mut rebind = Vec::new();
             let lams = info
                 .into_iter()
-                .map(|fun| (fun.name, ctx.elab_decl_fnbind(fun, &mut types)))
+                .map(|fun| {
+                    let name = fun.name;
+                    let (lambda, sp, generalize) = ctx.elab_decl_fnbind(fun, &mut types);
+                    rebind.push((name, sp, generalize));
+                    (name, lambda)
+                })
                 .collect();
+            ctx.recursive.truncate(n);
+
+            // Rebind with the final types, only once every function in the group
+            // has been elaborated. Unbind them all first, since a function that
+            // is still monomorphic in the environment would keep the type
+            // variables it shares with the others from being generalized
+            for (name, _, _) in &rebind {
+                ctx.unbind_value(*name);
+            }
+            for ((name, sp, generalize), ty) in rebind.into_iter().zip(&types) {
+                let sch = match generalize {
+                    true => ctx.generalize(ty),
+                    false => Scheme::Mono(ty),
+                };
+                ctx.define_value(name, sp, sch, IdStatus::Var);
+            }
+            if let Some(f) = fbs.first() {
+                ctx.check_explicit_tyvars(f.span, &explicit);
+            }
 
             let tyvars = Type::ftv_rank_init(ctx.tyvar_rank, types);
             elab.push(Decl::Fun(tyvars, lams));
         })
     }
 
-    fn elab_decl_val(
+    /// Elaborate a group of simultaneous value bindings. All of the right
+    /// hand sides are elaborated before any of the patterns are bound, so
+    /// that no binding in the group can refer to another. The bindings
+    /// before a `rec` are only bound once the recursive ones have been
+    /// elaborated, since they aren't visible to them either
+    fn elab_decl_val(&mut self, tyvars: &[Symbol], vbs: &[ast::Valbind], elab: &mut Vec<Decl<'a>>) {
+        let idx = vbs.iter().position(|vb| vb.recursive).unwrap_or(vbs.len());
+        let (defs, decls) = match idx {
+            0 => (Vec::new(), Vec::new()),
+            _ => self.elab_val_bindings(tyvars, &vbs[..idx], idx < vbs.len(), elab),
+        };
+        if idx < vbs.len() {
+            self.elab_decl_val_rec(tyvars, &vbs[idx..], elab);
+        }
+        elab.extend(decls);
+        for (var, sp, sch) in defs {
+            self.define_value(var, sp, sch, IdStatus::Var);
+        }
+    }
+
+    /// Elaborate non-recursive value bindings, returning the variables that
+    /// they bind and their core declarations, without binding either. If
+    /// `defer` is set, every right hand side is first bound to a fresh
+    /// variable in `elab`, so that the declarations returned can come after
+    /// other bindings of the same group
+    fn elab_val_bindings(
         &mut self,
         tyvars: &[Symbol],
-        pat: &ast::Pat,
-        expr: &ast::Expr,
+        vbs: &[ast::Valbind],
+        defer: bool,
         elab: &mut Vec<Decl<'a>>,
-    ) {
+    ) -> (Vec<(Symbol, Span, Scheme<'a>)>, Vec<Decl<'a>>) {
         self.with_tyvars(|ctx| {
             ctx.tyvar_rank += 1;
             for tyvar in tyvars {
                 ctx.tyvars
                     .push((*tyvar, ctx.arena.types.fresh_type_var(ctx.tyvar_rank)));
             }
+            let explicit = ctx.tyvars[ctx.tyvars.len() - tyvars.len()..].to_vec();
 
-            let expr = ctx.elaborate_expr(expr);
-
-            let (pat, bindings) = ctx.elaborate_pat(pat, false);
+            let exprs = vbs
+                .iter()
+                .map(|vb| ctx.elaborate_expr(&vb.expr))
+                .collect::<Vec<_>>();
+            let pats = vbs
+                .iter()
+                .map(|vb| ctx.elaborate_pat(&vb.pat, false))
+                .collect::<Vec<_>>();
             ctx.tyvar_rank -= 1;
-            ctx.unify(pat.ty, expr.ty, &|c| {
-                c.span(expr.span)
-                    .message("pattern and expression have different types in `val` declaration")
-            });
+            for ((pat, _), expr) in pats.iter().zip(&exprs) {
+                ctx.unify(pat.ty, expr.ty, &|c| {
+                    c.span(expr.span)
+                        .message("pattern and expression have different types in `val` declaration")
+                });
+            }
+            if let Some((pat, _)) = pats.first() {
+                ctx.check_explicit_tyvars(pat.span, &explicit);
+            }
 
-            let dontgeneralize = !expr.non_expansive() || pat.flexible();
-            let mut tyvars = Vec::new();
-            for (var, tv) in &bindings {
-                let sch = match dontgeneralize {
-                    false => ctx.generalize(tv),
-                    true => Scheme::Mono(tv),
-                };
-                if let Scheme::Poly(vars, _) = &sch {
-                    tyvars.extend(vars);
+            // Core ML bindings are sequential, so when there are several
+            // bindings, each expression is first bound to a fresh variable.
+            // This keeps a pattern from shadowing a variable that one of the
+            // other expressions refers to
+            let group = defer || pats.len() > 1;
+            let mut defs = Vec::new();
+            let mut decls = Vec::with_capacity(pats.len());
+            for ((pat, bindings), mut expr) in pats.into_iter().zip(exprs) {
+                let dontgeneralize = !expr.non_expansive() || pat.flexible();
+                let mut tyvars = Vec::new();
+                for (var, tv, _) in &bindings {
+                    let sch = match dontgeneralize {
+                        false => ctx.generalize(tv),
+                        true => Scheme::Mono(tv),
+                    };
+                    if let Scheme::Poly(vars, _) = &sch {
+                        tyvars.extend(vars);
+                    }
+                    defs.push((*var, pat.span, sch));
                 }
-                ctx.define_value(*var, pat.span, sch, IdStatus::Var);
+                if group {
+                    let var = ctx.fresh_var();
+                    let vars = match dontgeneralize {
+                        false => ctx.generalize(expr.ty),
+                        true => Scheme::Mono(expr.ty),
+                    };
+                    let vars = match vars {
+                        Scheme::Poly(vars, _) => vars,
+                        Scheme::Mono(_) => Vec::new(),
+                    };
+                    let tmp = ctx.arena.pat_var(var, expr.ty);
+                    elab.push(Decl::Val(
+                        vars,
+                        Rule {
+                            pat: tmp,
+                            expr,
+                            span: expr.span,
+                        },
+                    ));
+                    expr = Expr::new(
+                        ctx.arena.exprs.alloc(ExprKind::Var(var)),
+                        expr.ty,
+                        expr.span,
+                    );
+                }
+                match pat.kind {
+                    PatKind::Var(_) | PatKind::Wild => {
+                        let span = pat.span + expr.span;
+                        decls.push(Decl::Val(tyvars, Rule { pat, expr, span }));
+                    }
+                    _ => {
+                        // If we have some kind of compound binding, go ahead and
+                        // do a source->source rewrite
+                        let vars = bindings
+                            .iter()
+                            .map(|(var, tv, _)| (*var, *tv))
+                            .collect::<Vec<_>>();
+                        let rule = crate::match_compile::val(ctx, expr, pat, &vars);
+                        decls.push(Decl::Val(tyvars, rule));
+                    }
+                }
+            }
+            (defs, decls)
+        })
+    }
+
+    /// Elaborate the bindings of a `val rec`. Each one must bind a variable,
+    /// and its right hand side must be a `fn`, possibly with type constraints.
+    /// As with the functions of a `fun`, the variables are bound
+    /// monomorphically while the right hand sides are elaborated, and are
+    /// only generalized afterwards. A binding that breaks these rules is
+    /// reported, and elaborated as if it weren't recursive
+    fn elab_decl_val_rec(
+        &mut self,
+        tyvars: &[Symbol],
+        vbs: &[ast::Valbind],
+        elab: &mut Vec<Decl<'a>>,
+    ) {
+        let mut fns = Vec::new();
+        let mut rest = Vec::new();
+        for vb in vbs {
+            let mut pat = &vb.pat;
+            while let ast::PatKind::Ascribe(p, _) = &pat.data {
+                pat = p;
+            }
+            let mut expr = &vb.expr;
+            while let ast::ExprKind::Constraint(e, _) = &expr.data {
+                expr = e;
+            }
+            if !matches!(pat.data, ast::PatKind::Variable(_)) {
+                self.elab_errors.push(ElabError::new(
+                    vb.pat.span,
+                    "`val rec` can only bind a variable",
+                ));
+            } else if !matches!(expr.data, ast::ExprKind::Fn(_)) {
+                self.elab_errors.push(ElabError::new(
+                    vb.expr.span,
+                    "the right hand side of a `val rec` binding must be a `fn` expression",
+                ));
+            } else {
+                fns.push(vb);
+                continue;
+            }
+            rest.push(ast::Valbind {
+                recursive: false,
+                ..vb.clone()
+            });
+        }
+        let (defs, decls) = match rest.is_empty() {
+            true => (Vec::new(), Vec::new()),
+            false => self.elab_val_bindings(tyvars, &rest, !fns.is_empty(), elab),
+        };
+        if !fns.is_empty() {
+            self.elab_val_rec_fns(tyvars, &fns, elab);
+        }
+        elab.extend(decls);
+        for (var, sp, sch) in defs {
+            self.define_value(var, sp, sch, IdStatus::Var);
+        }
+    }
+
+    /// Elaborate the well-formed bindings of a `val rec`, each binding a
+    /// variable to a `fn`
+    fn elab_val_rec_fns(
+        &mut self,
+        tyvars: &[Symbol],
+        fns: &[&ast::Valbind],
+        elab: &mut Vec<Decl<'a>>,
+    ) {
+        self.with_tyvars(|ctx| {
+            ctx.tyvar_rank += 1;
+            for tyvar in tyvars {
+                ctx.tyvars
+                    .push((*tyvar, ctx.arena.types.fresh_type_var(ctx.tyvar_rank)));
             }
-            match pat.kind {
-                PatKind::Var(_) | PatKind::Wild => {
-                    elab.push(Decl::Val(tyvars, Rule { pat, expr }));
+            let explicit = ctx.tyvars[ctx.tyvars.len() - tyvars.len()..].to_vec();
+
+            let mut pats = Vec::new();
+            let mut names = Vec::new();
+            for vb in fns {
+                let (pat, bindings) = ctx.elaborate_pat(&vb.pat, false);
+                for (var, ty, _) in bindings {
+                    ctx.define_value(var, pat.span, Scheme::Mono(ty), IdStatus::Var);
+                    names.push((var, ty));
                 }
-                _ => {
-                    // If we have some kind of compound binding, go ahead and
-                    // do a source->source rewrite
-                    let rule = crate::match_compile::val(ctx, expr, pat, &bindings);
-                    elab.push(Decl::Val(tyvars, rule));
+                pats.push(pat);
+            }
+
+            let n = ctx.bind_recursive(names.iter().copied());
+            let exprs = fns
+                .iter()
+                .map(|vb| ctx.elaborate_expr(&vb.expr))
+                .collect::<Vec<_>>();
+            ctx.recursive.truncate(n);
+            ctx.tyvar_rank -= 1;
+
+            for (pat, expr) in pats.iter().zip(&exprs) {
+                ctx.unify(pat.ty, expr.ty, &|c| {
+                    c.span(expr.span)
+                        .message("pattern and expression have different types in `val` declaration")
+                });
+            }
+            if let Some(pat) = pats.first() {
+                ctx.check_explicit_tyvars(pat.span, &explicit);
+            }
+
+            // As in `elab_decl_fun`, unbind the whole group before generalizing
+            for (name, _) in &names {
+                ctx.unbind_value(*name);
+            }
+            for ((name, ty), pat) in names.iter().zip(&pats) {
+                let sch = ctx.generalize(ty);
+                ctx.define_value(*name, pat.span, sch, IdStatus::Var);
+            }
+
+            let mut types = Vec::new();
+            let mut lams = Vec::new();
+            for ((name, _), expr) in names.iter().zip(&exprs) {
+                // The depth limit may have replaced the `fn` with a variable,
+                // in which case an error has already been reported
+                if let ExprKind::Lambda(lam) = expr.kind {
+                    types.push(expr.ty);
+                    lams.push((*name, *lam));
                 }
             }
+            let tyvars = Type::ftv_rank_init(ctx.tyvar_rank, types);
+            elab.push(Decl::Fun(tyvars, lams));
         })
     }
 
     fn elaborate_decl_inner(&mut self, decl: &ast::Decl, elab: &mut Vec<Decl<'a>>) {
         match &decl.data {
             ast::DeclKind::Datatype(dbs) => self.elab_decl_datatype(dbs, elab),
+            ast::DeclKind::Replicate(tycon, name) => {
+                self.elab_decl_replicate(decl.span, *tycon, *name)
+            }
             ast::DeclKind::Type(tbs) => self.elab_decl_type(tbs, elab),
             ast::DeclKind::Function(tyvars, fbs) => self.elab_decl_fun(tyvars, fbs, elab),
-            ast::DeclKind::Value(tyvars, pat, expr) => self.elab_decl_val(tyvars, pat, expr, elab),
+            ast::DeclKind::Value(tyvars, vbs) => self.elab_decl_val(tyvars, vbs, elab),
             ast::DeclKind::Exception(exns) => self.elab_decl_exception(exns, elab),
             ast::DeclKind::Fixity(fixity, bp, sym) => self.elab_decl_fixity(fixity, *bp, *sym),
             ast::DeclKind::Local(decls, body) => self.elab_decl_local(decls, body, elab),
             ast::DeclKind::Seq(decls) => self.elab_decl_seq(decls, elab),
+            ast::DeclKind::Signature(name, specs, wheres) => {
+                self.elab_decl_signature(*name, specs, wheres)
+            }
         }
     }
 
+    /// Elaborate a top-level declaration. If no errors were encountered, the
+    /// resulting core declarations are also saved in the program returned by
+    /// [`Context::program`]
     pub fn elaborate_decl(&mut self, decl: &ast::Decl) -> Vec<Decl<'a>> {
+        let (elab_errors, unification_errors) =
+            (self.elab_errors.len(), self.unification_errors.len());
         let mut elab = Vec::new();
         self.elaborate_decl_inner(decl, &mut elab);
+        self.resolve_overloads();
+
+        // Warnings are reported through `elab_errors` too, but they don't
+        // prevent a declaration from being part of the program
+        let failed = self.unification_errors.len() > unification_errors
+            || self.elab_errors[elab_errors..]
+                .iter()
+                .any(|e| matches!(e.level, Level::Error | Level::Bug));
+        if !failed {
+            self.decls.extend(elab.iter().cloned());
+        }
         elab
     }
+
+    /// Parse `src` as a program, then check and elaborate each of its
+    /// declarations in turn. Parsing recovers from errors, so the diagnostics
+    /// of every phase are collected and returned together, in source order,
+    /// if any of them is an error. Warnings are discarded otherwise
+    pub fn elaborate_str(
+        &mut self,
+        interner: &mut Interner,
+        src: &str,
+    ) -> Result<(), Vec<Diagnostic>> {
+        let mut parser = sml_frontend::parser::Parser::new(src, interner);
+        let (decls, errors) = parser.parse_program();
+        let mut diags = parser.diags;
+        diags.extend(errors.into_iter().map(|e| e.to_diagnostic()));
+
+        for decl in &decls {
+            let mut check = super::check::Check::new(interner);
+            check.check_decl(decl);
+            diags.extend(check.diags);
+            self.elaborate_decl(decl);
+        }
+        diags.extend(self.take_diagnostics(interner));
+        diags.sort_by_key(|d| (d.primary.span.start.line, d.primary.span.start.col));
+
+        match diags
+            .iter()
+            .any(|d| matches!(d.level, Level::Error | Level::Bug))
+        {
+            true => Err(diags),
+            false => Ok(()),
+        }
+    }
+
+    /// Elaborate an expression entered at top level, as if it were the
+    /// declaration `val it = expr`. The inferred type of the expression can
+    /// then be queried with [`Context::type_of`]
+    pub fn elaborate_top_expr(&mut self, expr: &ast::Expr) -> Vec<Decl<'a>> {
+        self.elaborate_decl(&ast::make_top_level_expr(expr.clone()))
+    }
+
+    /// Resolve the overloaded identifiers used in the last top-level
+    /// declaration whose type is still ambiguous, by defaulting them to `int`.
+    /// If `overload_default_int` isn't set, each ambiguous type is also
+    /// reported as an error, listing the types it could have been
+    fn resolve_overloads(&mut self) {
+        for (ty, sp, sym) in std::mem::take(&mut self.overloads) {
+            if let Type::Var(tv) = ty.resolve() {
+                if let Some(class) = tv.overload() {
+                    if !self.overload_default_int {
+                        self.elab_errors.push(
+                            ElabError::new(sp, "ambiguous use of overloaded identifier")
+                                .kind(ErrorKind::Overload(sym, class)),
+                        );
+                    }
+                    tv.data.set(Some(self.arena.types.int()));
+                }
+            }
+        }
+    }
+
+    /// All successfully elaborated top-level declarations, in order
+    pub fn program(&self) -> &[Decl<'a>] {
+        &self.decls
+    }
+
+    /// Save the current state of the context, so that anything elaborated
+    /// afterwards can be discarded with [`Context::rollback`]. Only the
+    /// namespaces enclosing the current scope are copied, since those are
+    /// the only existing namespaces that new definitions can be added to.
+    ///
+    /// Types and terms allocated in the arena are not reclaimed, and type
+    /// variables from before the checkpoint that are unified afterwards stay
+    /// unified. For the same reason, the arena's counters for fresh type
+    /// variables and gensyms are not restored either: a variable created
+    /// after the checkpoint may still be reachable through one that was
+    /// unified with it, and must not share its id with a later one
+    pub fn checkpoint(&self) -> Checkpoint {
+        let mut scopes = Vec::new();
+        let mut id = Some(self.current);
+        while let Some(ns) = id {
+            scopes.push((ns, self.namespaces[ns].clone()));
+            id = self.namespaces[ns].parent;
+        }
+        Checkpoint {
+            scopes,
+            namespaces: self.namespaces.len(),
+            current: self.current,
+            types: self.types.len(),
+            values: self.values.len(),
+            tyvars: self.tyvars.len(),
+            tyvar_rank: self.tyvar_rank,
+            locals: self.locals.len(),
+            recursive: self.recursive.len(),
+            elab_errors: self.elab_errors.len(),
+            unification_errors: self.unification_errors.len(),
+            overloads: self.overloads.len(),
+            diags: self.diags.len(),
+            next_tyvar: self.arena.types.next_var_id(),
+            decls: self.decls.len(),
+            signatures: self.signatures.clone(),
+        }
+    }
+
+    /// Restore the context to the state it was in at `checkpoint`, including
+    /// the overloads awaiting resolution and the pending diagnostics. As
+    /// described in [`Context::checkpoint`], the arena is left as it is
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.namespaces.truncate(checkpoint.namespaces);
+        for (id, ns) in checkpoint.scopes {
+            self.namespaces[id] = ns;
+        }
+        self.current = checkpoint.current;
+        self.types.truncate(checkpoint.types);
+        self.values.truncate(checkpoint.values);
+        self.tyvars.truncate(checkpoint.tyvars);
+        self.tyvar_rank = checkpoint.tyvar_rank;
+        self.locals.truncate(checkpoint.locals);
+        self.recursive.truncate(checkpoint.recursive);
+        self.elab_errors.truncate(checkpoint.elab_errors);
+        self.unification_errors
+            .truncate(checkpoint.unification_errors);
+        self.overloads.truncate(checkpoint.overloads);
+        self.diags.truncate(checkpoint.diags);
+        let next_tyvar = checkpoint.next_tyvar;
+        self.tyvar_origins.retain(|id, _| *id < next_tyvar);
+        self.decls.truncate(checkpoint.decls);
+        self.signatures = checkpoint.signatures;
+    }
+
+    /// Return the type scheme of the value bound to `name` in the current
+    /// scope, if there is one
+    pub fn type_of(&self, interner: &Interner, name: &str) -> Option<Scheme<'a>> {
+        let sym = interner.lookup(name)?;
+        self.lookup_value(&sym).map(|(sch, _)| sch.clone())
+    }
+
+    /// Is `name` bound to a data or exception constructor in the current
+    /// scope?
+    pub fn is_constructor(&self, interner: &Interner, name: &str) -> bool {
+        let sym = match interner.lookup(name) {
+            Some(sym) => sym,
+            None => return false,
+        };
+        matches!(
+            self.lookup_value(&sym),
+            Some((_, IdStatus::Con(_))) | Some((_, IdStatus::Exn(_)))
+        )
+    }
+
+    /// Describe the types and values bound in each namespace visible from the
+    /// current scope, starting with the innermost. This is only intended for
+    /// debugging the elaborator
+    pub fn dump_env(&self, interner: &Interner) -> String {
+        let mut out = String::new();
+        let name = |sym: &Symbol| interner.get(*sym).unwrap_or("?").to_string();
+        for ns in self.namespace_iter() {
+            let _ = writeln!(out, "namespace at depth {}", ns.depth);
+
+            let mut types = ns
+                .types
+                .iter()
+                .map(|(s, id)| (name(s), *id))
+                .collect::<Vec<_>>();
+            types.sort_by(|a, b| a.0.cmp(&b.0));
+            for (tycon, id) in types {
+                let tystr = &self.types[id.0 as usize];
+                let kind = match tystr {
+                    TypeStructure::Datatype(_, _) => "datatype",
+                    TypeStructure::Scheme(_) => "type",
+                    TypeStructure::Tycon(_) => "tycon",
+                };
+                let _ = writeln!(out, "  type {}: {}, arity {}", tycon, kind, tystr.arity());
+            }
+
+            let mut values = ns
+                .values
+                .iter()
+                .map(|(s, id)| (name(s), *id))
+                .collect::<Vec<_>>();
+            values.sort_by(|a, b| a.0.cmp(&b.0));
+            for (value, id) in values {
+                let (scheme, status) = &self.values[id.0 as usize];
+                let ty = match scheme {
+                    Scheme::Mono(ty) | Scheme::Poly(_, ty) => ty,
+                };
+                let mut pp = PrettyPrinter::new(interner);
+                let _ = write!(out, "  val {}: ", value);
+                let _ = pp.print(*ty).write_fmt(&mut out);
+                let status = match status {
+                    IdStatus::Var => "var",
+                    IdStatus::Con(_) => "con",
+                    IdStatus::Exn(_) => "exn",
+                };
+                let _ = writeln!(out, ", {}", status);
+            }
+        }
+        out
+    }
 }
 
 impl<'a> Query<ast::Pat> for &Context<'a> {
@@ -1812,26 +3266,30 @@ impl<'a> Query<ast::Pat> for &Context<'a> {
         }
     }
 
+    fn span(&self, t: &ast::Pat) -> Span {
+        t.span
+    }
+
     fn infix(&self, a: ast::Pat, b: ast::Pat, c: ast::Pat) -> Result<ast::Pat, precedence::Error> {
         // We know `a` must be a symbol, since it has a Fixity!
         match a.data {
             ast::PatKind::Variable(s) => {
-                let sp_bc = b.span + c.span;
-                let sp = a.span + sp_bc;
+                let sp_bc = b.span.merge(c.span);
+                let sp = Span::merge_all(vec![a.span, b.span, c.span]);
                 let rec = ast::Pat::new(ast::make_record_pat(vec![b, c], false), sp_bc);
                 Ok(ast::Pat::new(ast::PatKind::App(s, Box::new(rec)), sp))
             }
-            _ => Err(precedence::Error::InvalidOperator),
+            _ => Err(precedence::Error::InvalidOperator(a.span)),
         }
     }
 
     fn apply(&self, a: ast::Pat, b: ast::Pat) -> Result<ast::Pat, precedence::Error> {
         match a.data {
             ast::PatKind::Variable(s) => {
-                let sp = a.span + b.span;
+                let sp = a.span.merge(b.span);
                 Ok(ast::Pat::new(ast::PatKind::App(s, Box::new(b)), sp))
             }
-            _ => Err(precedence::Error::InvalidOperator),
+            _ => Err(precedence::Error::InvalidOperator(a.span)),
         }
     }
 }
@@ -1844,14 +3302,18 @@ impl<'a> Query<ast::Expr> for &Context<'a> {
         }
     }
 
+    fn span(&self, t: &ast::Expr) -> Span {
+        t.span
+    }
+
     fn infix(
         &self,
         a: ast::Expr,
         b: ast::Expr,
         c: ast::Expr,
     ) -> Result<ast::Expr, precedence::Error> {
-        let sp_bc = b.span + c.span;
-        let sp = a.span + sp_bc;
+        let sp_bc = b.span.merge(c.span);
+        let sp = Span::merge_all(vec![a.span, b.span, c.span]);
         let rec = ast::Expr::new(ast::make_record(vec![b, c]), sp_bc);
         Ok(ast::Expr::new(
             ast::ExprKind::App(Box::new(a), Box::new(rec)),
@@ -1859,7 +3321,7 @@ impl<'a> Query<ast::Expr> for &Context<'a> {
         ))
     }
     fn apply(&self, a: ast::Expr, b: ast::Expr) -> Result<ast::Expr, precedence::Error> {
-        let sp = a.span + b.span;
+        let sp = a.span.merge(b.span);
         Ok(ast::Expr::new(
             ast::ExprKind::App(Box::new(a), Box::new(b)),
             sp,
@@ -1882,3 +3344,692 @@ impl<'a> Context<'a> {
         Precedence::run(self, exprs)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{elaborate, with_context};
+    use sml_frontend::parser::Parser;
+
+    fn bound_name(decl: &Decl<'_>) -> Option<Symbol> {
+        match decl {
+            Decl::Val(_, Rule { pat, .. }) => match pat.kind {
+                PatKind::Var(sym) => Some(*sym),
+                _ => None,
+            },
+            Decl::Fun(_, binds) => binds.first().map(|(name, _)| *name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn program_saves_decls_in_order() {
+        with_context(|interner, _, ctx| {
+            for src in &["val a = 1", "fun b x = x"] {
+                elaborate(ctx, interner, src);
+            }
+            assert!(ctx.diagnostics(interner).is_empty());
+
+            let names = ctx.program().iter().map(bound_name).collect::<Vec<_>>();
+            assert_eq!(
+                names,
+                vec![Some(interner.intern("a")), Some(interner.intern("b"))]
+            );
+        })
+    }
+
+    #[test]
+    fn fixity_persists_across_decls() {
+        with_context(|interner, _, ctx| {
+            // Each input is elaborated separately in the same `Context`, as the
+            // REPL does with successive inputs
+            for src in &["infix 6 ++", "fun ++ (a, b) = a + b", "val z = 1 ++ 2"] {
+                elaborate(ctx, interner, src);
+            }
+            assert!(ctx.diagnostics(interner).is_empty());
+            match ctx.type_of(interner, "z") {
+                Some(Scheme::Mono(ty)) => assert!(*ty == *ctx.arena.types.int()),
+                _ => panic!("expected `z` to be bound to an int"),
+            }
+
+            // And within a single sequence of top-level declarations
+            elaborate(
+                ctx,
+                interner,
+                "infixr 5 @@ fun @@ (a, b) = (a, b) val w = 1 @@ true",
+            );
+            assert!(ctx.diagnostics(interner).is_empty());
+            assert!(ctx.type_of(interner, "w").is_some());
+        })
+    }
+
+    #[test]
+    fn reset_gensym_numbers_from_zero() {
+        with_context(|interner, arena, ctx| {
+            let decl = Parser::new("val f = fn (a, b) => a", interner)
+                .parse_decl()
+                .unwrap();
+            let render = |ctx: &mut Context| {
+                let mut out = String::new();
+                for d in ctx.elaborate_decl(&decl) {
+                    PrettyPrinter::new(interner)
+                        .print(&d)
+                        .write_fmt(&mut out)
+                        .unwrap();
+                }
+                out
+            };
+
+            ctx.reset_gensym();
+            let first = render(ctx);
+            ctx.reset_gensym();
+            let second = render(ctx);
+            assert!(first.contains("_0"));
+            assert_eq!(first, second);
+
+            // Ids allocated directly from the arena, as in monomorphization, are
+            // numbered from zero again too
+            ctx.reset_gensym();
+            assert_eq!(arena.exprs.allocate_id(), Symbol::Gensym(0));
+            assert_eq!(ctx.fresh_var(), Symbol::Gensym(1));
+        })
+    }
+
+    #[test]
+    fn do_decls_are_saved_in_order() {
+        with_context(|interner, _, ctx| {
+            let src = "do (fn _ => ()) 1 do (fn _ => ()) 2 do (fn _ => ()) 3";
+            elaborate(ctx, interner, src);
+            assert!(ctx.diagnostics(interner).is_empty());
+
+            let program = ctx.program();
+            assert_eq!(program.len(), 3);
+            for (idx, decl) in program.iter().enumerate() {
+                let out = crate::pretty::Pretty::new(interner).decls(std::slice::from_ref(decl));
+                assert!(out.starts_with("val () = "));
+                assert!(out.contains(&format!("end) {} ", idx + 1)));
+            }
+        })
+    }
+
+    #[test]
+    fn duplicate_diagnostics_are_dropped() {
+        with_context(|interner, _, ctx| {
+            // Elaborating the same declaration twice reports the unbound variable
+            // twice at the same span, while the second declaration differs in span
+            elaborate(ctx, interner, "val a = y");
+            elaborate(ctx, interner, "val a = y");
+            elaborate(ctx, interner, "val bb = y");
+            assert_eq!(ctx.take_diagnostics(interner).len(), 2);
+
+            elaborate(ctx, interner, "val a = y");
+            elaborate(ctx, interner, "val a = y");
+            assert_eq!(ctx.diagnostics(interner).len(), 2);
+        })
+    }
+
+    #[test]
+    fn query_bound_names() {
+        with_context(|interner, _, ctx| {
+            elaborate(ctx, interner, "val x = 1");
+
+            let mut pp = PrettyPrinter::new(interner);
+            match ctx.type_of(interner, "x") {
+                Some(Scheme::Mono(ty)) => {
+                    let mut out = String::new();
+                    pp.print(ty).write_fmt(&mut out).unwrap();
+                    assert_eq!(out, "int");
+                }
+                _ => panic!("expected a monomorphic scheme for `x`"),
+            }
+            assert!(ctx.type_of(interner, "y").is_none());
+
+            assert!(ctx.is_constructor(interner, "true"));
+            assert!(ctx.is_constructor(interner, "::"));
+            assert!(!ctx.is_constructor(interner, "x"));
+        })
+    }
+
+    #[test]
+    fn short_sequences() {
+        with_context(|interner, arena, ctx| {
+            let c = |c| ast::Expr::new(ast::ExprKind::Const(c), Span::dummy());
+
+            let ex = ctx.elaborate_expr(&ast::Expr::new(ast::ExprKind::Seq(vec![]), Span::dummy()));
+            assert!(*ex.ty == *arena.types.unit());
+
+            let ex = ctx.elaborate_expr(&ast::Expr::new(
+                ast::ExprKind::Seq(vec![c(Const::Int(1))]),
+                Span::dummy(),
+            ));
+            assert!(*ex.ty == *arena.types.int());
+
+            let ex = ctx.elaborate_expr(&ast::Expr::new(
+                ast::ExprKind::Seq(vec![c(Const::Unit), c(Const::Int(1))]),
+                Span::dummy(),
+            ));
+            assert!(*ex.ty == *arena.types.int());
+            assert!(ctx.diagnostics(interner).is_empty());
+
+            let ex = ctx.elaborate_expr(&ast::Expr::new(
+                ast::ExprKind::Seq(vec![c(Const::Int(1)), c(Const::Unit)]),
+                Span::dummy(),
+            ));
+            assert!(*ex.ty == *arena.types.unit());
+            assert_eq!(ctx.diagnostics(interner).len(), 1);
+        })
+    }
+
+    #[test]
+    fn redundant_arm_spans_whole_rule() {
+        with_context(|interner, _, ctx| {
+            elaborate(ctx, interner, "val x = fn 1 => 2 | 1 => 30 | _ => 4");
+
+            let err = ctx
+                .elab_errors
+                .iter()
+                .find(|e| matches!(e.kind, ErrorKind::Redundant))
+                .expect("expected a redundant arm error");
+            assert_eq!((err.sp.start.line, err.sp.start.col), (0, 20));
+            assert_eq!((err.sp.end.line, err.sp.end.col), (0, 27));
+        })
+    }
+
+    /// A declaration binding `b` to a chain of `n` alternating `andalso` and
+    /// `orelse` operators, one per line
+    fn boolean_chain(n: usize) -> String {
+        let ops = ["andalso", "orelse"];
+        let mut src = String::from("val b = true");
+        for i in 0..n {
+            src.push_str(&format!("\n{} {} < 1", ops[i % 2], i));
+        }
+        src
+    }
+
+    #[test]
+    fn long_boolean_chains_are_not_too_deep() {
+        with_context(|interner, _, ctx| {
+            // Far more operators than either the parser's or the elaborator's
+            // default `max_depth`
+            elaborate(ctx, interner, &boolean_chain(2000));
+
+            assert!(ctx.diagnostics(interner).is_empty());
+            match ctx.type_of(interner, "b") {
+                Some(Scheme::Mono(ty)) => assert!(*ty == *ctx.arena.types.bool()),
+                _ => panic!("expected `b` to be bound to a bool"),
+            }
+        })
+    }
+
+    /// Timing dependent, so only run on request, with
+    /// `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    fn long_boolean_chains_take_linear_time() {
+        // The best of a few runs of parsing and elaborating a chain of `n`
+        // operators
+        let time = |n| {
+            let src = boolean_chain(n);
+            (0..3)
+                .map(|_| {
+                    let mut elapsed = std::time::Duration::default();
+                    with_context(|interner, _, ctx| {
+                        let start = std::time::Instant::now();
+                        elaborate(ctx, interner, &src);
+                        elapsed = start.elapsed();
+                    });
+                    elapsed
+                })
+                .min()
+                .unwrap()
+        };
+
+        // Four times the operators should take about four times as long, and
+        // sixteen times as long if the work were quadratic
+        let (small, large) = (time(2000), time(8000));
+        assert!(
+            large < small * 8,
+            "{} operators took {:?}, but {} took {:?}",
+            2000,
+            small,
+            8000,
+            large
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "too many values defined")]
+    fn value_ids_do_not_wrap() {
+        with_context(|interner, _, ctx| {
+            // Leave room for exactly one more value
+            ctx.max_ids = ctx.values.len() + 1;
+            for src in &["val a = 1", "val b = 2"] {
+                elaborate(ctx, interner, src);
+            }
+        })
+    }
+
+    /// Render an infix expression resolved by [`Context::expr_precedence`]
+    /// with explicit parentheses
+    fn parenthesize(interner: &Interner, expr: &ast::Expr) -> String {
+        match &expr.data {
+            ast::ExprKind::Var(s) => interner.get(*s).unwrap().to_string(),
+            ast::ExprKind::App(op, arg) => match &arg.data {
+                ast::ExprKind::Record(rows) => format!(
+                    "({} {} {})",
+                    parenthesize(interner, &rows[0].data),
+                    parenthesize(interner, op),
+                    parenthesize(interner, &rows[1].data)
+                ),
+                _ => panic!("expected an infix application"),
+            },
+            _ => panic!("expected a variable or an infix application"),
+        }
+    }
+
+    #[test]
+    fn infix_associativity() {
+        with_context(|interner, _, ctx| {
+            elaborate(ctx, interner, "infix 5 <+ infixr 5 +> infix 6 <*");
+
+            let mut resolve = |src: &str| {
+                let expr = Parser::new(src, interner).parse_expr().unwrap();
+                let exprs = match expr.data {
+                    ast::ExprKind::FlatApp(exprs) => exprs,
+                    _ => panic!("expected an infix expression"),
+                };
+                ctx.expr_precedence(exprs)
+                    .map(|e| parenthesize(interner, &e))
+            };
+
+            assert_eq!(resolve("a <+ b <+ c"), Ok("((a <+ b) <+ c)".into()));
+            assert_eq!(resolve("a +> b +> c"), Ok("(a +> (b +> c))".into()));
+            assert_eq!(resolve("a - b - c"), Ok("((a - b) - c)".into()));
+            assert_eq!(resolve("a :: b :: c"), Ok("(a :: (b :: c))".into()));
+            // Adjacent precedence levels
+            assert_eq!(resolve("a <+ b <* c"), Ok("(a <+ (b <* c))".into()));
+            assert_eq!(resolve("a <* b +> c"), Ok("((a <* b) +> c)".into()));
+            assert_eq!(resolve("a + b * c"), Ok("(a + (b * c))".into()));
+            // Left and right associative operators of the same precedence
+            assert!(matches!(
+                resolve("a <+ b +> c"),
+                Err(precedence::Error::SamePrecedence(_))
+            ));
+        })
+    }
+
+    #[test]
+    fn val_rec_binds_a_function() {
+        with_context(|interner, _, ctx| {
+            let decls = elaborate(ctx, interner, "val rec f = fn x => f x");
+            assert!(ctx.diagnostics(interner).is_empty());
+            assert!(matches!(decls.as_slice(), [Decl::Fun(_, _)]));
+        })
+    }
+
+    #[test]
+    fn elaborate_str_collects_all_diagnostics() {
+        with_context(|interner, _, ctx| {
+            assert!(ctx
+                .elaborate_str(interner, "val x = 1\nfun f y = y + x")
+                .is_ok());
+            assert!(ctx.type_of(interner, "f").is_some());
+
+            // The parser recovers from the missing `end`, so elaboration still
+            // reports errors on both sides of it, in source order
+            let src = "val b = 1 + true\nfun f x = let val y = x in y\nval c = 2 + false";
+            let diags = ctx.elaborate_str(interner, src).unwrap_err();
+            let lines = diags
+                .iter()
+                .map(|d| d.primary.span.start.line)
+                .collect::<Vec<_>>();
+            assert_eq!(lines, vec![0, 1, 2]);
+            assert_eq!(diags[1].primary.info, "Inserting token End");
+        })
+    }
+
+    #[test]
+    fn empty_record_unifies_with_unit() {
+        with_context(|_, arena, ctx| {
+            // An empty record type unifies with `unit`, but a non-empty one doesn't
+            let unit = arena.types.unit();
+            ctx.unify(arena.types.tuple(vec![]), unit, &|c| c);
+            assert!(ctx.unification_errors.is_empty());
+            ctx.unify(arena.types.tuple(vec![unit]), unit, &|c| c);
+            assert_eq!(ctx.unification_errors.len(), 1);
+        })
+    }
+
+    fn too_deep(src: &str, message: &str) {
+        with_context(|interner, _, ctx| {
+            elaborate(ctx, interner, src);
+            let errors = ctx
+                .diagnostics(interner)
+                .into_iter()
+                .filter(|d| d.level == Level::Error)
+                .collect::<Vec<_>>();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].primary.info, message);
+        })
+    }
+
+    #[test]
+    fn long_chains_are_not_too_deep() {
+        for src in &[
+            format!("val x = {}nil", "1 :: ".repeat(300)),
+            format!("val x = 1{}", " + 1".repeat(300)),
+            format!("val x = {}0", "if true then 1 else ".repeat(300)),
+        ] {
+            with_context(|interner, _, ctx| {
+                elaborate(ctx, interner, src);
+                assert!(ctx.diagnostics(interner).is_empty());
+            })
+        }
+    }
+
+    // A flat chain of infix operators or type constructors parses fine, but
+    // elaborates to something deeply nested
+
+    #[test]
+    fn deeply_nested_expr_is_an_error() {
+        let src = format!("val x = {}nil", "1 :: ".repeat(2_000));
+        too_deep(&src, "expression nesting too deep");
+    }
+
+    #[test]
+    fn deeply_nested_pat_is_an_error() {
+        let src = format!("val {}nil = []", "_ :: ".repeat(2_000));
+        too_deep(&src, "pattern nesting too deep");
+    }
+
+    #[test]
+    fn deeply_nested_type_is_an_error() {
+        let src = format!("val x : int{} = []", " list".repeat(2_000));
+        too_deep(&src, "type nesting too deep");
+    }
+
+    #[test]
+    fn dump_env_lists_types_and_values() {
+        with_context(|interner, _, ctx| {
+            elaborate(
+                ctx,
+                interner,
+                "datatype 'a tree = Leaf | Node of 'a tree * 'a * 'a tree; val leaf = Leaf",
+            );
+            assert!(ctx.diagnostics(interner).is_empty());
+
+            let dump = ctx.dump_env(interner);
+            assert!(dump.contains("  type tree: datatype, arity 1\n"));
+            assert!(dump.contains("  val Node: 'a tree * 'a * 'a tree -> 'a tree, con\n"));
+            assert!(dump.contains("  val leaf: 'a tree, var\n"));
+            // Builtins live in the same top-level namespace
+            assert!(dump.contains("  type int: tycon, arity 0\n"));
+        })
+    }
+
+    #[test]
+    fn datatype_constructors_in_tag_order() {
+        with_context(|interner, _, ctx| {
+            elaborate(ctx, interner, "datatype t = C | A of int | B");
+            assert!(ctx.diagnostics(interner).is_empty());
+
+            let tystr = ctx.lookup_type(&interner.intern("t")).unwrap();
+            let cons = tystr
+                .constructors()
+                .iter()
+                .map(|c| (interner.get(c.name).unwrap(), c.con.tag, c.con.type_arity))
+                .collect::<Vec<_>>();
+            assert_eq!(cons, vec![("C", 0, 3), ("A", 1, 3), ("B", 2, 3)]);
+        })
+    }
+
+    #[test]
+    fn top_level_expr_binds_it() {
+        with_context(|interner, _, ctx| {
+            let expr = Parser::new("1 + 2", interner).parse_expr().unwrap();
+            let decls = ctx.elaborate_top_expr(&expr);
+            assert!(ctx.diagnostics(interner).is_empty());
+            assert_eq!(decls.len(), 1);
+            assert_eq!(ctx.program().len(), 1);
+
+            match ctx.type_of(interner, "it") {
+                Some(Scheme::Mono(ty)) => assert!(*ty == *ctx.arena.types.int()),
+                _ => panic!("expected `it` to be bound to an int"),
+            }
+        })
+    }
+
+    #[test]
+    fn strict_overloads_are_ambiguous() {
+        with_context(|interner, _, ctx| {
+            ctx.overload_default_int = false;
+
+            elaborate(ctx, interner, "val f = fn x => x + x");
+            let diags = ctx.diagnostics(interner);
+            assert_eq!(diags.len(), 1);
+            assert!(diags[0].primary.info.contains("candidate types are `int`"));
+
+            // A use whose type is fixed by the rest of the declaration is fine
+            elaborate(ctx, interner, "val g = fn x => x < \"s\"");
+            assert!(ctx.diagnostics(interner).is_empty());
+        })
+    }
+
+    #[test]
+    fn environment_tyvars_are_not_generalized() {
+        with_context(|interner, arena, ctx| {
+            // Even if its rank were too high, a type variable that is free in
+            // the environment isn't quantified
+            let tv = arena.types.fresh_var(ctx.tyvar_rank + 1);
+            let x = interner.intern("x");
+            ctx.define_value(x, Span::dummy(), Scheme::Mono(tv), IdStatus::Var);
+            assert!(ctx.env_free_tyvars().contains(&tv.as_tyvar().id));
+            match ctx.generalize(
+                arena
+                    .types
+                    .arrow(tv, arena.types.fresh_var(ctx.tyvar_rank + 1)),
+            ) {
+                Scheme::Poly(vars, _) => assert_eq!(vars.len(), 1),
+                Scheme::Mono(_) => panic!("expected the unconstrained result to be generalized"),
+            }
+
+            // `g` is generalized over the type of its own argument `y`, so it can
+            // be applied to both an `int` and a `bool`. The type of `x` is free in
+            // the environment of `g`, so it stays monomorphic there, and `f` is
+            // only quantified over that one variable
+            let src = "val f = fn x => let val g = fn y => (x, y) in (g 1, g true) end";
+            elaborate(ctx, interner, src);
+            assert!(ctx.diagnostics(interner).is_empty());
+            match ctx.lookup_value(&interner.intern("f")) {
+                Some((Scheme::Poly(vars, _), _)) => assert_eq!(vars.len(), 1),
+                _ => panic!("expected `f` to be polymorphic in one type variable"),
+            }
+        })
+    }
+
+    #[test]
+    fn rollback_discards_definitions() {
+        with_context(|interner, _, ctx| {
+            elaborate(ctx, interner, "val x = 1");
+            let x = interner.intern("x");
+            let y = interner.intern("y");
+
+            let checkpoint = ctx.checkpoint();
+            elaborate(ctx, interner, "val x = true val y = x datatype t = A");
+            assert!(ctx.lookup_value(&y).is_some());
+            assert!(ctx.lookup_type(&interner.intern("t")).is_some());
+
+            ctx.rollback(checkpoint);
+            assert!(ctx.lookup_value(&y).is_none());
+            assert!(ctx.lookup_value(&interner.intern("A")).is_none());
+            assert!(ctx.lookup_type(&interner.intern("t")).is_none());
+            assert_eq!(ctx.program().len(), 1);
+            match ctx.lookup_value(&x) {
+                Some((Scheme::Mono(ty), _)) => assert!(**ty == *ctx.arena.types.int()),
+                _ => panic!("expected `x` to be bound to an int"),
+            }
+        })
+    }
+
+    #[test]
+    fn rollback_discards_overloads() {
+        with_context(|interner, _, ctx| {
+            ctx.overload_default_int = false;
+
+            // Overloads are only resolved at the end of a declaration, so
+            // those of a speculatively elaborated expression are still pending
+            let checkpoint = ctx.checkpoint();
+            let expr = Parser::new("fn x => x + x", interner)
+                .parse_expr()
+                .unwrap();
+            ctx.elaborate_expr(&expr);
+            ctx.rollback(checkpoint);
+
+            elaborate(ctx, interner, "val a = 1");
+            assert!(ctx.diagnostics(interner).is_empty());
+        })
+    }
+
+    #[test]
+    fn where_type_refines_abstract_type() {
+        with_context(|interner, _, ctx| {
+            let src = "signature S = sig type t type u end where type t = int";
+            elaborate(ctx, interner, src);
+            assert!(ctx.diagnostics(interner).is_empty());
+
+            let ns = &ctx.namespaces[ctx.signatures[&interner.intern("S")]];
+            let t = ns.types[&interner.intern("t")];
+            let u = ns.types[&interner.intern("u")];
+            match &ctx.types[t.0 as usize] {
+                TypeStructure::Scheme(Scheme::Mono(Type::Con(con, _))) => {
+                    assert_eq!(*con, crate::builtin::tycons::T_INT)
+                }
+                _ => panic!("expected `t` to be refined to `int`"),
+            }
+            assert!(matches!(ctx.types[u.0 as usize], TypeStructure::Tycon(_)));
+        })
+    }
+
+    #[test]
+    fn sharing_type_identifies_abstract_types() {
+        with_context(|interner, _, ctx| {
+            let src = "signature S = sig type t type u sharing type t = u end where type t = int";
+            elaborate(ctx, interner, src);
+            assert!(ctx.diagnostics(interner).is_empty());
+
+            // Refining `t` also refines `u`, since they are now the same type
+            let ns = &ctx.namespaces[ctx.signatures[&interner.intern("S")]];
+            let t = ns.types[&interner.intern("t")];
+            let u = ns.types[&interner.intern("u")];
+            assert_eq!(t, u);
+            match &ctx.types[u.0 as usize] {
+                TypeStructure::Scheme(Scheme::Mono(Type::Con(con, _))) => {
+                    assert_eq!(*con, crate::builtin::tycons::T_INT)
+                }
+                _ => panic!("expected `u` to be refined to `int`"),
+            }
+        })
+    }
+
+    #[test]
+    fn program_skips_decls_with_errors() {
+        with_context(|interner, _, ctx| {
+            elaborate(ctx, interner, "val a = 1 true");
+            assert!(ctx.program().is_empty());
+        })
+    }
+
+    #[test]
+    fn program_keeps_decls_with_warnings() {
+        with_context(|interner, _, ctx| {
+            elaborate(ctx, interner, "val SOME a = SOME 1");
+            let diags = ctx.diagnostics(interner);
+            assert_eq!(diags.len(), 1);
+            assert!(matches!(diags[0].level, Level::Warn));
+            assert_eq!(ctx.program().len(), 1);
+        })
+    }
+
+    #[test]
+    fn constructor_args_are_instantiated() {
+        with_context(|interner, arena, ctx| {
+            let int = arena.types.int();
+            let bool = arena.types.bool();
+            let option = ctx.lookup_type(&S_OPTION).unwrap();
+            let some = option
+                .constructor_arg(&arena.types, S_SOME, &[int])
+                .unwrap()
+                .unwrap();
+            assert!(*some == *int);
+            assert!(option
+                .constructor_arg(&arena.types, S_NONE, &[int])
+                .unwrap()
+                .is_none());
+            assert!(option
+                .constructor_arg(&arena.types, S_SOME, &[int, int])
+                .is_err());
+
+            let src = "datatype ('a, 'b) either = Left of 'a | Right of 'b * 'a | Neither";
+            elaborate(ctx, interner, src);
+            let either = ctx.lookup_type(&interner.intern("either")).unwrap();
+            assert!(either.constructor_args(&arena.types, &[int]).is_err());
+            let args = either.constructor_args(&arena.types, &[int, bool]).unwrap();
+            assert_eq!(args.len(), 3);
+            assert!(*args[0].1.unwrap() == *int);
+            assert!(*args[1].1.unwrap() == *arena.types.tuple(vec![bool, int]));
+            assert!(args[2].1.is_none());
+        })
+    }
+
+    #[test]
+    fn inexhaustive_match_raises_match() {
+        use crate::builtin::constructors::C_MATCH;
+        use crate::visit::{walk_expr, Visitor};
+
+        /// The exceptions raised by the wildcard default arms of `case`s
+        struct Defaults(Vec<Constructor>);
+        impl<'a> Visitor<'a> for Defaults {
+            fn visit_expr(&mut self, expr: &Expr<'a>) {
+                if let ExprKind::Case(_, rules) = expr.kind {
+                    if let Some(rule) = rules.last() {
+                        if let (PatKind::Wild, ExprKind::Raise(exn)) =
+                            (rule.pat.kind, rule.expr.kind)
+                        {
+                            if let ExprKind::Con(con, _) = exn.kind {
+                                self.0.push(*con);
+                            }
+                        }
+                    }
+                }
+                walk_expr(self, expr)
+            }
+        }
+
+        with_context(|interner, arena, ctx| {
+            for name in &["Match", "Bind"] {
+                match ctx.lookup_value(&interner.intern(name)) {
+                    Some((Scheme::Mono(ty), IdStatus::Exn(_))) => {
+                        assert!(**ty == *arena.types.exn())
+                    }
+                    _ => panic!("expected `{}` to be an exception", name),
+                }
+            }
+
+            let src = "val f = fn x => case x of SOME y => y end";
+            let mut defaults = Defaults(Vec::new());
+            for decl in &elaborate(ctx, interner, src) {
+                defaults.visit_decl(decl);
+            }
+            assert_eq!(defaults.0, vec![C_MATCH]);
+            assert_eq!(ctx.diagnostics(interner).len(), 1);
+
+            // An unmatched exception is raised again by a handler
+            let src = "fun g x = x handle Match => 0";
+            let mut defaults = Defaults(Vec::new());
+            for decl in &elaborate(ctx, interner, src) {
+                defaults.visit_decl(decl);
+            }
+            assert!(defaults.0.is_empty());
+        })
+    }
+}
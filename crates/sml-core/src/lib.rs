@@ -20,7 +20,7 @@ use sml_util::interner::Symbol;
 use sml_util::span::Span;
 use sml_util::Const;
 use std::collections::HashMap;
-use types::{Constructor, Scheme, Tycon, Type, TypeVar};
+use types::{Constructor, Overload, Scheme, Tycon, Type, TypeVar};
 
 pub mod alpha;
 pub mod arenas;
@@ -29,7 +29,13 @@ pub mod check;
 pub mod core_pp;
 pub mod elaborate;
 pub mod match_compile;
+pub mod mono;
+pub mod pretty;
 pub mod types;
+pub mod visit;
+
+#[cfg(test)]
+mod testing;
 
 pub type Var<'a> = (Symbol, &'a Type<'a>);
 
@@ -54,6 +60,7 @@ pub enum ExprKind<'ar> {
     Record(Vec<Row<Expr<'ar>>>),
     Seq(Vec<Expr<'ar>>),
     Var(Symbol),
+    Vector(Vec<Expr<'ar>>),
 }
 
 #[derive(Copy, Clone)]
@@ -75,10 +82,15 @@ pub enum PatKind<'ar> {
     App(Constructor, Option<Pat<'ar>>),
     /// Constant
     Const(Const),
+    /// Layered pattern, binding a variable to the value matched by the
+    /// inner pattern
+    Layer(Symbol, Pat<'ar>),
     /// Record
     Record(SortedRecord<Pat<'ar>>),
     /// Variable binding
     Var(Symbol),
+    /// Fixed-length vector
+    Vector(Vec<Pat<'ar>>),
     /// Wildcard
     Wild,
 }
@@ -94,6 +106,7 @@ pub struct Pat<'ar> {
 pub struct Rule<'ar> {
     pub pat: Pat<'ar>,
     pub expr: Expr<'ar>,
+    pub span: Span,
 }
 
 #[derive(Clone)]
@@ -103,6 +116,7 @@ pub struct Datatype<'ar> {
     pub constructors: Vec<(Constructor, Option<&'ar Type<'ar>>)>,
 }
 
+#[derive(Clone)]
 pub enum Decl<'ar> {
     Datatype(Vec<Datatype<'ar>>),
     Fun(Vec<usize>, Vec<(Symbol, Lambda<'ar>)>),
@@ -136,7 +150,9 @@ impl<'ar> Expr<'ar> {
             ExprKind::Var(_) => true,
             ExprKind::Primitive(_) => true,
             ExprKind::Record(rec) => rec.iter().all(|r| r.data.non_expansive()),
-            ExprKind::List(exprs) => exprs.iter().all(|r| r.non_expansive()),
+            ExprKind::List(exprs) | ExprKind::Vector(exprs) => {
+                exprs.iter().all(|r| r.non_expansive())
+            }
             _ => false,
         }
     }
@@ -159,14 +175,25 @@ impl<'ar> Pat<'ar> {
             true
         } else {
             match &self.kind {
-                PatKind::App(_, Some(p)) => p.flexible(),
+                PatKind::App(_, Some(p)) | PatKind::Layer(_, p) => p.flexible(),
                 PatKind::Record(rows) => rows.iter().any(|r| r.data.flexible()),
+                PatKind::Vector(pats) => pats.iter().any(|p| p.flexible()),
                 _ => false,
             }
         }
     }
 }
 
+/// Returns true if `rows` are labeled exactly `1` through `n`, in order, and
+/// can therefore be displayed as a tuple. Like SML, `{1 = x}` is not a tuple
+pub fn is_tuple<T>(rows: &[Row<T>]) -> bool {
+    rows.len() > 1
+        && rows
+            .iter()
+            .enumerate()
+            .all(|(idx, row)| row.label == Symbol::tuple_field(idx as u32 + 1))
+}
+
 impl<T> Row<T> {
     pub fn fmap<S, F: FnOnce(&T) -> S>(&self, f: F) -> Row<S> {
         Row {
@@ -18,11 +18,14 @@ use crate::elaborate::{Context, ElabError, ErrorKind};
 use crate::types::{Constructor, Type};
 use crate::{Decl, Expr, ExprKind, Lambda, Pat, PatKind, Row, Rule, SortedRecord, Var};
 use sml_util::diagnostics::Level;
-use sml_util::interner::Symbol;
+use sml_util::interner::{Symbol, S_EXN};
 use sml_util::span::Span;
 use sml_util::Const;
 use std::collections::{HashMap, HashSet, VecDeque};
 
+/// Compile a `case` expression. The scrutinee is bound to a fresh variable
+/// before the match is compiled, so it is evaluated exactly once no matter how
+/// many of the decision tree's tests inspect it
 pub fn case<'a>(
     ctx: &mut Context<'a>,
     scrutinee: Expr<'a>,
@@ -30,10 +33,36 @@ pub fn case<'a>(
     rules: Vec<Rule<'a>>,
     span: Span,
 ) -> Expr<'a> {
-    let test = ctx.fresh_var();
-    let pats = rules.iter().map(|r| vec![r.pat]).collect();
+    let diags = MatchDiags::with_capacity(span, rules.len(), C_MATCH);
+    case_with(ctx, scrutinee, ret_ty, rules, diags)
+}
 
+/// Compile the rules of a `handle` expression. Exceptions that none of the
+/// rules match are raised again, rather than raising `Match`, so a handler
+/// is never reported as inexhaustive
+pub fn handler<'a>(
+    ctx: &mut Context<'a>,
+    scrutinee: Expr<'a>,
+    ret_ty: &'a Type<'a>,
+    rules: Vec<Rule<'a>>,
+    span: Span,
+) -> Expr<'a> {
     let mut diags = MatchDiags::with_capacity(span, rules.len(), C_MATCH);
+    diags.reraise = true;
+    case_with(ctx, scrutinee, ret_ty, rules, diags)
+}
+
+fn case_with<'a>(
+    ctx: &mut Context<'a>,
+    scrutinee: Expr<'a>,
+    ret_ty: &'a Type<'a>,
+    rules: Vec<Rule<'a>>,
+    mut diags: MatchDiags,
+) -> Expr<'a> {
+    let test = ctx.fresh_var();
+    let pats = rules.iter().map(|r| vec![r.pat.unlayer()]).collect();
+    let span = diags.span;
+
     let (mut decls, rules) = preflight(ctx, rules, &mut diags);
 
     let tyvars = scrutinee.ty.ftv_rank(ctx.tyvar_rank + 1);
@@ -42,6 +71,7 @@ pub fn case<'a>(
         Rule {
             pat: ctx.arena.pat_var(test, scrutinee.ty),
             expr: scrutinee,
+            span: scrutinee.span,
         },
     ));
     let mut mat = Matrix {
@@ -75,6 +105,10 @@ pub fn fun<'a>(
     let test = ctx.fresh_var();
     let mut diags = MatchDiags::with_capacity(span, rules.len(), C_MATCH);
     let (decls, rules) = preflight(ctx, rules, &mut diags);
+    let pats = pats
+        .into_iter()
+        .map(|row| row.into_iter().map(Pat::unlayer).collect())
+        .collect();
 
     let rec = SortedRecord::new_unchecked(
         vars.iter()
@@ -167,9 +201,17 @@ pub fn val<'a>(
         }
     };
 
-    let pats = vec![vec![pat]];
+    let pats = vec![vec![pat.unlayer()]];
     let mut diags = MatchDiags::with_capacity(span, 1, C_BIND);
-    let (mut decls, rules) = preflight(ctx, vec![Rule { pat, expr: rexpr }], &mut diags);
+    let (mut decls, rules) = preflight(
+        ctx,
+        vec![Rule {
+            pat,
+            expr: rexpr,
+            span,
+        }],
+        &mut diags,
+    );
 
     let tyvars = scrutinee.ty.ftv_rank(ctx.tyvar_rank + 1);
     decls.push(Decl::Val(
@@ -177,6 +219,7 @@ pub fn val<'a>(
         Rule {
             pat: ctx.arena.pat_var(test, scrutinee.ty),
             expr: scrutinee,
+            span: scrutinee.span,
         },
     ));
     let mut mat = Matrix {
@@ -199,7 +242,11 @@ pub fn val<'a>(
         expr.span,
     );
 
-    Rule { pat: rpat, expr }
+    Rule {
+        pat: rpat,
+        expr,
+        span,
+    }
 }
 
 /// Abstract match bodies into functions, to be declared prior to the
@@ -230,7 +277,7 @@ fn preflight<'a>(
 ) -> (Vec<Decl<'a>>, Vec<Rule<'a>>) {
     let mut finished = Vec::new();
     let mut decls = Vec::new();
-    for Rule { pat, expr } in rules {
+    for Rule { pat, expr, span } in rules {
         let vars = collect_vars(pat);
         let arg = ctx.fresh_var();
 
@@ -241,7 +288,14 @@ fn preflight<'a>(
                 let pat = ctx.arena.pat_var(var, ty);
                 let ex = ctx.arena.expr_var(arg, ty);
                 let tyvars = ty.ftv_rank(ctx.tyvar_rank + 1);
-                let decl = Decl::Val(tyvars, Rule { pat, expr: ex });
+                let decl = Decl::Val(
+                    tyvars,
+                    Rule {
+                        pat,
+                        expr: ex,
+                        span: Span::dummy(),
+                    },
+                );
                 (
                     Expr::new(
                         ctx.arena.exprs.alloc(ExprKind::Let(vec![decl], expr)),
@@ -271,9 +325,10 @@ fn preflight<'a>(
         finished.push(Rule {
             pat,
             expr: ctx.arena.expr_var(name, ty),
+            span,
         });
 
-        diags.renamed.push((expr.span, name));
+        diags.renamed.push((span, name));
     }
     (decls, finished)
 }
@@ -282,6 +337,7 @@ fn preflight<'a>(
 pub enum Fact {
     Con(Constructor, Option<Symbol>),
     Record(SortedRecord<Symbol>),
+    Vector(Vec<Symbol>),
 }
 
 #[derive(Default, Clone)]
@@ -311,6 +367,12 @@ impl Facts {
                         panic!("Bug: Facts.bind rebinding")
                     }
                 }
+                PatKind::Layer(x, pat) => {
+                    if map.insert(*x, (*var, pat.ty)).is_some() {
+                        panic!("Bug: Facts.bind rebinding")
+                    }
+                    queue.push_back((var, pat));
+                }
                 PatKind::App(_, Some(pat)) => match facts.get(var) {
                     Some(Fact::Con(_, Some(x))) => {
                         queue.push_back((x, pat));
@@ -319,12 +381,25 @@ impl Facts {
                 },
                 PatKind::Record(rp) => match facts.get(var) {
                     Some(Fact::Record(rx)) => {
-                        for (rp, rx) in rp.iter().zip(rx.iter()) {
-                            queue.push_back((&rx.data, &rp.data));
+                        // Flexible record patterns may only mention some of
+                        // the fields, so match them up by label
+                        for rp in rp.iter() {
+                            match rx.contains(&rp.label) {
+                                Some(rx) => queue.push_back((&rx.data, &rp.data)),
+                                None => panic!("Bug: Facts.bind record label"),
+                            }
                         }
                     }
                     _ => panic!("Bug: Facts.bind record"),
                 },
+                PatKind::Vector(ps) => match facts.get(var) {
+                    Some(Fact::Vector(xs)) => {
+                        for (p, x) in ps.iter().zip(xs.iter()) {
+                            queue.push_back((x, p));
+                        }
+                    }
+                    _ => panic!("Bug: Facts.bind vector"),
+                },
                 _ => continue,
             }
         }
@@ -342,6 +417,9 @@ pub struct MatchDiags {
     constr: Constructor,
     // Did we emit a `raise Match`?
     inexhaustive: bool,
+    // Should an unmatched value be raised again, as in a handler, rather than
+    // raising `constr`?
+    reraise: bool,
 }
 
 impl MatchDiags {
@@ -352,6 +430,7 @@ impl MatchDiags {
             reached: HashSet::with_capacity(capacity),
             constr,
             inexhaustive: false,
+            reraise: false,
         }
     }
 
@@ -409,6 +488,17 @@ impl<'a> Pat<'a> {
             _ => false,
         }
     }
+
+    /// Remove any layered patterns wrapping this one. Variables bound by a
+    /// layered pattern are recovered from the original rule by
+    /// [`Facts::bind`], so the matrix only needs to see the inner pattern
+    fn unlayer(self) -> Pat<'a> {
+        let mut pat = self;
+        while let PatKind::Layer(_, inner) = pat.kind {
+            pat = *inner;
+        }
+        pat
+    }
 }
 
 impl<'a, 'ctx> Matrix<'a, 'ctx> {
@@ -432,11 +522,15 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
     /// Deconstruct a record or tuple pattern, binding each field to a fresh
     /// variable, and flattening all of the record patterns in the first column
     /// [{a, b, c}, ...] --> [a, b, c, ...]
+    ///
+    /// `fields` are the fields of the record type, rather than of any single
+    /// pattern, since flexible record patterns `{a, ...}` in the same column
+    /// may each mention a different subset of the fields
     fn record_rule(
         &self,
         facts: &mut Facts,
         diags: &mut MatchDiags,
-        fields: &SortedRecord<Pat<'a>>,
+        fields: &SortedRecord<&'a Type<'a>>,
     ) -> Expr<'a> {
         // This part is a little tricky. We need to generate a fresh variable
         // for every field in the pattern
@@ -450,9 +544,9 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
         for (idx, row) in fields.iter().enumerate() {
             let fresh = self.ctx.fresh_var();
             fact_r.push(row.fmap(|_| fresh));
-            record.push((row.label, fresh, row.data.ty));
+            record.push((row.label, fresh, row.data));
             // insert the new variables into the matrice's variable
-            vars.insert(idx, (fresh, row.data.ty));
+            vars.insert(idx, (fresh, row.data));
         }
 
         facts.add(
@@ -466,13 +560,17 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
 
             match &row[0].kind {
                 PatKind::Record(bound) => {
-                    for (idx, row) in bound.iter().enumerate() {
-                        new_row.insert(idx, row.data);
+                    for (idx, row) in fields.iter().enumerate() {
+                        let pat = match bound.contains(&row.label) {
+                            Some(b) => b.data.unlayer(),
+                            None => self.mk_wild(row.data),
+                        };
+                        new_row.insert(idx, pat);
                     }
                 }
                 PatKind::Var(_) | PatKind::Wild => {
                     for (idx, row) in fields.iter().enumerate() {
-                        new_row.insert(idx, self.mk_wild(row.data.ty));
+                        new_row.insert(idx, self.mk_wild(row.data));
                     }
                 }
                 _ => continue,
@@ -505,7 +603,7 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
             let mut new_row: Vec<Pat> = row.iter().skip(1).copied().collect();
             match &row[0].kind {
                 PatKind::App(con, Some(arg)) if con == head => {
-                    new_row.insert(0, *arg);
+                    new_row.insert(0, arg.unlayer());
                 }
                 PatKind::App(con, None) if con == head => {}
                 PatKind::Wild | PatKind::Var(_) => {
@@ -537,16 +635,22 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
         // Generate the set of constructors appearing in the column
         let mut set = HashMap::new();
         let mut type_arity = 0;
+        let mut ty_is_closed = true;
         for row in &self.pats {
             if let PatKind::App(con, p) = &row[0].kind {
                 set.insert(con, p.map(|p| p.ty));
                 type_arity = con.type_arity;
+                ty_is_closed = con.tycon != S_EXN;
             }
         }
         let mut set = set.into_iter().collect::<Vec<_>>();
         set.sort_by(|a, b| a.0.name.cmp(&b.0.name));
 
-        let exhaustive = set.len() == type_arity as usize;
+        // New exceptions can always be declared, so a match on them is never
+        // exhaustive. A type with a single constructor, like `ref`, is always
+        // covered here, leaving it to the argument patterns in the
+        // specialized matrix
+        let exhaustive = set.len() == type_arity as usize && ty_is_closed;
         let mut rules = Vec::new();
         for (con, arg_ty) in set {
             let fresh = self.ctx.fresh_var();
@@ -562,14 +666,22 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
                 self.pats[0][0].ty,
                 Span::dummy(),
             );
-            rules.push(Rule { pat, expr });
+            rules.push(Rule {
+                pat,
+                expr,
+                span: Span::dummy(),
+            });
         }
 
         // If we don't have an exhaustive match, generate a default matrix
         if !exhaustive {
             let pat = self.mk_wild(self.pats[0][0].ty);
             let expr = self.default_matrix(facts, diags);
-            rules.push(Rule { pat, expr });
+            rules.push(Rule {
+                pat,
+                expr,
+                span: Span::dummy(),
+            });
         }
 
         Expr::new(
@@ -607,9 +719,10 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
         mat.compile(facts, diags)
     }
 
-    /// Generate a case expression for the data constructors in the first column
+    /// Generate a case expression with one branch per distinct constant in the
+    /// first column, in ascending order, and a default branch for the rest
     fn const_rule(&self, facts: &mut Facts, diags: &mut MatchDiags) -> Expr<'a> {
-        // Generate the set of constructors appearing in the column
+        // Generate the set of constants appearing in the column
         let mut set = HashSet::new();
         for row in &self.pats {
             match &row[0].kind {
@@ -620,7 +733,7 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
             }
         }
         let mut set = set.into_iter().collect::<Vec<_>>();
-        set.sort_by(|a, b| a.cmp(&b));
+        set.sort();
 
         let mut rules = Vec::new();
         for &con in &set {
@@ -633,7 +746,11 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
                 self.pats[0][0].ty,
                 Span::dummy(),
             );
-            rules.push(Rule { pat, expr });
+            rules.push(Rule {
+                pat,
+                expr,
+                span: Span::dummy(),
+            });
         }
 
         if set.len() == 1 && set[0] == &Const::Unit {
@@ -641,9 +758,110 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
         } else {
             let pat = self.mk_wild(self.pats[0][0].ty);
             let expr = self.default_matrix(facts, diags);
-            rules.push(Rule { pat, expr });
+            rules.push(Rule {
+                pat,
+                expr,
+                span: Span::dummy(),
+            });
+        }
+
+        Expr::new(
+            self.ctx
+                .arena
+                .exprs
+                .alloc(ExprKind::Case(self.vars[0], rules)),
+            self.ret_ty,
+            Span::dummy(),
+        )
+    }
+
+    /// Select all of the rows in the first column that will match a vector
+    /// of length `vars.len()`, flattening the elements of the vector patterns
+    /// into new columns bound to `vars`
+    fn specialize_vector(
+        &self,
+        facts: &mut Facts,
+        diags: &mut MatchDiags,
+        vars: &[Var<'a>],
+    ) -> Expr<'a> {
+        let mut mat = self.shallow();
+        for (idx, row) in self.pats.iter().enumerate() {
+            let mut new_row: Vec<Pat> = row.iter().skip(1).copied().collect();
+            match &row[0].kind {
+                PatKind::Vector(pats) if pats.len() == vars.len() => {
+                    for (idx, pat) in pats.iter().enumerate() {
+                        new_row.insert(idx, pat.unlayer());
+                    }
+                }
+                PatKind::Wild | PatKind::Var(_) => {
+                    for (idx, (_, ty)) in vars.iter().enumerate() {
+                        new_row.insert(idx, self.mk_wild(ty));
+                    }
+                }
+                _ => continue,
+            }
+            mat.rules.push(self.rules[idx]);
+            mat.pats.push(new_row);
         }
 
+        facts.add(
+            mat.vars[0].0,
+            Fact::Vector(vars.iter().map(|(s, _)| *s).collect()),
+        );
+        mat.vars.remove(0);
+        for (idx, var) in vars.iter().enumerate() {
+            mat.vars.insert(idx, *var);
+        }
+        mat.compile(facts, diags)
+    }
+
+    /// Generate a case expression for the vector patterns in the first column,
+    /// with one arm for each distinct vector length. There are vectors of
+    /// every length, so a default matrix is always generated
+    fn vector_rule(&self, facts: &mut Facts, diags: &mut MatchDiags) -> Expr<'a> {
+        let mut set = HashMap::new();
+        for row in &self.pats {
+            if let PatKind::Vector(pats) = &row[0].kind {
+                set.entry(pats.len())
+                    .or_insert_with(|| pats.iter().map(|p| p.ty).collect::<Vec<_>>());
+            }
+        }
+        let mut set = set.into_iter().collect::<Vec<_>>();
+        set.sort_by_key(|(len, _)| *len);
+
+        let mut rules = Vec::new();
+        for (_, tys) in set {
+            let vars = tys
+                .into_iter()
+                .map(|ty| (self.ctx.fresh_var(), ty))
+                .collect::<Vec<_>>();
+            let mut f = facts.clone();
+            let expr = self.specialize_vector(&mut f, diags, &vars);
+
+            let pats = vars
+                .iter()
+                .map(|(sym, ty)| self.ctx.arena.pat_var(*sym, ty))
+                .collect();
+            let pat = Pat::new(
+                self.ctx.arena.pats.alloc(PatKind::Vector(pats)),
+                self.pats[0][0].ty,
+                Span::dummy(),
+            );
+            rules.push(Rule {
+                pat,
+                expr,
+                span: Span::dummy(),
+            });
+        }
+
+        let pat = self.mk_wild(self.pats[0][0].ty);
+        let expr = self.default_matrix(facts, diags);
+        rules.push(Rule {
+            pat,
+            expr,
+            span: Span::dummy(),
+        });
+
         Expr::new(
             self.ctx
                 .arena
@@ -671,13 +889,23 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
 
     /// Compile a [`Matrix`] into a source-level expression
     fn compile(&mut self, facts: &mut Facts, diags: &mut MatchDiags) -> Expr<'a> {
-        if self.pats.is_empty() {
+        if self.pats.is_empty() && diags.reraise {
+            let exn = self.ctx.arena.types.exn();
+            Expr::new(
+                self.ctx
+                    .arena
+                    .exprs
+                    .alloc(ExprKind::Raise(self.ctx.arena.expr_var(self.test, exn))),
+                self.ret_ty,
+                Span::zero(),
+            )
+        } else if self.pats.is_empty() {
             let matchh = Expr::new(
                 self.ctx
                     .arena
                     .exprs
                     .alloc(ExprKind::Con(diags.constr, vec![])),
-                self.ret_ty,
+                self.ctx.arena.types.exn(),
                 Span::zero(),
             );
 
@@ -728,10 +956,20 @@ impl<'a, 'ctx> Matrix<'a, 'ctx> {
             // There is at least one non-wild pattern in the matrix somewhere
             for row in &self.pats {
                 match &row[0].kind {
-                    PatKind::Record(fields) => return self.record_rule(facts, diags, fields),
+                    PatKind::Record(fields) => {
+                        let fields = match row[0].ty.de_record() {
+                            Some(rows) => rows.clone(),
+                            None => SortedRecord::new_unchecked(
+                                fields.iter().map(|row| row.fmap(|p| p.ty)).collect(),
+                            ),
+                        };
+                        return self.record_rule(facts, diags, &fields);
+                    }
                     PatKind::App(_, _) => return self.sum_rule(facts, diags),
                     PatKind::Const(_) => return self.const_rule(facts, diags),
+                    PatKind::Vector(_) => return self.vector_rule(facts, diags),
                     PatKind::Wild | PatKind::Var(_) => continue,
+                    PatKind::Layer(_, _) => panic!("Bug: layered pattern in match matrix"),
                 }
             }
 
@@ -771,8 +1009,13 @@ fn collect_vars<'a>(pat: Pat<'a>) -> Vec<Var<'a>> {
     while let Some(pat) = queue.pop_front() {
         match pat.kind {
             PatKind::Var(s) => v.push((*s, pat.ty)),
+            PatKind::Layer(s, inner) => {
+                v.push((*s, pat.ty));
+                queue.push_back(*inner);
+            }
             PatKind::Record(fields) => queue.extend(fields.iter().map(|row| row.data)),
             PatKind::App(_, Some(pat)) => queue.push_back(*pat),
+            PatKind::Vector(pats) => queue.extend(pats.iter().copied()),
             _ => {}
         }
     }
@@ -0,0 +1,549 @@
+//! Monomorphization of Core ML
+//!
+//! Polymorphic `val` and `fun` bindings are replaced by one monomorphic copy
+//! for each distinct instantiation of their type variables that is reachable
+//! from the program. Each copy is bound to a fresh symbol, and every use of
+//! the polymorphic binding is rewritten to refer to the matching copy.
+//!
+//! Uses are discovered while the program is traversed, and specializing a
+//! binding may in turn discover new uses of other (or the same) polymorphic
+//! bindings, so each declaration list keeps a worklist of copies that have
+//! been requested but not yet built. Copies are emitted in place of the
+//! original binding, so that everything they refer to is still in scope.
+//!
+//! Type variables that are never resolved, either because they are unused
+//! by the body of a binding or because the value restriction prevented them
+//! from being generalized, are defaulted to `unit`. Polymorphic bindings that
+//! are never used are dropped: under the value restriction they cannot have
+//! any side effects.
+
+use crate::arenas::CoreArena;
+use crate::types::Type;
+use crate::visit::{fold_decl_children, fold_expr_children, fold_pat_children, Folder};
+use crate::{Decl, Expr, ExprKind, Lambda, Pat, PatKind, Row, Rule, SortedRecord};
+use sml_util::interner::Symbol;
+use std::collections::HashMap;
+
+/// Monomorphize a program, such as the one returned by
+/// [`crate::elaborate::Context::program`]
+pub fn monomorphize<'a>(arena: &'a CoreArena<'a>, decls: &[Decl<'a>]) -> Vec<Decl<'a>> {
+    let mut mono = Mono {
+        arena,
+        entries: Vec::new(),
+        env: Vec::new(),
+        subst: HashMap::new(),
+        rename: HashMap::new(),
+    };
+    mono.decls(decls, None).0
+}
+
+/// A specialized copy of a polymorphic binding
+struct Instance<'a> {
+    /// Fresh names for each of the names bound by the original declaration
+    names: Vec<Symbol>,
+    /// Monomorphic types that the binding's type variables are instantiated to
+    args: Vec<&'a Type<'a>>,
+    decl: Option<Decl<'a>>,
+}
+
+/// A polymorphic binding, and all of the instances requested so far
+struct Entry<'a> {
+    decl: Decl<'a>,
+    tyvars: Vec<usize>,
+    /// Names bound by `decl`, along with their generic types
+    names: Vec<(Symbol, &'a Type<'a>)>,
+    /// The environment and substitution in effect at the binding site
+    env: Vec<Scope>,
+    subst: HashMap<usize, &'a Type<'a>>,
+    instances: Vec<Instance<'a>>,
+    /// Index into `instances` of the instance for each list of type arguments
+    keys: HashMap<Vec<&'a Type<'a>>, usize>,
+}
+
+/// Maps each symbol bound in a scope to the [`Entry`] and index into
+/// [`Entry::names`] that it refers to, or `None` for monomorphic bindings
+type Scope = HashMap<Symbol, Option<(usize, usize)>>;
+
+struct Mono<'a> {
+    arena: &'a CoreArena<'a>,
+    entries: Vec<Entry<'a>>,
+    env: Vec<Scope>,
+    /// Instantiation of the type variables of enclosing polymorphic bindings
+    subst: HashMap<usize, &'a Type<'a>>,
+    /// Fresh names for the variables bound by the pattern currently being
+    /// specialized
+    rename: HashMap<Symbol, Symbol>,
+}
+
+enum Slot<'a> {
+    Mono(Decl<'a>),
+    Poly(usize),
+}
+
+/// Match the generic type of a binding against the type of one of its uses,
+/// recording the types that the variables in `tyvars` are instantiated to
+fn bind<'a>(
+    generic: &'a Type<'a>,
+    ty: &'a Type<'a>,
+    tyvars: &[usize],
+    map: &mut HashMap<usize, &'a Type<'a>>,
+) {
+    match (generic.resolve(), ty.resolve()) {
+        (Type::Var(tv), ty) if tyvars.contains(&tv.id) => {
+            map.entry(tv.id).or_insert(ty);
+        }
+        (Type::Con(_, xs), Type::Con(_, ys)) => {
+            for (x, y) in xs.iter().zip(ys) {
+                bind(x, y, tyvars, map);
+            }
+        }
+        (Type::Record(xs), ty) => {
+            for x in xs.iter() {
+                if let Some(y) = ty.field(x.label) {
+                    bind(x.data, y, tyvars, map);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn pat_vars<'a>(pat: &Pat<'a>, vars: &mut Vec<(Symbol, &'a Type<'a>)>) {
+    match pat.kind {
+        PatKind::App(_, Some(pat)) => pat_vars(pat, vars),
+        PatKind::Layer(sym, pat) => {
+            vars.push((*sym, pat.ty));
+            pat_vars(pat, vars)
+        }
+        PatKind::Record(rows) => rows.iter().for_each(|row| pat_vars(&row.data, vars)),
+        PatKind::Var(sym) => vars.push((*sym, pat.ty)),
+        PatKind::Vector(pats) => pats.iter().for_each(|pat| pat_vars(pat, vars)),
+        _ => {}
+    }
+}
+
+impl<'a> Mono<'a> {
+    fn bind_mono(&mut self, sym: Symbol) {
+        if let Some(scope) = self.env.last_mut() {
+            scope.insert(sym, None);
+        }
+    }
+
+    fn lookup(&self, sym: Symbol) -> Option<(usize, usize)> {
+        self.env
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&sym))
+            .copied()?
+    }
+
+    fn with_scope<T, F: FnOnce(&mut Mono<'a>) -> T>(&mut self, f: F) -> T {
+        self.env.push(Scope::new());
+        let r = f(self);
+        self.env.pop();
+        r
+    }
+
+    /// Rewrite a use of `sym` at type `ty` to refer to the matching instance
+    /// of the binding it refers to, requesting that instance if needed
+    fn var(&mut self, sym: Symbol, ty: &'a Type<'a>) -> Symbol {
+        let (id, idx) = match self.lookup(sym) {
+            Some(binding) => binding,
+            None => return sym,
+        };
+        let mut map = HashMap::new();
+        let (tyvars, generic) = (&self.entries[id].tyvars, self.entries[id].names[idx].1);
+        bind(generic, ty, tyvars, &mut map);
+
+        let args = self.entries[id]
+            .tyvars
+            .clone()
+            .into_iter()
+            .map(|v| match map.get(&v) {
+                Some(ty) => self.fold_type(ty),
+                None => self.arena.types.unit(),
+            })
+            .collect::<Vec<_>>();
+        let arena = self.arena;
+        let entry = &mut self.entries[id];
+        let inst = match entry.keys.get(&args) {
+            Some(inst) => *inst,
+            None => {
+                let names = entry
+                    .names
+                    .iter()
+                    .map(|_| arena.exprs.allocate_id())
+                    .collect();
+                entry.keys.insert(args.clone(), entry.instances.len());
+                entry.instances.push(Instance {
+                    names,
+                    args,
+                    decl: None,
+                });
+                entry.instances.len() - 1
+            }
+        };
+        entry.instances[inst].names[idx]
+    }
+
+    /// Record a polymorphic declaration, which will be specialized on demand
+    fn define(&mut self, decl: &Decl<'a>) -> usize {
+        let id = self.entries.len();
+        let (tyvars, names, recursive) = match decl {
+            Decl::Val(tyvars, rule) => {
+                let mut names = Vec::new();
+                pat_vars(&rule.pat, &mut names);
+                (tyvars, names, false)
+            }
+            Decl::Fun(tyvars, binds) => {
+                let names = binds
+                    .iter()
+                    .map(|(name, lam)| (*name, self.arena.types.arrow(lam.ty, lam.body.ty)))
+                    .collect();
+                (tyvars, names, true)
+            }
+            _ => unreachable!("only `val` and `fun` declarations can be polymorphic"),
+        };
+
+        let insert = |mono: &mut Mono<'a>, names: &[(Symbol, &'a Type<'a>)]| {
+            let scope = mono.env.last_mut().expect("BUG: Mono::define");
+            for (idx, (name, _)) in names.iter().enumerate() {
+                scope.insert(*name, Some((id, idx)));
+            }
+        };
+        if recursive {
+            insert(self, &names);
+        }
+        self.entries.push(Entry {
+            decl: decl.clone(),
+            tyvars: tyvars.clone(),
+            names: names.clone(),
+            env: self.env.clone(),
+            subst: self.subst.clone(),
+            instances: Vec::new(),
+            keys: HashMap::new(),
+        });
+        if !recursive {
+            insert(self, &names);
+        }
+        id
+    }
+
+    /// Build the declaration for an instance of a polymorphic binding
+    fn specialize(&mut self, id: usize, inst: usize) -> Decl<'a> {
+        let entry = &self.entries[id];
+        let instance = &entry.instances[inst];
+        let decl = entry.decl.clone();
+
+        let mut subst = entry.subst.clone();
+        subst.extend(
+            entry
+                .tyvars
+                .iter()
+                .copied()
+                .zip(instance.args.iter().copied()),
+        );
+        let rename = entry
+            .names
+            .iter()
+            .map(|(name, _)| *name)
+            .zip(instance.names.iter().copied())
+            .collect::<HashMap<_, _>>();
+
+        let env = entry.env.clone();
+        let env = std::mem::replace(&mut self.env, env);
+        let subst = std::mem::replace(&mut self.subst, subst);
+        let decl = match decl {
+            Decl::Val(_, rule) => {
+                let expr = self.fold_expr(&rule.expr);
+                self.rename = rename;
+                let pat = self.fold_pat(&rule.pat);
+                self.rename.clear();
+                Decl::Val(
+                    Vec::new(),
+                    Rule {
+                        pat,
+                        expr,
+                        span: rule.span,
+                    },
+                )
+            }
+            Decl::Fun(_, binds) => Decl::Fun(
+                Vec::new(),
+                binds
+                    .iter()
+                    .map(|(name, lam)| (rename[name], self.lambda(lam)))
+                    .collect(),
+            ),
+            _ => unreachable!("only `val` and `fun` declarations can be polymorphic"),
+        };
+        self.env = env;
+        self.subst = subst;
+        decl
+    }
+
+    fn lambda(&mut self, lam: &Lambda<'a>) -> Lambda<'a> {
+        self.with_scope(|mono| {
+            mono.bind_mono(lam.arg);
+            Lambda {
+                arg: lam.arg,
+                ty: mono.fold_type(lam.ty),
+                body: mono.fold_expr(&lam.body),
+            }
+        })
+    }
+
+    /// Monomorphize a list of declarations, and the body of the `let`
+    /// expression they belong to, if any
+    fn decls(
+        &mut self,
+        decls: &[Decl<'a>],
+        body: Option<&Expr<'a>>,
+    ) -> (Vec<Decl<'a>>, Option<Expr<'a>>) {
+        self.with_scope(|mono| {
+            let mut slots = Vec::with_capacity(decls.len());
+            for decl in decls {
+                match decl {
+                    Decl::Val(tyvars, _) | Decl::Fun(tyvars, _) if !tyvars.is_empty() => {
+                        slots.push(Slot::Poly(mono.define(decl)))
+                    }
+                    _ => slots.push(Slot::Mono(mono.fold_decl(decl))),
+                }
+            }
+            let body = body.map(|body| mono.fold_expr(body));
+
+            // Specializing an instance can request new instances of any of
+            // the bindings in this list, so keep going until none are left
+            let entries = slots
+                .iter()
+                .filter_map(|slot| match slot {
+                    Slot::Poly(id) => Some(*id),
+                    Slot::Mono(_) => None,
+                })
+                .collect::<Vec<_>>();
+            let mut progress = true;
+            while progress {
+                progress = false;
+                for &id in &entries {
+                    while let Some(inst) = mono.entries[id]
+                        .instances
+                        .iter()
+                        .position(|inst| inst.decl.is_none())
+                    {
+                        let decl = mono.specialize(id, inst);
+                        mono.entries[id].instances[inst].decl = Some(decl);
+                        progress = true;
+                    }
+                }
+            }
+
+            let mut out = Vec::with_capacity(slots.len());
+            for slot in slots {
+                match slot {
+                    Slot::Mono(decl) => out.push(decl),
+                    Slot::Poly(id) => out.extend(
+                        mono.entries[id]
+                            .instances
+                            .iter_mut()
+                            .filter_map(|inst| inst.decl.take()),
+                    ),
+                }
+            }
+            (out, body)
+        })
+    }
+}
+
+impl<'a> Folder<'a> for Mono<'a> {
+    fn arena(&self) -> &'a CoreArena<'a> {
+        self.arena
+    }
+
+    fn fold_decl(&mut self, decl: &Decl<'a>) -> Decl<'a> {
+        match decl {
+            Decl::Val(tyvars, rule) => {
+                let expr = self.fold_expr(&rule.expr);
+                let pat = self.fold_pat(&rule.pat);
+                Decl::Val(
+                    tyvars.clone(),
+                    Rule {
+                        pat,
+                        expr,
+                        span: rule.span,
+                    },
+                )
+            }
+            Decl::Fun(tyvars, binds) => {
+                for (name, _) in binds {
+                    self.bind_mono(*name);
+                }
+                Decl::Fun(
+                    tyvars.clone(),
+                    binds
+                        .iter()
+                        .map(|(name, lam)| (*name, self.lambda(lam)))
+                        .collect(),
+                )
+            }
+            _ => fold_decl_children(self, decl),
+        }
+    }
+
+    fn fold_expr(&mut self, expr: &Expr<'a>) -> Expr<'a> {
+        let kind = match expr.kind {
+            ExprKind::Case((var, ty), rules) => {
+                let var = self.var(*var, ty);
+                let rules = rules
+                    .iter()
+                    .map(|rule| {
+                        self.with_scope(|mono| Rule {
+                            pat: mono.fold_pat(&rule.pat),
+                            expr: mono.fold_expr(&rule.expr),
+                            span: rule.span,
+                        })
+                    })
+                    .collect();
+                ExprKind::Case((var, self.fold_type(ty)), rules)
+            }
+            ExprKind::Handle(tryy, sym, handler) => {
+                let tryy = self.fold_expr(tryy);
+                let handler = self.with_scope(|mono| {
+                    mono.bind_mono(*sym);
+                    mono.fold_expr(handler)
+                });
+                ExprKind::Handle(tryy, *sym, handler)
+            }
+            ExprKind::Lambda(lam) => ExprKind::Lambda(self.lambda(lam)),
+            ExprKind::Let(decls, body) => {
+                let (decls, body) = self.decls(decls, Some(body));
+                ExprKind::Let(decls, body.expect("BUG: Mono::fold_expr"))
+            }
+            ExprKind::Var(sym) => ExprKind::Var(self.var(*sym, expr.ty)),
+            _ => return fold_expr_children(self, expr),
+        };
+        Expr::new(
+            self.arena.exprs.alloc(kind),
+            self.fold_type(expr.ty),
+            expr.span,
+        )
+    }
+
+    fn fold_pat(&mut self, pat: &Pat<'a>) -> Pat<'a> {
+        match pat.kind {
+            PatKind::Var(sym) => {
+                self.bind_mono(*sym);
+                let sym = self.rename.get(sym).copied().unwrap_or(*sym);
+                Pat::new(
+                    self.arena.pats.alloc(PatKind::Var(sym)),
+                    self.fold_type(pat.ty),
+                    pat.span,
+                )
+            }
+            _ => fold_pat_children(self, pat),
+        }
+    }
+
+    /// Apply the current substitution, defaulting any type variables that are
+    /// left over to `unit`
+    fn fold_type(&mut self, ty: &'a Type<'a>) -> &'a Type<'a> {
+        match ty.resolve() {
+            Type::Var(tv) => match self.subst.get(&tv.id) {
+                Some(ty) => ty,
+                None => self.arena.types.unit(),
+            },
+            Type::Flex(flex) => {
+                let rows = flex
+                    .constraints
+                    .iter()
+                    .map(|row| Row {
+                        label: row.label,
+                        data: self.fold_type(row.data),
+                        span: row.span,
+                    })
+                    .collect();
+                self.arena
+                    .types
+                    .alloc(Type::Record(SortedRecord::new_unchecked(rows)))
+            }
+            ty => crate::visit::fold_type_children(self, ty),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pretty::Pretty;
+    use crate::testing::elaborate_src;
+    use sml_util::pretty_print::PrettyPrinter;
+
+    /// Monomorphize `src`, returning the pretty-printed program along with
+    /// the type of each top-level binding
+    fn mono(src: &str) -> (String, Vec<String>) {
+        elaborate_src(src, |interner, arena, decls| {
+            let decls = monomorphize(arena, decls);
+            let mut types = Vec::new();
+            for decl in &decls {
+                let ty = match decl {
+                    Decl::Val(_, rule) => rule.pat.ty,
+                    Decl::Fun(_, binds) => arena.types.arrow(binds[0].1.ty, binds[0].1.body.ty),
+                    _ => continue,
+                };
+                let mut s = String::new();
+                PrettyPrinter::new(interner)
+                    .print(ty)
+                    .write_fmt(&mut s)
+                    .unwrap();
+                types.push(s);
+            }
+            (Pretty::new(interner).decls(&decls), types)
+        })
+    }
+
+    #[test]
+    fn specialize_each_instantiation() {
+        let (src, types) = mono("val id = fn x => x val p = (id 1, id true)");
+        assert_eq!(types, vec!["int -> int", "bool -> bool", "int * bool"]);
+        assert_eq!(
+            src,
+            "val _0 = fn _1 => let fun _2 _3 = let val x = _3 in x end val _4 = _1 in _2 _4 end \
+             val _5 = fn _1 => let fun _2 _3 = let val x = _3 in x end val _4 = _1 in _2 _4 end \
+             val p = (_0 1, _5 true)"
+        );
+    }
+
+    #[test]
+    fn reuse_instances() {
+        let (_, types) = mono("fun f x = x val a = f 1 val b = f 2 val c = f \"c\"");
+        assert_eq!(
+            types,
+            vec!["int -> int", "string -> string", "int", "int", "string"]
+        );
+    }
+
+    #[test]
+    fn unused_tyvar_defaults_to_unit() {
+        let (_, types) = mono("fun f x = 1 val n = f []");
+        assert_eq!(types, vec!["unit list -> int", "int"]);
+    }
+
+    #[test]
+    fn unused_binding_is_dropped() {
+        let (src, types) = mono("val id = fn x => x val n = 1");
+        assert_eq!(types, vec!["int"]);
+        assert_eq!(src, "val n = 1");
+    }
+
+    #[test]
+    fn nested_let() {
+        let (_, types) =
+            mono("fun g x = let fun h y = (x, y) in (h 1, h true) end val c = g \"s\"");
+        assert_eq!(
+            types,
+            vec![
+                "string -> string * int * string * bool",
+                "string * int * string * bool"
+            ]
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! Render elaborated Core ML back into SML-like source text
+//!
+//! Unlike the layout-oriented printer in [`crate::core_pp`], this produces a
+//! compact, single-line rendering with only the parentheses required to
+//! preserve the structure of the expression. Compiler-generated symbols
+//! ([`Symbol::Gensym`]) are renamed to `_0`, `_1`, ... in order of first
+//! appearance, so that the output does not depend on how many fresh variables
+//! were allocated before the printed term, and is stable enough to compare
+//! against in tests.
+
+use crate::{Decl, Expr, ExprKind, Pat, PatKind, Row, Rule};
+use sml_util::interner::{Interner, Symbol};
+use sml_util::Const;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Precedence levels used to decide when a subterm must be parenthesized
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+enum Prec {
+    /// `fn`, `case`, `raise`, `handle`: extend as far to the right as possible
+    Top,
+    /// Function or constructor application
+    App,
+    /// Atomic expressions and patterns
+    Atom,
+}
+
+/// Escape `s` for use inside an SML string or character literal
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_ascii_control() => {
+                let _ = write!(out, "\\{:03}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub struct Pretty<'i> {
+    interner: &'i Interner,
+    gensyms: HashMap<u32, usize>,
+    out: String,
+}
+
+impl<'i> Pretty<'i> {
+    pub fn new(interner: &'i Interner) -> Pretty<'i> {
+        Pretty {
+            interner,
+            gensyms: HashMap::new(),
+            out: String::new(),
+        }
+    }
+
+    /// Render an expression, returning the accumulated output
+    pub fn expr(mut self, expr: &Expr<'_>) -> String {
+        self.print_expr(expr, Prec::Top);
+        self.out
+    }
+
+    /// Render a sequence of declarations, separated by spaces
+    pub fn decls(mut self, decls: &[Decl<'_>]) -> String {
+        self.print_decls(decls);
+        self.out
+    }
+
+    fn symbol(&mut self, sym: Symbol) {
+        match sym {
+            Symbol::Gensym(n) => {
+                let next = self.gensyms.len();
+                let id = *self.gensyms.entry(n).or_insert(next);
+                let _ = write!(self.out, "_{}", id);
+            }
+            Symbol::Tuple(n) => {
+                let _ = write!(self.out, "{}", n);
+            }
+            _ => {
+                let s = self.interner.get(sym).unwrap_or("?");
+                self.out.push_str(s);
+            }
+        }
+    }
+
+    fn constant(&mut self, c: &Const) {
+        let _ = match c {
+            Const::Unit => write!(self.out, "()"),
+            Const::Int(i) if *i < 0 => write!(self.out, "~{}", (*i as i128).abs()),
+            Const::Int(i) => write!(self.out, "{}", i),
+            Const::Word(w) => write!(self.out, "0w{}", w),
+            Const::Char(c) => write!(self.out, "#\"{}\"", escape(&c.to_string())),
+            Const::String(s) => write!(
+                self.out,
+                "\"{}\"",
+                escape(self.interner.get(*s).unwrap_or("?"))
+            ),
+        };
+    }
+
+    fn open(&mut self, cond: bool) {
+        if cond {
+            self.out.push('(');
+        }
+    }
+
+    fn close(&mut self, cond: bool) {
+        if cond {
+            self.out.push(')');
+        }
+    }
+
+    /// Print a tuple or record, `f` is called on the data of each row
+    fn rows<T, F: FnMut(&mut Pretty<'i>, &T)>(&mut self, rows: &[Row<T>], mut f: F) {
+        if rows.is_empty() {
+            self.out.push_str("()");
+            return;
+        }
+        let tuple = crate::is_tuple(rows);
+        self.out.push(if tuple { '(' } else { '{' });
+        for (idx, row) in rows.iter().enumerate() {
+            if idx > 0 {
+                self.out.push_str(", ");
+            }
+            if !tuple {
+                self.symbol(row.label);
+                self.out.push_str(" = ");
+            }
+            f(self, &row.data);
+        }
+        self.out.push(if tuple { ')' } else { '}' });
+    }
+
+    fn print_pat(&mut self, pat: &Pat<'_>, prec: Prec) {
+        match pat.kind {
+            PatKind::App(con, Some(arg)) => {
+                self.open(prec > Prec::App);
+                self.symbol(con.name);
+                self.out.push(' ');
+                self.print_pat(arg, Prec::Atom);
+                self.close(prec > Prec::App);
+            }
+            PatKind::App(con, None) => self.symbol(con.name),
+            PatKind::Const(c) => self.constant(c),
+            PatKind::Layer(sym, p) => {
+                self.open(prec > Prec::Top);
+                self.symbol(*sym);
+                self.out.push_str(" as ");
+                self.print_pat(p, Prec::Top);
+                self.close(prec > Prec::Top);
+            }
+            PatKind::Record(rows) => self.rows(rows, |pp, p| pp.print_pat(p, Prec::Top)),
+            PatKind::Var(sym) => self.symbol(*sym),
+            PatKind::Vector(pats) => {
+                self.out.push_str("#[");
+                for (idx, p) in pats.iter().enumerate() {
+                    if idx > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.print_pat(p, Prec::Top);
+                }
+                self.out.push(']');
+            }
+            PatKind::Wild => self.out.push('_'),
+        }
+    }
+
+    fn print_rules(&mut self, rules: &[Rule<'_>]) {
+        for (idx, rule) in rules.iter().enumerate() {
+            if idx > 0 {
+                self.out.push_str(" | ");
+            }
+            self.print_pat(&rule.pat, Prec::Top);
+            self.out.push_str(" => ");
+            // A `case`, `fn` or `handle` in any arm but the last would
+            // otherwise swallow the arms that follow it
+            let last = idx + 1 == rules.len();
+            self.print_expr(&rule.expr, if last { Prec::Top } else { Prec::App });
+        }
+    }
+
+    fn print_expr(&mut self, expr: &Expr<'_>, prec: Prec) {
+        match expr.kind {
+            ExprKind::App(e1, e2) => {
+                self.open(prec > Prec::App);
+                self.print_expr(e1, Prec::App);
+                self.out.push(' ');
+                self.print_expr(e2, Prec::Atom);
+                self.close(prec > Prec::App);
+            }
+            ExprKind::Case((var, _), rules) => {
+                self.open(prec > Prec::Top);
+                self.out.push_str("case ");
+                self.symbol(*var);
+                self.out.push_str(" of ");
+                self.print_rules(rules);
+                self.close(prec > Prec::Top);
+            }
+            ExprKind::Con(con, _) => self.symbol(con.name),
+            ExprKind::Const(c) => self.constant(c),
+            ExprKind::Handle(tryy, sym, handler) => {
+                self.open(prec > Prec::Top);
+                self.print_expr(tryy, Prec::App);
+                self.out.push_str(" handle ");
+                self.symbol(*sym);
+                self.out.push_str(" => ");
+                self.print_expr(handler, Prec::Top);
+                self.close(prec > Prec::Top);
+            }
+            ExprKind::Lambda(lam) => {
+                self.open(prec > Prec::Top);
+                self.out.push_str("fn ");
+                self.symbol(lam.arg);
+                self.out.push_str(" => ");
+                self.print_expr(&lam.body, Prec::Top);
+                self.close(prec > Prec::Top);
+            }
+            ExprKind::Let(decls, body) => {
+                self.out.push_str("let ");
+                self.print_decls(decls);
+                self.out.push_str(" in ");
+                self.print_expr(body, Prec::Top);
+                self.out.push_str(" end");
+            }
+            ExprKind::List(exprs) => {
+                self.out.push('[');
+                for (idx, ex) in exprs.iter().enumerate() {
+                    if idx > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.print_expr(ex, Prec::Top);
+                }
+                self.out.push(']');
+            }
+            ExprKind::Primitive(sym) => {
+                self.out.push_str("primitive \"");
+                self.symbol(*sym);
+                self.out.push('"');
+            }
+            ExprKind::Raise(ex) => {
+                self.open(prec > Prec::Top);
+                self.out.push_str("raise ");
+                self.print_expr(ex, Prec::App);
+                self.close(prec > Prec::Top);
+            }
+            ExprKind::Record(rows) => self.rows(rows, |pp, e| pp.print_expr(e, Prec::Top)),
+            ExprKind::Seq(exprs) => {
+                self.out.push('(');
+                for (idx, ex) in exprs.iter().enumerate() {
+                    if idx > 0 {
+                        self.out.push_str("; ");
+                    }
+                    self.print_expr(ex, Prec::Top);
+                }
+                self.out.push(')');
+            }
+            ExprKind::Var(sym) => self.symbol(*sym),
+            ExprKind::Vector(exprs) => {
+                self.out.push_str("#[");
+                for (idx, ex) in exprs.iter().enumerate() {
+                    if idx > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.print_expr(ex, Prec::Top);
+                }
+                self.out.push(']');
+            }
+        }
+    }
+
+    fn print_decls(&mut self, decls: &[Decl<'_>]) {
+        for (idx, decl) in decls.iter().enumerate() {
+            if idx > 0 {
+                self.out.push(' ');
+            }
+            self.print_decl(decl);
+        }
+    }
+
+    fn print_decl(&mut self, decl: &Decl<'_>) {
+        match decl {
+            Decl::Val(_, Rule { pat, expr, .. }) => {
+                self.out.push_str("val ");
+                self.print_pat(pat, Prec::Top);
+                self.out.push_str(" = ");
+                self.print_expr(expr, Prec::Top);
+            }
+            Decl::Fun(_, binds) => {
+                for (idx, (name, lam)) in binds.iter().enumerate() {
+                    self.out.push_str(if idx == 0 { "fun " } else { " and " });
+                    self.symbol(*name);
+                    self.out.push(' ');
+                    self.symbol(lam.arg);
+                    self.out.push_str(" = ");
+                    self.print_expr(&lam.body, Prec::Top);
+                }
+            }
+            Decl::Datatype(dts) => {
+                for (idx, dt) in dts.iter().enumerate() {
+                    self.out
+                        .push_str(if idx == 0 { "datatype " } else { " and " });
+                    self.symbol(dt.tycon.name);
+                    self.out.push_str(" = ");
+                    for (idx, (con, arg)) in dt.constructors.iter().enumerate() {
+                        if idx > 0 {
+                            self.out.push_str(" | ");
+                        }
+                        self.symbol(con.name);
+                        if arg.is_some() {
+                            self.out.push_str(" of _");
+                        }
+                    }
+                }
+            }
+            Decl::Exn(con, arg) => {
+                self.out.push_str("exception ");
+                self.symbol(con.name);
+                if arg.is_some() {
+                    self.out.push_str(" of _");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::elaborate_src;
+
+    fn pretty_val(src: &str) -> String {
+        elaborate_src(src, |interner, _, decls| match decls.last() {
+            Some(Decl::Val(_, rule)) => Pretty::new(interner).expr(&rule.expr),
+            _ => panic!("expected a `val` declaration"),
+        })
+    }
+
+    #[test]
+    fn lambda() {
+        assert_eq!(
+            pretty_val("val f = fn x => x"),
+            "fn _0 => let fun _1 _2 = let val x = _2 in x end val _3 = _0 in _1 _3 end"
+        );
+    }
+
+    #[test]
+    fn andalso() {
+        assert_eq!(
+            pretty_val("val b = true andalso false"),
+            "let val _0 = true in case _0 of true => false | false => false end"
+        );
+    }
+
+    #[test]
+    fn nested_arms() {
+        assert_eq!(
+            pretty_val("val f = if true then fn x => x else fn x => 1"),
+            "let val _0 = true in case _0 of \
+             true => (fn _1 => let fun _2 _3 = let val x = _3 in x end val _4 = _1 in _2 _4 end) | \
+             false => fn _5 => let fun _6 _7 = let val x = _7 in 1 end val _8 = _5 in _6 _8 end end"
+        );
+    }
+
+    #[test]
+    fn escaped_constants() {
+        let mut interner = Interner::with_capacity(4);
+        let s = interner.intern("say \"hi\"\\\n");
+        let mut pp = Pretty::new(&interner);
+        pp.constant(&Const::String(s));
+        pp.out.push(' ');
+        pp.constant(&Const::Char('"'));
+        pp.out.push(' ');
+        pp.constant(&Const::Char('\x07'));
+        assert_eq!(pp.out, r#""say \"hi\"\\\n" #"\"" #"\007""#);
+    }
+}
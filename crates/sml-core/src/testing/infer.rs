@@ -0,0 +1,141 @@
+//! Golden tests for type inference. Each snippet in the corpus is parsed and
+//! elaborated in a fresh [`Context`], and the type inferred for `it` is
+//! compared with the expected one, as printed by the pretty printer
+use super::with_context;
+use crate::types::Scheme;
+use sml_frontend::parser::Parser;
+use sml_util::diagnostics::{Diagnostic, Level};
+use sml_util::pretty_print::PrettyPrinter;
+
+/// A snippet of the corpus: declarations to elaborate first, the expression
+/// whose type is checked, and its expected type
+struct Snippet {
+    decls: &'static str,
+    expr: &'static str,
+    ty: &'static str,
+}
+
+const fn snippet(decls: &'static str, expr: &'static str, ty: &'static str) -> Snippet {
+    Snippet { decls, expr, ty }
+}
+
+const CORPUS: &[Snippet] = &[
+    // Functions
+    snippet("", "fn x => x", "'a -> 'a"),
+    snippet("", "fn x => fn y => x", "'a -> 'b -> 'a"),
+    snippet("", "fn (x, y) => (y, x)", "'a * 'b -> 'b * 'a"),
+    snippet("", "fn x => if x then 1 else 2", "bool -> int"),
+    snippet(
+        "",
+        "let val id = fn x => x in (id 1, id true) end",
+        "int * bool",
+    ),
+    snippet(
+        "fun len [] = 0 | len (_ :: xs) = 1 + len xs",
+        "len",
+        "'a list -> int",
+    ),
+    // Lists and vectors
+    snippet("", "[1, 2, 3]", "int list"),
+    snippet("", "fn x => x :: nil", "'a -> 'a list"),
+    snippet("", "List.map (fn x => x + 1) [1, 2]", "int list"),
+    snippet("", "#[true, false]", "bool vector"),
+    // Records
+    snippet("", "{a = 1, b = \"s\"}", "{a: int, b: string}"),
+    snippet("", "#a {a = 1, b = true}", "int"),
+    // Datatypes
+    snippet(
+        "datatype 'a tree = Leaf | Node of 'a tree * 'a * 'a tree",
+        "Node (Leaf, 1, Leaf)",
+        "int tree",
+    ),
+    snippet(
+        "datatype color = Red | Green",
+        "fn Red => 0 | Green => 1",
+        "color -> int",
+    ),
+    // Overloaded arithmetic and comparisons
+    snippet("", "1 + 2 * 3", "int"),
+    snippet("", "fn x => x + 1", "int -> int"),
+    snippet("", "0w1 + 0w2", "word"),
+    snippet("", "fn x => x < \"a\"", "string -> bool"),
+];
+
+/// Render an error diagnostic for a failed snippet
+fn report(diags: Vec<Diagnostic>, src: &str) -> Vec<String> {
+    diags
+        .into_iter()
+        .filter(|d| matches!(d.level, Level::Error | Level::Bug))
+        .map(|d| d.minimal(src).trim_end().to_string())
+        .collect()
+}
+
+/// Elaborate `snippet` and return the printed type of `it`, or the errors
+/// reported along the way
+fn infer(snippet: &Snippet) -> Result<String, Vec<String>> {
+    with_context(|interner, _, ctx| {
+        if !snippet.decls.is_empty() {
+            let mut p = Parser::new(snippet.decls, interner);
+            let decl = p
+                .parse_decl()
+                .map_err(|e| report(vec![e.to_diagnostic()], snippet.decls))?;
+            ctx.elaborate_decl(&decl);
+
+            // Report these against the declarations, not the expression
+            let errors = report(ctx.diagnostics(interner), snippet.decls);
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+        }
+        let mut p = Parser::new(snippet.expr, interner);
+        let expr = p
+            .parse_expr()
+            .map_err(|e| report(vec![e.to_diagnostic()], snippet.expr))?;
+        ctx.elaborate_top_expr(&expr);
+
+        let errors = report(ctx.diagnostics(interner), snippet.expr);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let ty = match ctx.type_of(interner, "it") {
+            Some(Scheme::Mono(ty)) | Some(Scheme::Poly(_, ty)) => ty,
+            None => return Err(vec!["`it` is not bound".into()]),
+        };
+        let mut out = String::new();
+        let mut pp = PrettyPrinter::new(interner);
+        pp.print(ty);
+        pp.write_fmt(&mut out).unwrap();
+        Ok(out)
+    })
+}
+
+#[test]
+fn inferred_types() {
+    let mut failures = Vec::new();
+    for snippet in CORPUS {
+        let src = match snippet.decls {
+            "" => snippet.expr.to_string(),
+            decls => format!("{}; {}", decls, snippet.expr),
+        };
+        match infer(snippet) {
+            Ok(ty) if ty == snippet.ty => {}
+            Ok(ty) => failures.push(format!(
+                "{}\n  expected: {}\n  found:    {}",
+                src, snippet.ty, ty
+            )),
+            Err(errors) => failures.push(format!(
+                "{}\n  expected: {}\n  errors:\n    {}",
+                src,
+                snippet.ty,
+                errors.join("\n").replace('\n', "\n    ")
+            )),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} snippets failed:\n\n{}",
+        failures.len(),
+        CORPUS.len(),
+        failures.join("\n\n")
+    );
+}
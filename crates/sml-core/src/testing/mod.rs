@@ -0,0 +1,45 @@
+//! Fixtures shared by the unit tests of the elaborator and the passes that
+//! run over its output
+use crate::arenas::{CoreArena, OwnedCoreArena};
+use crate::elaborate::Context;
+use crate::Decl;
+use sml_frontend::parser::Parser;
+use sml_util::interner::Interner;
+
+mod infer;
+
+/// Run `f` with a fresh interner and an elaboration context over a fresh
+/// arena, on a stack large enough for the default nesting limits
+pub fn with_context<T, F>(f: F) -> T
+where
+    T: Send,
+    F: for<'a> FnOnce(&mut Interner, &'a CoreArena<'a>, &mut Context<'a>) -> T + Send,
+{
+    sml_util::with_large_stack(|| {
+        let mut interner = Interner::with_capacity(64);
+        let owned = OwnedCoreArena::new();
+        let arena = owned.borrow();
+        let mut ctx = Context::new(&arena);
+        f(&mut interner, &arena, &mut ctx)
+    })
+}
+
+/// Parse `src` as a single declaration, and elaborate it in `ctx`
+pub fn elaborate<'a>(ctx: &mut Context<'a>, interner: &mut Interner, src: &str) -> Vec<Decl<'a>> {
+    let decl = Parser::new(src, interner).parse_decl().unwrap();
+    ctx.elaborate_decl(&decl)
+}
+
+/// Elaborate the single declaration `src` in a fresh context, assert that no
+/// diagnostics were reported, and call `f` with the elaborated declarations
+pub fn elaborate_src<T, F>(src: &str, f: F) -> T
+where
+    T: Send,
+    F: for<'a> FnOnce(&Interner, &'a CoreArena<'a>, &[Decl<'a>]) -> T + Send,
+{
+    with_context(|interner, arena, ctx| {
+        let decls = elaborate(ctx, interner, src);
+        assert!(ctx.diagnostics(interner).is_empty());
+        f(interner, arena, &decls)
+    })
+}
@@ -17,11 +17,28 @@ use std::collections::{HashSet, VecDeque};
 /// variable. When combined with the interior mutability aspect, we get
 /// maximal sharing of type unification information.
 ///
+/// A type variable introduced by the use of an overloaded identifier, like
+/// `+` or `<`, carries the class of types that it may be instantiated to.
+///
 /// Invariants: it is important that `data` is set at *most* one time.
 pub struct TypeVar<'a> {
     pub id: usize,
     rank: Cell<usize>,
     pub data: Cell<Option<&'a Type<'a>>>,
+    overload: Cell<Option<Overload>>,
+}
+
+/// The class of types that an overloaded identifier may be used at.
+///
+/// Overloaded type variables are never generalized, and if one is still
+/// unresolved at the end of the enclosing top-level declaration, it defaults
+/// to `int`, which is a member of every class
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Overload {
+    /// Arithmetic operators: `+`, `-`, `*`, `~` and `abs`
+    Num,
+    /// Comparison operators: `<`, `>`, `<=` and `>=`
+    NumText,
 }
 
 /// A flexible record type.
@@ -30,7 +47,8 @@ pub struct TypeVar<'a> {
 /// with the value restriction. We allow the maximum possible scope to resolve
 /// flex records. Similar to [`TypeVar`], it is critical that `unified` is set
 /// at *most* once. We impose an additional restriction that the unified type
-/// must always be a Record type
+/// must always be a Record type, or another Flex type that combines the
+/// constraints of two flexible records
 pub struct Flex<'a> {
     pub constraints: SortedRecord<&'a Type<'a>>,
     pub unified: Cell<Option<&'a Type<'a>>>,
@@ -48,7 +66,7 @@ pub enum Type<'a> {
 }
 
 /// A type constructor
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Eq, Hash)]
 pub struct Tycon {
     pub name: Symbol,
     pub arity: usize,
@@ -72,6 +90,20 @@ pub enum Scheme<'a> {
     Poly(Vec<usize>, &'a Type<'a>),
 }
 
+/// A mapping from the ids of type variables to the types that replace them,
+/// applied functionally with [`Subst::apply`]. Unlike unification, applying a
+/// substitution allocates new types and leaves the original untouched
+#[derive(Clone, Default)]
+pub struct Subst<'a>(HashMap<usize, &'a Type<'a>>);
+
+/// The number of type arguments given to [`Scheme::instantiate_with`] is not
+/// the number of variables quantified by the scheme
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ArityError {
+    pub expected: usize,
+    pub found: usize,
+}
+
 impl<'a> Type<'a> {
     pub fn as_tyvar(&self) -> &TypeVar<'a> {
         match self {
@@ -80,6 +112,16 @@ impl<'a> Type<'a> {
         }
     }
 
+    /// Follow any type variables and flexible records that have been unified
+    /// with another type, returning the canonical representative of `self`
+    pub fn resolve(&self) -> &Type<'a> {
+        match self {
+            Type::Var(tv) => tv.ty().map(Type::resolve).unwrap_or(self),
+            Type::Flex(flex) => flex.ty().map(Type::resolve).unwrap_or(self),
+            _ => self,
+        }
+    }
+
     /// 'de-arrow' an arrow type, returning the argument and result type
     pub fn de_arrow(&self) -> Option<(&'_ Type<'a>, &'_ Type<'a>)> {
         match self {
@@ -95,6 +137,27 @@ impl<'a> Type<'a> {
         }
     }
 
+    /// 'de-record' a record type, returning the known fields. For a flexible
+    /// record that hasn't been resolved yet, this is only the fields that
+    /// have been constrained so far
+    pub fn de_record(&self) -> Option<&'_ SortedRecord<&'a Type<'a>>> {
+        match self {
+            Type::Record(rows) => Some(rows),
+            Type::Flex(flex) => match flex.ty() {
+                Some(ty) => ty.de_record(),
+                None => Some(&flex.constraints),
+            },
+            Type::Var(tv) => tv.ty()?.de_record(),
+            _ => None,
+        }
+    }
+
+    /// Look up the type of the field `label` of a record type, which may be
+    /// [`Symbol::tuple_field`] for a tuple component
+    pub fn field(&self, label: Symbol) -> Option<&'a Type<'a>> {
+        self.de_record()?.contains(&label).map(|row| row.data)
+    }
+
     /// Return true if the type-graph contains any unresolved flex variables
     pub fn unresolved_flex(&self) -> bool {
         let mut unres = false;
@@ -144,6 +207,30 @@ impl<'a> Type<'a> {
         }
     }
 
+    /// Collect the ids of the unresolved type variables in `self` into `out`,
+    /// in the order they are first encountered. Type variables that have
+    /// already been unified with another type are followed, not collected
+    pub fn free_vars(&self, out: &mut Vec<usize>) {
+        self.visit(|ty| {
+            if let Type::Var(tv) = ty {
+                if tv.ty().is_none() && !out.contains(&tv.id) {
+                    out.push(tv.id);
+                }
+            }
+        });
+    }
+
+    /// Does the unresolved type variable `id` occur anywhere in `self`?
+    pub fn occurs(&self, id: usize) -> bool {
+        let mut found = false;
+        self.visit(|ty| {
+            if let Type::Var(tv) = ty {
+                found |= tv.ty().is_none() && tv.id == id;
+            }
+        });
+        found
+    }
+
     /// Perform a breadth-first traversal of a type, collecting it's
     /// associated type variables that have a rank greater than `rank`
     pub fn ftv_rank(&self, rank: usize) -> Vec<usize> {
@@ -156,7 +243,9 @@ impl<'a> Type<'a> {
             match ty {
                 Type::Var(x) => match x.ty() {
                     None => {
-                        if x.rank() > rank && uniq.insert(x.id) {
+                        // Overloaded type variables must be resolved to a
+                        // single type, so they are never generalized
+                        if x.rank() > rank && x.overload().is_none() && uniq.insert(x.id) {
                             vars.push(x.id);
                         }
                     }
@@ -195,7 +284,9 @@ impl<'a> Type<'a> {
             match ty {
                 Type::Var(x) => match x.ty() {
                     None => {
-                        if x.rank() > rank && uniq.insert(x.id) {
+                        // Overloaded type variables must be resolved to a
+                        // single type, so they are never generalized
+                        if x.rank() > rank && x.overload().is_none() && uniq.insert(x.id) {
                             vars.push(x.id);
                         }
                     }
@@ -227,21 +318,14 @@ impl<'a> Type<'a> {
     }
 
     /// Apply a substitution to a type
-    pub fn apply(
-        &'a self,
-        arena: &'a TypeArena<'a>,
-        map: &HashMap<usize, &'a Type<'a>>,
-    ) -> &'a Type<'a> {
+    pub fn apply(&'a self, arena: &'a TypeArena<'a>, map: &Subst<'a>) -> &'a Type<'a> {
         match self {
             Type::Var(x) => match x.ty() {
                 Some(ty) => ty.apply(arena, map),
-                None => {
-                    match map.get(&x.id) {
-                        Some(ty) => ty,
-                        None => self,
-                    }
-                    // map.get(&x.id).copied().unwrap_or(self),
-                }
+                None => match map.get(x.id) {
+                    Some(ty) => ty,
+                    None => self,
+                },
             },
             Type::Con(tc, vars) => arena.alloc(Type::Con(
                 *tc,
@@ -260,6 +344,52 @@ impl<'a> Type<'a> {
         }
     }
 
+    /// Reconstruct the type in another arena, so that it doesn't alias any
+    /// type variables of the one it was allocated in. Unified type variables
+    /// and flexible records are followed, and each unresolved type variable
+    /// is replaced by a fresh one with the same rank and overload class
+    pub fn deep_clone_into<'b>(&self, arena: &'b TypeArena<'b>) -> &'b Type<'b> {
+        self.deep_clone_with(arena, &mut HashMap::new())
+    }
+
+    /// Like [`Type::deep_clone_into`], but sharing `map` from the ids of
+    /// the original type variables to their replacements, so that several
+    /// types can be cloned consistently
+    pub fn deep_clone_with<'b>(
+        &self,
+        arena: &'b TypeArena<'b>,
+        map: &mut HashMap<usize, &'b Type<'b>>,
+    ) -> &'b Type<'b> {
+        match self.resolve() {
+            Type::Var(tv) => map.entry(tv.id).or_insert_with(|| match tv.overload() {
+                Some(class) => arena.fresh_overloaded(tv.rank(), class),
+                None => arena.fresh_var(tv.rank()),
+            }),
+            Type::Con(tc, args) => {
+                let args = args
+                    .iter()
+                    .map(|ty| ty.deep_clone_with(arena, map))
+                    .collect();
+                arena.alloc(Type::Con(*tc, args))
+            }
+            Type::Record(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| row.fmap(|ty| ty.deep_clone_with(arena, map)))
+                    .collect();
+                arena.alloc(Type::Record(SortedRecord::new_unchecked(rows)))
+            }
+            Type::Flex(flex) => {
+                let rows = flex
+                    .constraints
+                    .iter()
+                    .map(|row| row.fmap(|ty| ty.deep_clone_with(arena, map)))
+                    .collect();
+                arena.alloc(Type::Flex(Flex::new(SortedRecord::new_unchecked(rows))))
+            }
+        }
+    }
+
     /// Check for potential cyclic occurences of `tyvar` in `self`.
     /// N.B. This function has potential side effects, in that it may promote
     /// the associated rank of `tyvar` to the rank of `self`, if `self` is also
@@ -290,6 +420,53 @@ impl<'a> Type<'a> {
     }
 }
 
+/// Types are compared structurally, after resolving both sides with
+/// [`Type::resolve`], so the result reflects the unification performed so
+/// far. Unresolved type variables are equal only to themselves, as identified
+/// by their `id`, and unresolved flexible records only to the very same
+/// record. Since unification can only make more types equal, the types used
+/// as keys of a map should not be unified while the map is in use
+impl<'a> PartialEq for Type<'a> {
+    fn eq(&self, other: &Type<'a>) -> bool {
+        match (self.resolve(), other.resolve()) {
+            (Type::Var(a), Type::Var(b)) => a.id == b.id,
+            (Type::Con(a, xs), Type::Con(b, ys)) => a == b && xs == ys,
+            (Type::Record(xs), Type::Record(ys)) => {
+                xs.len() == ys.len()
+                    && xs
+                        .iter()
+                        .zip(ys.iter())
+                        .all(|(x, y)| x.label == y.label && x.data == y.data)
+            }
+            (Type::Flex(a), Type::Flex(b)) => std::ptr::eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl<'a> Eq for Type<'a> {}
+
+impl<'a> std::hash::Hash for Type<'a> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let ty = self.resolve();
+        std::mem::discriminant(ty).hash(state);
+        match ty {
+            Type::Var(tv) => tv.id.hash(state),
+            Type::Con(tc, args) => {
+                tc.hash(state);
+                args.hash(state);
+            }
+            Type::Record(rows) => {
+                for row in rows.iter() {
+                    row.label.hash(state);
+                    row.data.hash(state);
+                }
+            }
+            Type::Flex(flex) => std::ptr::hash(flex, state),
+        }
+    }
+}
+
 pub fn fresh_name(x: usize) -> String {
     let last = ((x % 26) as u8 + b'a' as u8) as char;
     (0..x / 26)
@@ -319,23 +496,78 @@ impl<'a> Scheme<'a> {
     pub fn apply(&self, arena: &'a TypeArena<'a>, args: Vec<&'a Type<'a>>) -> &'a Type<'a> {
         match self {
             Scheme::Mono(ty) => ty,
+            Scheme::Poly(_, _) => self
+                .instantiate_with(arena, &args)
+                .expect("internal compiler error, not checking scheme arity"),
+        }
+    }
+
+    /// Instantiate the scheme with explicit type arguments, substituting
+    /// `args` for the quantified variables in order, rather than fresh type
+    /// variables as in elaboration. This fails if the number of arguments
+    /// isn't the arity of the scheme
+    pub fn instantiate_with(
+        &self,
+        arena: &'a TypeArena<'a>,
+        args: &[&'a Type<'a>],
+    ) -> Result<&'a Type<'a>, ArityError> {
+        if args.len() != self.arity() {
+            return Err(ArityError {
+                expected: self.arity(),
+                found: args.len(),
+            });
+        }
+        match self {
+            Scheme::Mono(ty) => Ok(ty),
             Scheme::Poly(vars, ty) => {
-                if vars.len() > args.len() {
-                    unimplemented!()
-                } else if vars.len() == args.len() {
-                    let map = vars
-                        .iter()
-                        .copied()
-                        .zip(args.into_iter())
-                        .collect::<HashMap<usize, &'a Type<'a>>>();
-                    ty.apply(arena, &map)
-                } else {
-                    panic!("internal compiler error, not checking scheme arity")
+                let map = vars
+                    .iter()
+                    .copied()
+                    .zip(args.iter().copied())
+                    .collect::<Subst<'a>>();
+                Ok(ty.apply(arena, &map))
+            }
+        }
+    }
+
+    /// Collect the ids of the free type variables of the scheme into `out`,
+    /// like [`Type::free_vars`], but excluding the quantified variables
+    pub fn free_vars(&self, out: &mut Vec<usize>) {
+        match self {
+            Scheme::Mono(ty) => ty.free_vars(out),
+            Scheme::Poly(bound, ty) => {
+                let mut vars = Vec::new();
+                ty.free_vars(&mut vars);
+                for id in vars {
+                    if !bound.contains(&id) && !out.contains(&id) {
+                        out.push(id);
+                    }
                 }
             }
         }
     }
 
+    /// Reconstruct the scheme in another arena, as with
+    /// [`Type::deep_clone_into`], quantifying over the replacements of its
+    /// quantified type variables
+    pub fn deep_clone_into<'b>(&self, arena: &'b TypeArena<'b>) -> Scheme<'b> {
+        let mut map = HashMap::new();
+        match self {
+            Scheme::Mono(ty) => Scheme::Mono(ty.deep_clone_with(arena, &mut map)),
+            Scheme::Poly(vars, ty) => {
+                let ty = ty.deep_clone_with(arena, &mut map);
+                let vars = vars
+                    .iter()
+                    .map(|id| match map.get(id) {
+                        Some(Type::Var(tv)) => tv.id,
+                        _ => arena.fresh_type_var(0).id,
+                    })
+                    .collect();
+                Scheme::Poly(vars, ty)
+            }
+        }
+    }
+
     pub fn new(ty: &'a Type<'a>, tyvars: Vec<usize>) -> Scheme<'a> {
         match tyvars.len() {
             0 => Scheme::Mono(ty),
@@ -344,6 +576,46 @@ impl<'a> Scheme<'a> {
     }
 }
 
+impl<'a> Subst<'a> {
+    pub fn new() -> Subst<'a> {
+        Subst(HashMap::new())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&'a Type<'a>> {
+        self.0.get(&id).copied()
+    }
+
+    pub fn insert(&mut self, id: usize, ty: &'a Type<'a>) {
+        self.0.insert(id, ty);
+    }
+
+    /// Apply the substitution to `ty`, replacing each of the type variables
+    /// that it maps. Replacements are not themselves substituted into
+    pub fn apply(&self, arena: &'a TypeArena<'a>, ty: &'a Type<'a>) -> &'a Type<'a> {
+        ty.apply(arena, self)
+    }
+
+    /// Compose two substitutions, such that applying the result is the same
+    /// as applying `self` and then `other`
+    pub fn compose(&self, arena: &'a TypeArena<'a>, other: &Subst<'a>) -> Subst<'a> {
+        let mut map = other.0.clone();
+        for (id, ty) in &self.0 {
+            map.insert(*id, other.apply(arena, ty));
+        }
+        Subst(map)
+    }
+}
+
+impl<'a> std::iter::FromIterator<(usize, &'a Type<'a>)> for Subst<'a> {
+    fn from_iter<I: IntoIterator<Item = (usize, &'a Type<'a>)>>(iter: I) -> Subst<'a> {
+        Subst(iter.into_iter().collect())
+    }
+}
+
 impl<'a> TypeVar<'a> {
     pub fn new(id: usize, rank: usize) -> TypeVar<'a> {
         let data = Cell::new(None);
@@ -351,18 +623,57 @@ impl<'a> TypeVar<'a> {
             id,
             rank: Cell::new(rank),
             data,
+            overload: Cell::new(None),
         }
     }
 
+    pub fn overloaded(id: usize, rank: usize, class: Overload) -> TypeVar<'a> {
+        let tv = TypeVar::new(id, rank);
+        tv.overload.set(Some(class));
+        tv
+    }
+
     pub fn ty(&self) -> Option<&'a Type<'a>> {
         self.data.get()
     }
 
+    pub fn overload(&self) -> Option<Overload> {
+        self.overload.get()
+    }
+
+    /// Restrict this type variable to the types that are members of both
+    /// its own overload class (if any) and `class`
+    pub fn restrict(&self, class: Overload) {
+        let meet = match self.overload() {
+            Some(Overload::Num) => Overload::Num,
+            _ => class,
+        };
+        self.overload.set(Some(meet));
+    }
+
     fn rank(&self) -> usize {
         self.rank.get()
     }
 }
 
+impl Overload {
+    /// The type constructors that are members of this class
+    pub fn candidates(self) -> &'static [Tycon] {
+        use builtin::tycons::*;
+        match self {
+            Overload::Num => &[T_INT, T_WORD],
+            Overload::NumText => &[T_INT, T_WORD, T_CHAR, T_STRING],
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Overload::Num => "`int` or `word`",
+            Overload::NumText => "`int`, `word`, `char` or `string`",
+        }
+    }
+}
+
 impl<'a> Flex<'a> {
     pub fn new(constraints: SortedRecord<&'a Type<'a>>) -> Flex<'a> {
         Flex {
@@ -375,3 +686,234 @@ impl<'a> Flex<'a> {
         self.unified.get()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arenas::OwnedCoreArena;
+    use sml_util::interner::Interner;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    /// Run `f` with the type arena of a fresh [`OwnedCoreArena`]
+    fn with_types<F>(f: F)
+    where
+        F: for<'a> FnOnce(&'a TypeArena<'a>),
+    {
+        let owned = OwnedCoreArena::new();
+        let arena = owned.borrow();
+        f(&arena.types)
+    }
+
+    fn hash(ty: &Type<'_>) -> u64 {
+        let mut state = DefaultHasher::new();
+        ty.hash(&mut state);
+        state.finish()
+    }
+
+    #[test]
+    fn record_field_lookup() {
+        with_types(|types| {
+            let mut interner = Interner::with_capacity(64);
+            let (a, b) = (interner.intern("a"), interner.intern("b"));
+
+            let row = |label, data| Row {
+                label,
+                data,
+                span: Span::dummy(),
+            };
+            let rec = types.alloc(Type::Record(SortedRecord::new(vec![
+                row(a, types.int()),
+                row(b, types.bool()),
+            ])));
+            assert!(*rec.field(a).unwrap() == *types.int());
+            assert!(*rec.field(b).unwrap() == *types.bool());
+            assert!(rec.field(interner.intern("c")).is_none());
+
+            let tuple = types.tuple(vec![types.char(), types.string()]);
+            assert!(*tuple.field(Symbol::tuple_field(2)).unwrap() == *types.string());
+            assert!(tuple.field(Symbol::tuple_field(3)).is_none());
+
+            // Fields are found through resolved type variables, but a type that
+            // isn't a record has none
+            let var = types.fresh_var(0);
+            var.as_tyvar().data.set(Some(rec));
+            assert!(*var.field(a).unwrap() == *types.int());
+            assert!(types.int().field(a).is_none());
+        })
+    }
+
+    #[test]
+    fn instantiate_with_explicit_args() {
+        with_types(|types| {
+            let var = types.fresh_var(0);
+            let id = Scheme::new(types.arrow(var, var), vec![var.as_tyvar().id]);
+            let ty = id.instantiate_with(types, &[types.int()]).unwrap();
+            assert!(*ty == *types.arrow(types.int(), types.int()));
+
+            assert_eq!(
+                id.instantiate_with(types, &[types.int(), types.bool()])
+                    .err(),
+                Some(ArityError {
+                    expected: 1,
+                    found: 2
+                })
+            );
+            assert!(id.instantiate_with(types, &[]).is_err());
+            assert!(Scheme::Mono(types.int())
+                .instantiate_with(types, &[types.int()])
+                .is_err());
+        })
+    }
+
+    #[test]
+    fn empty_subst_is_identity() {
+        with_types(|types| {
+            let var = types.fresh_var(0);
+            let ty = types.arrow(var, types.list(types.tuple(vec![types.int(), var])));
+            let subst = Subst::new();
+            assert!(subst.is_empty());
+            assert!(*subst.apply(types, ty) == *ty);
+        })
+    }
+
+    #[test]
+    fn composed_subst_applies_in_sequence() {
+        with_types(|types| {
+            let (a, b, c) = (types.fresh_var(0), types.fresh_var(0), types.fresh_var(0));
+            let id = |ty: &Type<'_>| ty.as_tyvar().id;
+
+            // [a := b list, b := int] then [b := bool, c := a]
+            let first = vec![(id(a), types.list(b)), (id(b), types.int())]
+                .into_iter()
+                .collect::<Subst<'_>>();
+            let second = vec![(id(b), types.bool()), (id(c), a)]
+                .into_iter()
+                .collect::<Subst<'_>>();
+            let composed = first.compose(types, &second);
+
+            let ty = types.tuple(vec![a, types.arrow(b, c)]);
+            let sequenced = second.apply(types, first.apply(types, ty));
+            assert!(*composed.apply(types, ty) == *sequenced);
+            assert!(
+                *sequenced
+                    == *types.tuple(vec![types.list(types.bool()), types.arrow(types.int(), a)])
+            );
+        })
+    }
+
+    #[test]
+    fn deep_clone_into_another_arena() {
+        with_types(|types| {
+            let (a, b) = (types.fresh_var(0), types.fresh_var(0));
+            let ty = types.arrow(a, types.list(types.tuple(vec![b, a])));
+            let scheme = Scheme::new(ty, vec![a.as_tyvar().id, b.as_tyvar().id]);
+
+            with_types(|other| {
+                // Use up some ids in the target arena first, so that the
+                // replacement variables can't happen to have the same ids as
+                // the originals
+                for _ in 0..4 {
+                    other.fresh_var(0);
+                }
+                let clone = scheme.deep_clone_into(other);
+                let vars = match &clone {
+                    Scheme::Poly(vars, _) => vars.clone(),
+                    Scheme::Mono(_) => panic!("expected a polymorphic scheme"),
+                };
+                assert_eq!(vars.len(), 2);
+                assert!(vars
+                    .iter()
+                    .all(|id| *id != a.as_tyvar().id && *id != b.as_tyvar().id));
+
+                let (int, bool) = (other.int(), other.bool());
+                let ty = clone.instantiate_with(other, &[int, bool]).unwrap();
+                let expected = other.arrow(int, other.list(other.tuple(vec![bool, int])));
+                assert!(*ty == *expected);
+
+                // Unifying a variable of the clone leaves the original untouched
+                let cloned = match &clone {
+                    Scheme::Poly(_, ty) => *ty,
+                    Scheme::Mono(ty) => *ty,
+                };
+                let (dom, _) = cloned.de_arrow().unwrap();
+                dom.as_tyvar().data.set(Some(other.string()));
+                assert!(a.as_tyvar().ty().is_none());
+            })
+        })
+    }
+
+    #[test]
+    fn resolved_types_are_equal() {
+        with_types(|types| {
+            let var = types.fresh_var(0);
+            var.as_tyvar().data.set(Some(types.int()));
+            let a = types.arrow(types.list(var), types.tuple(vec![var, types.bool()]));
+            let b = types.arrow(
+                types.list(types.int()),
+                types.tuple(vec![types.int(), types.bool()]),
+            );
+            assert!(a == b);
+            assert_eq!(hash(a), hash(b));
+            assert!(a != types.arrow(types.list(types.int()), types.int()));
+        })
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn fresh_vars_are_distinct() {
+        with_types(|types| {
+            let (a, b) = (types.fresh_var(0), types.fresh_var(0));
+            assert!(a == a);
+            assert!(a != b);
+            assert!(types.list(a) != types.list(b));
+
+            let mut set = HashSet::new();
+            set.insert(types.list(a));
+            set.insert(types.list(b));
+            set.insert(types.list(a));
+            assert_eq!(set.len(), 2);
+        })
+    }
+
+    #[test]
+    fn free_vars_of_arrow_of_records() {
+        with_types(|types| {
+            let (a, b, c) = (types.fresh_var(0), types.fresh_var(0), types.fresh_var(0));
+            // `c` has been unified with `a`, so it isn't free itself
+            c.as_tyvar().data.set(Some(a));
+            let ty = types.arrow(
+                types.tuple(vec![a, types.int()]),
+                types.tuple(vec![types.list(b), c]),
+            );
+
+            let mut vars = Vec::new();
+            ty.free_vars(&mut vars);
+            assert_eq!(vars, vec![a.as_tyvar().id, b.as_tyvar().id]);
+            assert!(ty.occurs(a.as_tyvar().id));
+            assert!(ty.occurs(b.as_tyvar().id));
+            assert!(!ty.occurs(c.as_tyvar().id));
+        })
+    }
+
+    #[test]
+    fn scheme_free_vars_exclude_quantified() {
+        with_types(|types| {
+            let (a, b) = (types.fresh_var(0), types.fresh_var(0));
+            let (a_id, b_id) = (a.as_tyvar().id, b.as_tyvar().id);
+            let ty = types.arrow(a, types.tuple(vec![a, b]));
+
+            let mut vars = Vec::new();
+            Scheme::Poly(vec![a_id], ty).free_vars(&mut vars);
+            assert_eq!(vars, vec![b_id]);
+
+            let mut vars = Vec::new();
+            Scheme::Poly(vec![a_id, b_id], ty).free_vars(&mut vars);
+            assert!(vars.is_empty());
+
+            let mut vars = Vec::new();
+            Scheme::Mono(ty).free_vars(&mut vars);
+            assert_eq!(vars, vec![a_id, b_id]);
+        })
+    }
+}
@@ -0,0 +1,358 @@
+//! Generic traversals over Core ML
+//!
+//! [`Visitor`] walks a term by reference, for analysis passes, while [`Folder`]
+//! rebuilds a term in a [`CoreArena`], for transformations. Every method has a
+//! default implementation that simply recurses into the children of a node,
+//! via the `walk_*` and `fold_*_children` functions respectively. A pass
+//! overrides only the methods for the nodes it cares about, and calls the
+//! matching free function whenever it wants to continue the traversal.
+
+use crate::arenas::CoreArena;
+use crate::types::Type;
+use crate::{Datatype, Decl, Expr, ExprKind, Lambda, Pat, PatKind, Row, Rule, SortedRecord};
+
+pub trait Visitor<'a> {
+    fn visit_decl(&mut self, decl: &Decl<'a>) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'a>) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_pat(&mut self, pat: &Pat<'a>) {
+        walk_pat(self, pat)
+    }
+
+    fn visit_type(&mut self, ty: &'a Type<'a>) {
+        walk_type(self, ty)
+    }
+}
+
+pub fn walk_decl<'a, V: Visitor<'a> + ?Sized>(v: &mut V, decl: &Decl<'a>) {
+    match decl {
+        Decl::Datatype(dts) => {
+            for dt in dts {
+                for ty in dt.constructors.iter().filter_map(|(_, ty)| *ty) {
+                    v.visit_type(ty);
+                }
+            }
+        }
+        Decl::Fun(_, binds) => {
+            for (_, lam) in binds {
+                v.visit_type(lam.ty);
+                v.visit_expr(&lam.body);
+            }
+        }
+        Decl::Val(_, rule) => {
+            v.visit_pat(&rule.pat);
+            v.visit_expr(&rule.expr);
+        }
+        Decl::Exn(_, ty) => {
+            if let Some(ty) = ty {
+                v.visit_type(ty);
+            }
+        }
+    }
+}
+
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(v: &mut V, expr: &Expr<'a>) {
+    v.visit_type(expr.ty);
+    match expr.kind {
+        ExprKind::App(e1, e2) => {
+            v.visit_expr(e1);
+            v.visit_expr(e2);
+        }
+        ExprKind::Case((_, ty), rules) => {
+            v.visit_type(ty);
+            for rule in rules {
+                v.visit_pat(&rule.pat);
+                v.visit_expr(&rule.expr);
+            }
+        }
+        ExprKind::Con(_, tys) => {
+            for ty in tys {
+                v.visit_type(ty);
+            }
+        }
+        ExprKind::Handle(tryy, _, handler) => {
+            v.visit_expr(tryy);
+            v.visit_expr(handler);
+        }
+        ExprKind::Lambda(lam) => {
+            v.visit_type(lam.ty);
+            v.visit_expr(&lam.body);
+        }
+        ExprKind::Let(decls, body) => {
+            for decl in decls {
+                v.visit_decl(decl);
+            }
+            v.visit_expr(body);
+        }
+        ExprKind::List(exprs) | ExprKind::Seq(exprs) | ExprKind::Vector(exprs) => {
+            for ex in exprs {
+                v.visit_expr(ex);
+            }
+        }
+        ExprKind::Raise(ex) => v.visit_expr(ex),
+        ExprKind::Record(rows) => {
+            for row in rows {
+                v.visit_expr(&row.data);
+            }
+        }
+        ExprKind::Const(_) | ExprKind::Primitive(_) | ExprKind::Var(_) => {}
+    }
+}
+
+pub fn walk_pat<'a, V: Visitor<'a> + ?Sized>(v: &mut V, pat: &Pat<'a>) {
+    v.visit_type(pat.ty);
+    match pat.kind {
+        PatKind::App(_, Some(arg)) | PatKind::Layer(_, arg) => v.visit_pat(arg),
+        PatKind::Record(rows) => {
+            for row in rows.iter() {
+                v.visit_pat(&row.data);
+            }
+        }
+        PatKind::Vector(pats) => {
+            for p in pats {
+                v.visit_pat(p);
+            }
+        }
+        PatKind::App(_, None) | PatKind::Const(_) | PatKind::Var(_) | PatKind::Wild => {}
+    }
+}
+
+/// Resolved type variables and flexible records are transparently walked
+/// through to the type they have been unified with
+pub fn walk_type<'a, V: Visitor<'a> + ?Sized>(v: &mut V, ty: &'a Type<'a>) {
+    match ty {
+        Type::Var(tyvar) => {
+            if let Some(ty) = tyvar.ty() {
+                v.visit_type(ty);
+            }
+        }
+        Type::Con(_, args) => {
+            for arg in args {
+                v.visit_type(arg);
+            }
+        }
+        Type::Record(rows) => {
+            for row in rows.iter() {
+                v.visit_type(row.data);
+            }
+        }
+        Type::Flex(flex) => match flex.ty() {
+            Some(ty) => v.visit_type(ty),
+            None => {
+                for row in flex.constraints.iter() {
+                    v.visit_type(row.data);
+                }
+            }
+        },
+    }
+}
+
+pub trait Folder<'a> {
+    /// The arena that rebuilt terms are allocated in
+    fn arena(&self) -> &'a CoreArena<'a>;
+
+    fn fold_decl(&mut self, decl: &Decl<'a>) -> Decl<'a> {
+        fold_decl_children(self, decl)
+    }
+
+    fn fold_expr(&mut self, expr: &Expr<'a>) -> Expr<'a> {
+        fold_expr_children(self, expr)
+    }
+
+    fn fold_pat(&mut self, pat: &Pat<'a>) -> Pat<'a> {
+        fold_pat_children(self, pat)
+    }
+
+    fn fold_type(&mut self, ty: &'a Type<'a>) -> &'a Type<'a> {
+        fold_type_children(self, ty)
+    }
+}
+
+pub fn fold_decl_children<'a, F: Folder<'a> + ?Sized>(f: &mut F, decl: &Decl<'a>) -> Decl<'a> {
+    match decl {
+        Decl::Datatype(dts) => Decl::Datatype(
+            dts.iter()
+                .map(|dt| Datatype {
+                    tycon: dt.tycon,
+                    tyvars: dt.tyvars.clone(),
+                    constructors: dt
+                        .constructors
+                        .iter()
+                        .map(|(con, ty)| (*con, ty.map(|ty| f.fold_type(ty))))
+                        .collect(),
+                })
+                .collect(),
+        ),
+        Decl::Fun(vars, binds) => Decl::Fun(
+            vars.clone(),
+            binds
+                .iter()
+                .map(|(name, lam)| (*name, fold_lambda(f, lam)))
+                .collect(),
+        ),
+        Decl::Val(vars, rule) => Decl::Val(vars.clone(), fold_rule(f, rule)),
+        Decl::Exn(con, ty) => Decl::Exn(*con, ty.map(|ty| f.fold_type(ty))),
+    }
+}
+
+fn fold_lambda<'a, F: Folder<'a> + ?Sized>(f: &mut F, lam: &Lambda<'a>) -> Lambda<'a> {
+    Lambda {
+        arg: lam.arg,
+        ty: f.fold_type(lam.ty),
+        body: f.fold_expr(&lam.body),
+    }
+}
+
+fn fold_rule<'a, F: Folder<'a> + ?Sized>(f: &mut F, rule: &Rule<'a>) -> Rule<'a> {
+    Rule {
+        pat: f.fold_pat(&rule.pat),
+        expr: f.fold_expr(&rule.expr),
+        span: rule.span,
+    }
+}
+
+pub fn fold_expr_children<'a, F: Folder<'a> + ?Sized>(f: &mut F, expr: &Expr<'a>) -> Expr<'a> {
+    let kind = match expr.kind {
+        ExprKind::App(e1, e2) => ExprKind::App(f.fold_expr(e1), f.fold_expr(e2)),
+        ExprKind::Case((var, ty), rules) => ExprKind::Case(
+            (*var, f.fold_type(ty)),
+            rules.iter().map(|rule| fold_rule(f, rule)).collect(),
+        ),
+        ExprKind::Con(con, tys) => {
+            ExprKind::Con(*con, tys.iter().map(|ty| f.fold_type(ty)).collect())
+        }
+        ExprKind::Const(c) => ExprKind::Const(*c),
+        ExprKind::Handle(tryy, sym, handler) => {
+            ExprKind::Handle(f.fold_expr(tryy), *sym, f.fold_expr(handler))
+        }
+        ExprKind::Lambda(lam) => ExprKind::Lambda(fold_lambda(f, lam)),
+        ExprKind::Let(decls, body) => ExprKind::Let(
+            decls.iter().map(|d| f.fold_decl(d)).collect(),
+            f.fold_expr(body),
+        ),
+        ExprKind::List(exprs) => ExprKind::List(exprs.iter().map(|e| f.fold_expr(e)).collect()),
+        ExprKind::Primitive(sym) => ExprKind::Primitive(*sym),
+        ExprKind::Raise(ex) => ExprKind::Raise(f.fold_expr(ex)),
+        ExprKind::Record(rows) => ExprKind::Record(
+            rows.iter()
+                .map(|row| row.fmap(|ex| f.fold_expr(ex)))
+                .collect(),
+        ),
+        ExprKind::Seq(exprs) => ExprKind::Seq(exprs.iter().map(|e| f.fold_expr(e)).collect()),
+        ExprKind::Var(sym) => ExprKind::Var(*sym),
+        ExprKind::Vector(exprs) => ExprKind::Vector(exprs.iter().map(|e| f.fold_expr(e)).collect()),
+    };
+    let ty = f.fold_type(expr.ty);
+    Expr::new(f.arena().exprs.alloc(kind), ty, expr.span)
+}
+
+pub fn fold_pat_children<'a, F: Folder<'a> + ?Sized>(f: &mut F, pat: &Pat<'a>) -> Pat<'a> {
+    let kind = match pat.kind {
+        PatKind::App(con, arg) => PatKind::App(*con, arg.map(|p| f.fold_pat(&p))),
+        PatKind::Const(c) => PatKind::Const(*c),
+        PatKind::Layer(sym, p) => PatKind::Layer(*sym, f.fold_pat(p)),
+        PatKind::Record(rows) => PatKind::Record(SortedRecord::new_unchecked(
+            rows.iter().map(|row| row.fmap(|p| f.fold_pat(p))).collect(),
+        )),
+        PatKind::Var(sym) => PatKind::Var(*sym),
+        PatKind::Vector(pats) => PatKind::Vector(pats.iter().map(|p| f.fold_pat(p)).collect()),
+        PatKind::Wild => PatKind::Wild,
+    };
+    let ty = f.fold_type(pat.ty);
+    Pat::new(f.arena().pats.alloc(kind), ty, pat.span)
+}
+
+/// Type variables and flexible records are left as-is, so that rebuilding a
+/// type never changes what it can later be unified with
+pub fn fold_type_children<'a, F: Folder<'a> + ?Sized>(f: &mut F, ty: &'a Type<'a>) -> &'a Type<'a> {
+    match ty {
+        Type::Var(_) | Type::Flex(_) => ty,
+        Type::Con(con, args) => {
+            let args = args.iter().map(|arg| f.fold_type(arg)).collect();
+            f.arena().types.alloc(Type::Con(*con, args))
+        }
+        Type::Record(rows) => {
+            let rows = rows
+                .iter()
+                .map(|row| Row {
+                    label: row.label,
+                    data: f.fold_type(row.data),
+                    span: row.span,
+                })
+                .collect();
+            f.arena()
+                .types
+                .alloc(Type::Record(SortedRecord::new_unchecked(rows)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pretty::Pretty;
+    use crate::testing::elaborate_src;
+    use sml_util::Const;
+
+    struct CountVars(usize);
+
+    impl<'a> Visitor<'a> for CountVars {
+        fn visit_expr(&mut self, expr: &Expr<'a>) {
+            if let ExprKind::Var(_) = expr.kind {
+                self.0 += 1;
+            }
+            walk_expr(self, expr)
+        }
+    }
+
+    struct ZeroInts<'a>(&'a CoreArena<'a>);
+
+    impl<'a> Folder<'a> for ZeroInts<'a> {
+        fn arena(&self) -> &'a CoreArena<'a> {
+            self.0
+        }
+
+        fn fold_expr(&mut self, expr: &Expr<'a>) -> Expr<'a> {
+            match expr.kind {
+                ExprKind::Const(Const::Int(_)) => Expr::new(
+                    self.0.exprs.alloc(ExprKind::Const(Const::Int(0))),
+                    expr.ty,
+                    expr.span,
+                ),
+                _ => fold_expr_children(self, expr),
+            }
+        }
+    }
+
+    #[test]
+    fn count_vars() {
+        elaborate_src("fun f x y = (y, x)", |_, _, decls| {
+            let mut counter = CountVars(0);
+            for decl in decls {
+                counter.visit_decl(decl);
+            }
+            // `y` and `x` in the body, plus the desugared argument tuple, the
+            // lifted match arm, and the scrutinee it is applied to
+            assert_eq!(counter.0, 6);
+        })
+    }
+
+    #[test]
+    fn zero_ints() {
+        elaborate_src("val x = (1, [2, 3], \"a\")", |interner, arena, decls| {
+            let decls = decls
+                .iter()
+                .map(|d| ZeroInts(arena).fold_decl(d))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                Pretty::new(interner).decls(&decls),
+                "val x = (0, [0, 0], \"a\")"
+            );
+        })
+    }
+}
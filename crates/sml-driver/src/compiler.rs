@@ -23,6 +23,7 @@ pub struct Compiler<'a> {
     pub measure: bool,
     pub verbosity: u8,
     pub stop_phase: String,
+    pub curried_fn: bool,
     pub times: Vec<String>,
 }
 
@@ -55,6 +56,7 @@ impl<'a> Phase<'a> for Parse {
         _: Self::Input,
     ) -> Result<Self::Output, Vec<Diagnostic>> {
         let mut p = Parser::new(&ctx.src, &mut ctx.interner);
+        p.curried_fn = ctx.curried_fn;
 
         match p.parse_decl() {
             Ok(decl) => Ok((decl, p.diags)),
@@ -83,7 +85,7 @@ impl<'a> Phase<'a> for Elaborate {
         diags.extend(check.diags);
 
         let decls = ctx.elab.elaborate_decl(&decl);
-        diags.extend(ctx.elab.diagnostics(&ctx.interner));
+        diags.extend(ctx.elab.take_diagnostics(&ctx.interner));
 
         for diag in &diags {
             match diag.level {
@@ -100,7 +102,7 @@ impl<'a> Phase<'a> for Elaborate {
     }
 
     fn output(&self, ctx: &mut Compiler<'a>, data: Self::Output) {
-        print_core_decl(ctx, &data)
+        print_core_decl(ctx, &data, true)
     }
 }
 
@@ -114,13 +116,14 @@ impl<'a> Phase<'a> for Monomorphize {
         ctx: &mut Compiler<'a>,
         input: Self::Input,
     ) -> Result<Self::Output, Vec<Diagnostic>> {
+        let input = sml_core::mono::monomorphize(ctx.arena, &input);
         let mut alpha = sml_core::alpha::Rename::new(&ctx.arena);
         let decls = input.iter().map(|decl| alpha.visit_decl(decl)).collect();
         Ok(decls)
     }
 
     fn output(&self, ctx: &mut Compiler<'a>, data: Self::Output) {
-        print_core_decl(ctx, &data)
+        print_core_decl(ctx, &data, false)
     }
 }
 
@@ -193,7 +196,7 @@ fn report(verb: u8, diags: Vec<Diagnostic>, src: &str) {
 
     if !bugs.is_empty() {
         for diag in bugs {
-            let _ = writeln!(out, "BUG {}", diag.report(verb, &src));
+            let _ = writeln!(out, "{}", diag.report(verb, &src));
         }
         panic!("aborting due to internal compiler bugs!");
     }
@@ -207,7 +210,10 @@ fn report(verb: u8, diags: Vec<Diagnostic>, src: &str) {
     }
 }
 
-fn print_core_decl<'a>(ctx: &Compiler<'a>, decls: &[sml_core::Decl<'a>]) {
+/// Print `decls` according to the verbosity. If `hide_gensyms` is set, the
+/// types of compiler-generated variables are left out. Alpha renaming turns
+/// every variable into a gensym, so this only makes sense before it
+fn print_core_decl<'a>(ctx: &Compiler<'a>, decls: &[sml_core::Decl<'a>], hide_gensyms: bool) {
     let io = std::io::stdout();
     let mut out = io.lock();
     match ctx.verbosity {
@@ -216,8 +222,12 @@ fn print_core_decl<'a>(ctx: &Compiler<'a>, decls: &[sml_core::Decl<'a>]) {
             // Print only types
             let mut pp = PrettyPrinter::new(&ctx.interner);
             for decl in decls {
-                use sml_core::{Decl, Rule};
+                use sml_core::{Decl, PatKind, Rule};
                 match decl {
+                    // Temporaries introduced by the elaborator, e.g. for the
+                    // right hand sides of `and` bindings, aren't user bindings
+                    Decl::Val(_, Rule { pat, .. })
+                        if hide_gensyms && matches!(pat.kind, PatKind::Var(Symbol::Gensym(_))) => {}
                     Decl::Val(_, Rule { pat, .. }) => {
                         pp.text("val ").print(pat).text(": ").print(pat.ty).line();
                     }
@@ -7,6 +7,7 @@ pub struct CompilerBuilder {
     measure: Option<bool>,
     verbosity: Option<u8>,
     phase: Option<String>,
+    curried_fn: Option<bool>,
 }
 
 impl CompilerBuilder {
@@ -19,6 +20,7 @@ impl CompilerBuilder {
             measure: self.measure.unwrap_or(false),
             verbosity: self.verbosity.unwrap_or(0),
             stop_phase: self.phase.unwrap_or_default(),
+            curried_fn: self.curried_fn.unwrap_or(false),
             times: Vec::new(),
         }
     }
@@ -37,6 +39,11 @@ impl CompilerBuilder {
         self.measure = Some(val);
         self
     }
+
+    pub fn curried_fn(mut self, val: bool) -> Self {
+        self.curried_fn = Some(val);
+        self
+    }
 }
 
 pub struct ArgParse {
@@ -65,6 +72,12 @@ impl ArgParse {
                     "--measure" => {
                         builder = builder.measure(true);
                     }
+                    "--curried-fn" => {
+                        builder = builder.curried_fn(true);
+                    }
+                    "--panic-on-bug" => {
+                        sml_util::diagnostics::panic_on_bug(true);
+                    }
                     "--phase" => {
                         let phase =
                             match stack.pop().expect("expected phase after --phase").as_ref() {
@@ -4,6 +4,11 @@ mod config;
 use config::ArgParse;
 
 fn main() {
+    // Deeply nested programs are parsed and elaborated recursively
+    sml_util::with_large_stack(run)
+}
+
+fn run() {
     let owned_arena = sml_core::arenas::OwnedCoreArena::new();
     let borrow = owned_arena.borrow();
 
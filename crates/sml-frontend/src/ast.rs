@@ -1,4 +1,4 @@
-use sml_util::interner::Symbol;
+use sml_util::interner::{Symbol, S_IT};
 use sml_util::span::{Span, Spanned};
 use sml_util::Const;
 
@@ -17,6 +17,26 @@ pub struct Typebind {
     pub ty: Type,
 }
 
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct Valbind {
+    pub pat: Pat,
+    pub expr: Expr,
+    /// The binding follows `rec`, which applies to the rest of the bindings
+    /// of the `val`
+    pub recursive: bool,
+}
+
+/// A specification in the body of a signature
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum SpecKind {
+    /// Abstract type specification: type 'tyvars tycon
+    Type(Vec<Symbol>, Symbol),
+    /// Manifest type specification: type 'tyvars tycon = ty
+    Manifest(Typebind),
+    /// Sharing constraint between abstract types: sharing type tycon1 = ... = tyconN
+    Sharing(Vec<Symbol>),
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
 pub enum Fixity {
     Infix,
@@ -35,6 +55,8 @@ pub enum DeclKind {
     /// If `N` > 1, then we have potentially mutually recursive datatype
     /// definitions
     Datatype(Vec<Datatype>),
+    /// Datatype replication: datatype tycon = datatype name
+    Replicate(Symbol, Symbol),
     /// If `N` > 1, then we have mutually rec. type defs
     Type(Vec<Typebind>),
     /// Allow for mutually recursive function defs:
@@ -42,11 +64,15 @@ pub enum DeclKind {
     ///             ...
     ///      and    fnbindingsN
     Function(Vec<Symbol>, Vec<Fun>),
-    Value(Vec<Symbol>, Pat, Expr),
+    /// Simultaneous value bindings, none of which can see the others:
+    /// val 'tyvars pat1 = expr1 and ... and patN = exprN
+    Value(Vec<Symbol>, Vec<Valbind>),
     Exception(Vec<Variant>),
     Fixity(Fixity, u8, Symbol),
     Local(Box<Decl>, Box<Decl>),
     Seq(Vec<Decl>),
+    /// signature sigid = sig specs end where type typbind1 ... where type typbindN
+    Signature(Symbol, Vec<Spec>, Vec<Typebind>),
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -57,6 +83,8 @@ pub enum TypeKind {
     Con(Symbol, Vec<Type>),
     /// Record type
     Record(Vec<Row<Type>>),
+    /// Wildcard type `_`, to be inferred during elaboration
+    Wild,
     /* Universally quantified type
      * Univ(Symbol, Box<Type>), */
 }
@@ -74,6 +102,9 @@ pub enum ExprKind {
     If(Box<Expr>, Box<Expr>, Box<Expr>),
     Let(Vec<Decl>, Box<Expr>),
     List(Vec<Expr>),
+    /// Identifier preceded by `op`, or alone in parentheses, which is never
+    /// treated as infix
+    Op(Symbol),
     Orelse(Box<Expr>, Box<Expr>),
     Primitive(Primitive),
     Raise(Box<Expr>),
@@ -81,6 +112,7 @@ pub enum ExprKind {
     Selector(Symbol),
     Seq(Vec<Expr>),
     Var(Symbol),
+    Vector(Vec<Expr>),
     While(Box<Expr>, Box<Expr>),
 }
 
@@ -92,6 +124,8 @@ pub enum PatKind {
     Ascribe(Box<Pat>, Box<Type>),
     /// Constant
     Const(Const),
+    /// Layered pattern `x as pat`
+    Layer(Symbol, Box<Pat>),
     /// A collection of pat applications, possibly including infix constructors
     FlatApp(Vec<Pat>),
     /// List pattern [pat1, ... patN]
@@ -101,6 +135,8 @@ pub enum PatKind {
 
     /// Variable binding
     Variable(Symbol),
+    /// Fixed-length vector pattern #[pat1, ... patN]
+    Vector(Vec<Pat>),
     /// Wildcard
     Wild,
 }
@@ -133,9 +169,22 @@ pub type Decl = Spanned<DeclKind>;
 pub type Type = Spanned<TypeKind>;
 pub type Expr = Spanned<ExprKind>;
 pub type Pat = Spanned<PatKind>;
+pub type Spec = Spanned<SpecKind>;
 pub type Variant = Row<Option<Type>>;
 pub type Fun = Spanned<Vec<FnBinding>>;
 
+/// An expression entered at top level is bound to `it`, as if it were the
+/// declaration `val it = exp`
+pub fn make_top_level_expr(exp: Expr) -> Decl {
+    let sp = exp.span;
+    let bind = Valbind {
+        pat: Pat::new(PatKind::Variable(S_IT), sp),
+        expr: exp,
+        recursive: false,
+    };
+    Decl::new(DeclKind::Value(Vec::new(), vec![bind]), sp)
+}
+
 /// Interestingly, MLton immediately desugars tuples during parsing, rather than
 /// during elaboration. We do the same
 pub fn make_record(v: Vec<Expr>) -> ExprKind {
@@ -1,4 +1,5 @@
 use super::tokens::*;
+use sml_util::diagnostics::Diagnostic;
 use sml_util::interner::*;
 use sml_util::span::{Location, Span, Spanned};
 use sml_util::Const;
@@ -12,6 +13,9 @@ pub struct Lexer<'s, 'sym> {
     input: Peekable<Chars<'s>>,
     current: Location,
     abs: usize,
+    /// Non-fatal errors encountered while lexing, such as out of range
+    /// integer literals
+    pub diags: Vec<Diagnostic>,
 }
 
 impl<'s, 'sym> Lexer<'s, 'sym> {
@@ -26,6 +30,7 @@ impl<'s, 'sym> Lexer<'s, 'sym> {
             },
             abs: 0,
             interner,
+            diags: Vec::new(),
         }
     }
 
@@ -48,7 +53,8 @@ impl<'s, 'sym> Lexer<'s, 'sym> {
             }
             Some(ch) => {
                 self.current.col += 1;
-                self.abs += 1;
+                // `abs` is a byte offset into `source`, used for slicing
+                self.abs += ch.len_utf8();
                 // self.current.abs += 1;
                 Some(ch)
             }
@@ -115,9 +121,26 @@ impl<'s, 'sym> Lexer<'s, 'sym> {
         }
     }
 
+    /// Is the next character a `.` that continues a qualified identifier,
+    /// such as `Int.toString`?
+    fn long_id_follows(&self) -> bool {
+        let mut ahead = self.input.clone();
+        ahead.next() == Some('.') && ahead.next().is_some_and(char::is_alphabetic)
+    }
+
     /// Lex a reserved keyword or identifier
     fn keyword(&mut self) -> Spanned<Token> {
-        let (word, sp) = self.consume_while(Self::valid_id_char);
+        let abs = self.abs;
+        let (word, mut sp) = self.consume_while(Self::valid_id_char);
+        if self.long_id_follows() {
+            // Qualified identifiers are interned as a single symbol
+            while self.long_id_follows() {
+                self.consume();
+                sp += self.consume_while(Self::valid_id_char).1;
+            }
+            let word = self.interner.intern(&self.source[abs..self.abs]);
+            return Spanned::new(Token::Id(word), sp);
+        }
         let word = self.interner.intern(word);
         let kind = match word {
             S_ABSTYPE => Token::Abstype,
@@ -158,6 +181,8 @@ impl<'s, 'sym> Lexer<'s, 'sym> {
             S_SIGNATURE => Token::Signature,
             S_STRUCT => Token::Struct,
             S_STRUCTURE => Token::Structure,
+            S_WHERE => Token::Where,
+            S_SHARING => Token::Sharing,
             _ => Token::Id(word),
         };
         Spanned::new(kind, sp)
@@ -175,16 +200,97 @@ impl<'s, 'sym> Lexer<'s, 'sym> {
         ))
     }
 
-    /// Lex a natural number
-    fn number(&mut self) -> Option<Spanned<Token>> {
-        // Since we peeked at least one numeric char, we should always
-        // have a string containing at least 1 single digit, as such
-        // it is safe to call unwrap() on str::parse<u32>
-        let (data, span) = self.consume_while(char::is_numeric);
-        let n = data.parse::<usize>().ok()?;
+    /// Lex an integer literal in either decimal or hexadecimal (`0x`)
+    /// notation, preceded by `~` if `negative` is set. Literals that don't fit
+    /// into an `i64` are reported, and saturate to the nearest bound so that
+    /// lexing can continue
+    fn number(&mut self, negative: bool) -> Option<Spanned<Token>> {
+        let start = self.current;
+        let abs = self.abs;
+        if negative {
+            self.consume();
+        }
+
+        let mut hex = self.source[self.abs..].chars();
+        let (radix, digits) = match (hex.next(), hex.next(), hex.next()) {
+            (Some('0'), Some('w'), Some(c))
+                if !negative
+                    && (c.is_ascii_digit()
+                        || (c == 'x'
+                            && hex.clone().next().is_some_and(|c| c.is_ascii_hexdigit()))) =>
+            {
+                return self.word();
+            }
+            (Some('0'), Some('x'), Some(c)) if c.is_ascii_hexdigit() => {
+                self.consume();
+                self.consume();
+                (16, self.consume_while(|c| c.is_ascii_hexdigit()).0)
+            }
+            _ => (10, self.consume_while(|c| c.is_ascii_digit()).0),
+        };
+        let span = Span::new(start, self.current);
+
+        let parsed = match negative {
+            true => i64::from_str_radix(&format!("-{}", digits), radix),
+            false => i64::from_str_radix(digits, radix),
+        };
+        let n = match parsed {
+            Ok(n) => n,
+            Err(_) => {
+                self.diags.push(Diagnostic::error(
+                    span,
+                    format!(
+                        "integer literal `{}` is out of range, must be between ~{} and {}",
+                        &self.source[abs..self.abs],
+                        (i64::MIN as i128).abs(),
+                        i64::MAX
+                    ),
+                ));
+                match negative {
+                    true => i64::MIN,
+                    false => i64::MAX,
+                }
+            }
+        };
         Some(Spanned::new(Token::Const(Const::Int(n)), span))
     }
 
+    /// Lex a word literal in either decimal (`0w`) or hexadecimal (`0wx`)
+    /// notation. Word literals are unsigned, and those that don't fit into a
+    /// `u64` are reported and saturate to `u64::MAX`
+    fn word(&mut self) -> Option<Spanned<Token>> {
+        let start = self.current;
+        let abs = self.abs;
+        self.consume();
+        self.consume();
+
+        let mut hex = self.source[self.abs..].chars();
+        let (radix, digits) = match (hex.next(), hex.next()) {
+            (Some('x'), Some(c)) if c.is_ascii_hexdigit() => {
+                self.consume();
+                (16, self.consume_while(|c| c.is_ascii_hexdigit()).0)
+            }
+            _ => (10, self.consume_while(|c| c.is_ascii_digit()).0),
+        };
+        let span = Span::new(start, self.current);
+
+        let n = match u64::from_str_radix(digits, radix) {
+            Ok(n) => n,
+            Err(_) => {
+                self.diags.push(Diagnostic::error(
+                    span,
+                    format!(
+                        "word literal `{}` is out of range, must be at most {}",
+                        &self.source[abs..self.abs],
+                        u64::MAX
+                    ),
+                ));
+                u64::MAX
+            }
+        };
+        Some(Spanned::new(Token::Const(Const::Word(n)), span))
+    }
+
     fn char_lit(&mut self) -> Option<Spanned<Token>> {
         let sp = self.current;
         match self.consume()? {
@@ -254,13 +360,17 @@ impl<'s, 'sym> Lexer<'s, 'sym> {
                 self.consume();
                 match self.peek() {
                     Some('"') => self.char_lit(),
+                    Some('[') => eat!(Token::HashBracket),
                     Some(_) => Some(Spanned::new(Token::Selector, Span::new(sp, self.current))),
                     _ => None,
                 }
             }
             '"' => self.string_lit(),
             x if x.is_ascii_alphabetic() => Some(self.keyword()),
-            x if x.is_numeric() => self.number(),
+            '~' if self.source[self.abs + 1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                self.number(true)
+            }
+            x if x.is_ascii_digit() => self.number(false),
             x if Self::valid_symbolic(x) => Some(self.symbolic()),
             ch => {
                 self.consume();
@@ -308,4 +418,102 @@ mod test {
             vec![_span(0, 7), _span(8, 11), _span(12, 14), _span(15, 18),]
         )
     }
+
+    #[test]
+    fn qualified_ids() {
+        let mut int = Interner::with_capacity(64);
+        let lex = Lexer::new("Int.toString x.y {a, ...}".chars(), &mut int);
+        let tks = lex.collect::<Vec<Spanned<Token>>>();
+        assert_eq!(tks[0], Spanned::new(Token::Id(S_INT_TOSTRING), _span(0, 12)));
+        assert_eq!(tks[1].span, _span(13, 16));
+        assert_eq!(tks[5], Spanned::new(Token::Flex, _span(21, 24)));
+    }
+
+    fn ints(input: &str) -> (Vec<Token>, usize) {
+        let mut int = Interner::with_capacity(64);
+        let mut lex = Lexer::new(input.chars(), &mut int);
+        let tks = lex.by_ref().map(|t| t.data).collect::<Vec<Token>>();
+        (tks, lex.diags.len())
+    }
+
+    #[test]
+    fn int_in_range() {
+        assert_eq!(
+            ints("9223372036854775807 ~9223372036854775808 0x7fffffffffffffff ~0x10"),
+            (
+                vec![
+                    Token::Const(Const::Int(i64::MAX)),
+                    Token::Const(Const::Int(i64::MIN)),
+                    Token::Const(Const::Int(i64::MAX)),
+                    Token::Const(Const::Int(-16)),
+                ],
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn int_overflow() {
+        assert_eq!(
+            ints("9223372036854775808 0x8000000000000000"),
+            (
+                vec![
+                    Token::Const(Const::Int(i64::MAX)),
+                    Token::Const(Const::Int(i64::MAX)),
+                ],
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn int_underflow() {
+        assert_eq!(
+            ints("~9223372036854775809"),
+            (vec![Token::Const(Const::Int(i64::MIN))], 1)
+        );
+    }
+
+    #[test]
+    fn literals_after_non_ascii() {
+        // Literals are sliced out of the source by byte offset, not by the
+        // number of characters before them
+        let (tks, errors) = ints("\"\u{e9}\u{1f600}\" 42 0wxff ~0x10");
+        assert_eq!(
+            &tks[1..],
+            &[
+                Token::Const(Const::Int(42)),
+                Token::Const(Const::Word(255)),
+                Token::Const(Const::Int(-16)),
+            ]
+        );
+        assert_eq!(errors, 0);
+    }
+
+    #[test]
+    fn word_literals() {
+        assert_eq!(
+            ints("0w42 0wxff 0w18446744073709551615 0w18446744073709551616"),
+            (
+                vec![
+                    Token::Const(Const::Word(42)),
+                    Token::Const(Const::Word(255)),
+                    Token::Const(Const::Word(u64::MAX)),
+                    Token::Const(Const::Word(u64::MAX)),
+                ],
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn negation_is_not_a_literal() {
+        let mut int = Interner::with_capacity(64);
+        let tilde = int.intern("~");
+        let lex = Lexer::new("~ 1".chars(), &mut int);
+        assert_eq!(
+            lex.map(|t| t.data).collect::<Vec<Token>>(),
+            vec![Token::IdS(tilde), Token::Const(Const::Int(1))]
+        );
+    }
 }
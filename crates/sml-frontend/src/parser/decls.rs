@@ -1,5 +1,24 @@
 use super::*;
 
+/// Tokens that can begin a top-level declaration, at which
+/// [`Parser::parse_program`] resumes parsing after a syntax error
+pub const SYNC: [Token; 14] = [
+    Token::Val,
+    Token::Fun,
+    Token::Type,
+    Token::Datatype,
+    Token::Exception,
+    Token::Infix,
+    Token::Infixr,
+    Token::Nonfix,
+    Token::Local,
+    Token::Signature,
+    Token::Structure,
+    Token::Functor,
+    Token::Open,
+    Token::Abstype,
+];
+
 impl<'s, 'sym> Parser<'s, 'sym> {
     fn type_binding(&mut self) -> Result<Typebind, Error> {
         let tyvars = self.type_var_seq()?;
@@ -28,11 +47,24 @@ impl<'s, 'sym> Parser<'s, 'sym> {
     }
 
     fn datatype(&mut self) -> Result<Datatype, Error> {
-        let mut span = self.current.span;
+        let span = self.current.span;
         let tyvars = self.type_var_seq()?;
         let tycon = self.expect_id()?;
         self.expect(Token::Equals)?;
-        let constructors = self.delimited(|p| p.variant(), Token::Bar)?;
+        self.datatype_constructors(span, tyvars, tycon)
+    }
+
+    fn datatype_constructors(
+        &mut self,
+        mut span: Span,
+        tyvars: Vec<Symbol>,
+        tycon: Symbol,
+    ) -> Result<Datatype, Error> {
+        let constructors = match self.current() {
+            Token::Id(_) | Token::IdS(_) => self.delimited(|p| p.variant(), Token::Bar)?,
+            // Leave it to the elaborator to report a datatype without constructors
+            _ => Vec::new(),
+        };
         span += self.prev;
         Ok(Datatype {
             tycon,
@@ -42,19 +74,68 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         })
     }
 
+    /// datatype ::=   datatype datbind
+    ///                 datatype tycon = datatype longtycon
     fn parse_decl_datatype(&mut self) -> Result<DeclKind, Error> {
         self.expect(Token::Datatype)?;
-        let bindings = self.delimited(|p| p.datatype(), Token::And)?;
+        let span = self.current.span;
+        let tyvars = self.type_var_seq()?;
+        let tycon = self.expect_id()?;
+        self.expect(Token::Equals)?;
+        if self.bump_if(Token::Datatype) {
+            if !tyvars.is_empty() {
+                self.diags.push(Diagnostic::error(
+                    span,
+                    "datatype replication can't bind type variables".to_string(),
+                ));
+            }
+            let name = self.once(
+                |p| p.expect_id(),
+                "expected type constructor after `datatype`",
+            )?;
+            return Ok(DeclKind::Replicate(tycon, name));
+        }
+        let mut bindings = vec![self.datatype_constructors(span, tyvars, tycon)?];
+        while self.bump_if(Token::And) {
+            bindings.push(self.datatype()?);
+        }
         Ok(DeclKind::Datatype(bindings))
     }
 
-    fn parse_decl_val(&mut self) -> Result<DeclKind, Error> {
-        self.expect(Token::Val)?;
-        let tyvars = self.type_var_seq()?;
+    fn value_binding(&mut self) -> Result<Valbind, Error> {
+        let recursive = self.bump_if(Token::Rec);
         let pat = self.parse_pattern()?;
         self.expect(Token::Equals)?;
         let expr = self.parse_expr()?;
-        Ok(DeclKind::Value(tyvars, pat, expr))
+        Ok(Valbind {
+            pat,
+            expr,
+            recursive,
+        })
+    }
+
+    fn parse_decl_val(&mut self) -> Result<DeclKind, Error> {
+        self.expect(Token::Val)?;
+        let tyvars = self.type_var_seq()?;
+        let mut bindings = self.delimited(|p| p.value_binding(), Token::And)?;
+        let mut recursive = false;
+        for vb in &mut bindings {
+            recursive |= vb.recursive;
+            vb.recursive = recursive;
+        }
+        Ok(DeclKind::Value(tyvars, bindings))
+    }
+
+    /// do exp, which is sugar for val () = exp
+    fn parse_decl_do(&mut self) -> Result<DeclKind, Error> {
+        self.expect(Token::Do)?;
+        let expr = self.once(|p| p.parse_expr(), "expected expression after `do`")?;
+        let bind = Valbind {
+            pat: Pat::new(PatKind::Const(Const::Unit), expr.span),
+            expr,
+            recursive: false,
+        };
+        Ok(DeclKind::Value(Vec::new(), vec![bind]))
     }
 
     fn parse_fun_binding(&mut self) -> Result<FnBinding, Error> {
@@ -106,8 +187,14 @@ impl<'s, 'sym> Parser<'s, 'sym> {
 
         let num = match self.current() {
             Token::Const(Const::Int(i)) => {
+                if !(0..=9).contains(&i) {
+                    self.diags.push(Diagnostic::error(
+                        self.current.span,
+                        "fixity precedence must be between 0 and 9",
+                    ));
+                }
                 self.bump();
-                i as u8
+                i.clamp(0, 9) as u8
             }
             _ => 0,
         };
@@ -127,15 +214,60 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         Ok(DeclKind::Local(Box::new(a), Box::new(b)))
     }
 
+    /// spec ::=    type tyvars tycon
+    ///             type tyvars tycon = ty
+    ///             sharing type tycon1 = ... = tyconN
+    fn spec(&mut self) -> Result<Spec, Error> {
+        self.spanned(|p| {
+            if p.bump_if(Token::Sharing) {
+                p.expect(Token::Type)?;
+                let mut tycons = vec![p.expect_id()?];
+                p.expect(Token::Equals)?;
+                tycons.extend(p.delimited(|q| q.expect_id(), Token::Equals)?);
+                return Ok(SpecKind::Sharing(tycons));
+            }
+            p.expect(Token::Type)?;
+            let tyvars = p.type_var_seq()?;
+            let tycon = p.expect_id()?;
+            if p.bump_if(Token::Equals) {
+                let ty = p.parse_type()?;
+                return Ok(SpecKind::Manifest(Typebind { tycon, tyvars, ty }));
+            }
+            Ok(SpecKind::Type(tyvars, tycon))
+        })
+    }
+
+    /// sigdec ::=  signature sigid = sig spec* end (where type typbind)*
+    fn parse_decl_signature(&mut self) -> Result<DeclKind, Error> {
+        self.expect(Token::Signature)?;
+        let name = self.expect_id()?;
+        self.expect(Token::Equals)?;
+        self.expect(Token::Sig)?;
+        let mut specs = Vec::new();
+        while let Token::Type | Token::Sharing = self.current() {
+            specs.push(self.spec()?);
+            self.bump_if(Token::Semi);
+        }
+        self.expect_try_recover(Token::End);
+        let mut wheres = Vec::new();
+        while self.bump_if(Token::Where) {
+            self.expect(Token::Type)?;
+            wheres.push(self.type_binding()?);
+        }
+        Ok(DeclKind::Signature(name, specs, wheres))
+    }
+
     fn parse_decl_atom(&mut self) -> Result<Decl, Error> {
         match self.current() {
             Token::Fun => self.spanned(|p| p.parse_decl_fun()),
             Token::Val => self.spanned(|p| p.parse_decl_val()),
+            Token::Do => self.spanned(|p| p.parse_decl_do()),
             Token::Type => self.spanned(|p| p.parse_decl_type()),
             Token::Datatype => self.spanned(|p| p.parse_decl_datatype()),
             Token::Exception => self.spanned(|p| p.parse_decl_exn()),
             Token::Infix | Token::Infixr | Token::Nonfix => self.spanned(|p| p.fixity()),
             Token::Local => self.spanned(|p| p.parse_decl_local()),
+            Token::Signature => self.spanned(|p| p.parse_decl_signature()),
             Token::EOF => self.error(ErrorKind::EOF),
             _ => {
                 // self.diags.push(Diagnostic::error(
@@ -150,29 +282,39 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         }
     }
 
+    /// Recover from a syntax error in a declaration that began at `start`, by
+    /// skipping ahead to the next token in [`SYNC`]
+    fn synchronize(&mut self, start: Span) {
+        // Make sure that we always make progress, even if the offending
+        // token is itself in the synchronization set
+        if self.current.span == start {
+            self.bump();
+        }
+        while !SYNC.contains(&self.current()) && self.current() != Token::EOF {
+            self.bump();
+        }
+    }
+
     pub fn parse_decl(&mut self) -> Result<Decl, Error> {
         let mut seq = Vec::new();
+        let mut recovered = false;
         let span = self.current.span;
         self.bump_if(Token::Semi);
         loop {
+            let (start, diags) = (self.current.span, self.diags.len());
             match self.parse_decl_atom() {
                 Ok(d) => seq.push(d),
                 Err(Error {
                     kind: ErrorKind::EOF,
                     ..
                 }) => break,
-                Err(_) => match self.parse_expr() {
-                    Ok(exp) => {
-                        self.diags.push(Diagnostic::warn(
-                            exp.span,
-                            "top level expressions are not supported! emitting `val _ = ...`",
-                        ));
-                        let sp = exp.span;
-                        seq.push(Decl::new(
-                            DeclKind::Value(Vec::new(), Pat::new(PatKind::Wild, sp), exp),
-                            sp,
-                        ));
-                    }
+                // Only fall back to an expression if we didn't start parsing
+                // a declaration
+                Err(Error {
+                    kind: ErrorKind::ExpectedDecl,
+                    ..
+                }) if self.current.span == start => match self.parse_expr() {
+                    Ok(exp) => seq.push(make_top_level_expr(exp)),
                     Err(err) => {
                         match err.kind {
                             ErrorKind::EOF | ErrorKind::ExpectedExpr => {}
@@ -181,14 +323,320 @@ impl<'s, 'sym> Parser<'s, 'sym> {
                         break;
                     }
                 },
+                // Report the broken declaration, unless it has already been
+                // explained by a targeted diagnostic, and skip to the next
+                // declaration rather than reporting the rest of it as well
+                Err(err) => {
+                    if self.diags.len() == diags {
+                        self.diags.push(err.to_diagnostic());
+                    }
+                    recovered = true;
+                    self.synchronize(start);
+                }
             }
             self.bump_if(Token::Semi);
         }
 
         match seq.len() {
-            0 => self.error(ErrorKind::ExpectedDecl),
+            0 if !recovered => self.error(ErrorKind::ExpectedDecl),
             1 => Ok(seq.pop().unwrap()),
             _ => Ok(Decl::new(DeclKind::Seq(seq), span + self.prev)),
         }
     }
+
+    /// Parse an entire source file into a list of top-level declarations.
+    ///
+    /// Unlike [`Parser::parse_decl`], a syntax error doesn't end parsing: the
+    /// error is recorded, and the parser skips ahead to the next token in
+    /// [`SYNC`] before carrying on, so that every broken declaration in the
+    /// file is reported
+    pub fn parse_program(&mut self) -> (Vec<Decl>, Vec<Error>) {
+        let mut decls = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            while self.bump_if(Token::Semi) {}
+            let start = self.current.span;
+            let err = match self.parse_decl_atom() {
+                Ok(decl) => {
+                    decls.push(decl);
+                    continue;
+                }
+                Err(Error {
+                    kind: ErrorKind::EOF,
+                    ..
+                }) => break,
+                // Only fall back to an expression if we didn't start parsing
+                // a declaration
+                Err(
+                    err @ Error {
+                        kind: ErrorKind::ExpectedDecl,
+                        ..
+                    },
+                ) if self.current.span == start => match self.parse_expr() {
+                    Ok(exp) => {
+                        decls.push(make_top_level_expr(exp));
+                        continue;
+                    }
+                    Err(_) => err,
+                },
+                Err(err) => err,
+            };
+            errors.push(err);
+            self.synchronize(start);
+        }
+        (decls, errors)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recover_from_broken_decl() {
+        let mut interner = Interner::with_capacity(64);
+        let src = "val x = 1\nfun f = = 2\nval y = x";
+        let (decls, errors) = Parser::new(src, &mut interner).parse_program();
+        assert_eq!(decls.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span.start.line, 1);
+        match &decls[1].data {
+            DeclKind::Value(_, vbs) => assert_eq!(vbs.len(), 1),
+            _ => panic!("expected a `val` declaration"),
+        }
+    }
+
+    #[test]
+    fn parse_decl_skips_to_next_decl_after_hint() {
+        let mut interner = Interner::with_capacity(64);
+        let mut p = Parser::new("val f = fn x\nval g = 1", &mut interner);
+        let decl = p.parse_decl().unwrap();
+        assert_eq!(p.diags.len(), 1);
+        assert_eq!(
+            p.diags[0].primary.info,
+            "expected `=>` after function pattern"
+        );
+        match decl.data {
+            DeclKind::Value(_, vbs) => assert_eq!(vbs.len(), 1),
+            _ => panic!("expected the following `val` declaration"),
+        }
+    }
+
+    #[test]
+    fn parse_decl_reports_half_parsed_decl() {
+        let mut interner = Interner::with_capacity(64);
+        let mut p = Parser::new("val x = 1\nexception 1 2\nval y = 2", &mut interner);
+        let decl = p.parse_decl().unwrap();
+        assert_eq!(p.diags.len(), 1);
+        assert_eq!(p.diags[0].primary.span.start.line, 1);
+        match decl.data {
+            DeclKind::Seq(decls) => {
+                assert_eq!(decls.len(), 2);
+                for d in decls {
+                    match d.data {
+                        DeclKind::Value(_, vbs) => {
+                            assert_ne!(vbs[0].pat.data, PatKind::Variable(S_IT))
+                        }
+                        _ => panic!("expected a `val` declaration"),
+                    }
+                }
+            }
+            _ => panic!("expected a declaration sequence"),
+        }
+    }
+
+    #[test]
+    fn recover_at_sync_token() {
+        let mut interner = Interner::with_capacity(64);
+        let src = "val x = \nval y = 2; structure S = struct end; datatype t = A";
+        let (decls, errors) = Parser::new(src, &mut interner).parse_program();
+        assert_eq!(decls.len(), 2);
+        assert_eq!(errors.len(), 2);
+    }
+
+    fn val_exprs(src: &str) -> Vec<ExprKind> {
+        let mut interner = Interner::with_capacity(64);
+        let (decls, errors) = Parser::new(src, &mut interner).parse_program();
+        assert!(errors.is_empty());
+        decls
+            .into_iter()
+            .map(|d| match d.data {
+                DeclKind::Value(_, mut vbs) => vbs.remove(0).expr.data,
+                _ => panic!("expected a `val` declaration"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn op_equals_is_a_variable() {
+        let exprs = val_exprs("val f = op =\nval g = op +");
+        assert_eq!(exprs[0], ExprKind::Op(S_EQUAL));
+        assert_eq!(exprs[1], ExprKind::Op(S_PLUS));
+    }
+
+    #[test]
+    fn two_token_lookahead() {
+        let exprs =
+            val_exprs("val a = #[1]\nval b = #foo\nval c = (+)\nval d = (x)\nval e = (x, y)");
+        assert!(matches!(&exprs[0], ExprKind::Vector(xs) if xs.len() == 1));
+        assert!(matches!(exprs[1], ExprKind::Selector(_)));
+        assert_eq!(exprs[2], ExprKind::Op(S_PLUS));
+        assert!(matches!(exprs[3], ExprKind::Op(_)));
+        assert!(matches!(exprs[4], ExprKind::Record(_)));
+    }
+
+    #[test]
+    fn equals_in_bindings() {
+        let exprs = val_exprs("val x = 1 = 2\nval y = {a = 1}");
+        match &exprs[0] {
+            ExprKind::FlatApp(xs) => {
+                let xs = xs.iter().map(|x| &x.data).collect::<Vec<_>>();
+                assert_eq!(
+                    xs,
+                    vec![
+                        &ExprKind::Const(Const::Int(1)),
+                        &ExprKind::Var(S_EQUAL),
+                        &ExprKind::Const(Const::Int(2)),
+                    ]
+                );
+            }
+            _ => panic!("expected an infix expression"),
+        }
+        match &exprs[1] {
+            ExprKind::Record(rows) => assert_eq!(rows.len(), 1),
+            _ => panic!("expected a record expression"),
+        }
+    }
+
+    #[test]
+    fn missing_darrow_names_the_pattern() {
+        let mut interner = Interner::with_capacity(64);
+        let mut p = Parser::new("val f = fn x\nval g = case 1 of y", &mut interner);
+        let _ = p.parse_program();
+        let infos = p
+            .diags
+            .iter()
+            .map(|d| d.primary.info.as_str())
+            .collect::<Vec<_>>();
+        assert!(infos.contains(&"expected `=>` after function pattern"));
+        assert!(infos.contains(&"expected `=>` after `case` pattern"));
+        assert!(!infos.iter().any(|i| i.contains("DArrow")));
+    }
+
+    /// Render the `andalso`/`orelse`/`if` structure of an expression, with
+    /// applications shown as `app` and anything else as `_`
+    fn shape(expr: &ExprKind) -> String {
+        match expr {
+            ExprKind::Andalso(a, b) => format!("(andalso {} {})", shape(&a.data), shape(&b.data)),
+            ExprKind::Orelse(a, b) => format!("(orelse {} {})", shape(&a.data), shape(&b.data)),
+            ExprKind::If(a, b, c) => format!(
+                "(if {} {} {})",
+                shape(&a.data),
+                shape(&b.data),
+                shape(&c.data)
+            ),
+            ExprKind::Constraint(e, _) => format!("(: {})", shape(&e.data)),
+            ExprKind::App(_, _) | ExprKind::FlatApp(_) => "app".into(),
+            _ => "_".into(),
+        }
+    }
+
+    #[test]
+    fn boolean_operator_precedence() {
+        let cases = [
+            ("a orelse b andalso c", "(orelse _ (andalso _ _))"),
+            ("a andalso b orelse c", "(orelse (andalso _ _) _)"),
+            (
+                "a andalso b orelse c andalso d",
+                "(orelse (andalso _ _) (andalso _ _))",
+            ),
+            ("a andalso b andalso c", "(andalso _ (andalso _ _))"),
+            ("a orelse b orelse c", "(orelse _ (orelse _ _))"),
+            (
+                "f x andalso a = b orelse g y",
+                "(orelse (andalso app app) app)",
+            ),
+            ("if f x then a else b orelse c", "(if app _ (orelse _ _))"),
+            (
+                "a orelse if b then c else d andalso e",
+                "(orelse _ (if _ _ (andalso _ _)))",
+            ),
+            ("x : bool orelse y", "(orelse (: _) _)"),
+        ];
+        let src = cases
+            .iter()
+            .map(|(e, _)| format!("val it = {}", e))
+            .collect::<Vec<_>>()
+            .join("\n");
+        for (expr, (src, expected)) in val_exprs(&src).iter().zip(cases.iter()) {
+            assert_eq!(shape(expr), *expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn curried_fn_is_an_extension() {
+        let mut interner = Interner::with_capacity(64);
+        let src = "val f = fn x y => x\nval g = fn x => x";
+        let mut fns = |curried_fn| {
+            let mut p = Parser::new(src, &mut interner);
+            p.curried_fn = curried_fn;
+            let (decls, errors) = p.parse_program();
+            assert!(errors.is_empty());
+            decls
+                .into_iter()
+                .map(|d| match d.data {
+                    DeclKind::Value(_, mut vbs) => match vbs.remove(0).expr.data {
+                        ExprKind::Fn(mut rules) => rules.remove(0),
+                        _ => panic!("expected a `fn` expression"),
+                    },
+                    _ => panic!("expected a `val` declaration"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let rules = fns(false);
+        assert!(matches!(rules[0].pat.data, PatKind::FlatApp(_)));
+        assert!(matches!(rules[1].pat.data, PatKind::Variable(_)));
+
+        let rules = fns(true);
+        assert!(matches!(rules[0].pat.data, PatKind::Variable(_)));
+        match &rules[0].expr.data {
+            ExprKind::Fn(inner) => assert!(matches!(inner[0].expr.data, ExprKind::Var(_))),
+            _ => panic!("expected a curried `fn`"),
+        }
+        assert!(matches!(rules[1].pat.data, PatKind::Variable(_)));
+        assert!(matches!(rules[1].expr.data, ExprKind::Var(_)));
+    }
+
+    /// Whether parsing `src` reports `message`, and nothing else
+    fn too_deep(src: &str, message: &str) -> bool {
+        sml_util::with_large_stack(|| {
+            let mut interner = Interner::with_capacity(64);
+            let mut p = Parser::new(src, &mut interner);
+            let _ = p.parse_decl();
+            p.diags.len() == 1 && p.diags[0].primary.info == message
+        })
+    }
+
+    #[test]
+    fn deeply_nested_expr_is_an_error() {
+        let src = format!("val x = {}1{}", "(".repeat(30_000), ")".repeat(30_000));
+        assert!(too_deep(&src, "expression nesting too deep"));
+    }
+
+    #[test]
+    fn deeply_nested_pat_is_an_error() {
+        let src = format!("val {}x{} = 1", "(".repeat(30_000), ")".repeat(30_000));
+        assert!(too_deep(&src, "pattern nesting too deep"));
+    }
+
+    #[test]
+    fn deeply_nested_type_is_an_error() {
+        let src = format!(
+            "val x : {}int{} = 1",
+            "(".repeat(30_000),
+            ")".repeat(30_000)
+        );
+        assert!(too_deep(&src, "type nesting too deep"));
+    }
 }
@@ -10,7 +10,7 @@ impl<'s, 'sym> Parser<'s, 'sym> {
 
     fn record_row(&mut self) -> Result<Row<Expr>, Error> {
         let mut span = self.current.span;
-        let label = self.expect_id()?;
+        let label = self.expect_label()?;
         self.expect_try_recover(Token::Equals);
         let data = self.once(|p| p.parse_expr(), "missing expr in record row")?;
         span += self.prev;
@@ -43,11 +43,19 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         Ok(ExprKind::Let(decls, Box::new(t2)))
     }
 
-    fn case_arm(&mut self) -> Result<Rule, Error> {
+    /// Parse a match rule `pat => exp`, where `what` describes the pattern in
+    /// the error reported for a missing `=>`
+    fn case_arm(&mut self, what: &str) -> Result<Rule, Error> {
         let pat = self.once(|p| p.parse_pattern(), "missing pattern in case arm")?;
-        self.expect(Token::DArrow)?;
+        if self.current() != Token::DArrow {
+            self.diags.push(Diagnostic::error(
+                Span::new(pat.span.end, pat.span.end),
+                format!("expected `=>` after {}", what),
+            ));
+            return self.error(ErrorKind::ExpectedToken(Token::DArrow));
+        }
+        self.bump();
         let expr = self.once(|p| p.parse_expr(), "missing expression in case arm")?;
-        self.bump_if(Token::Comma);
         Ok(Rule {
             span: pat.span + expr.span,
             pat,
@@ -60,17 +68,39 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         let expr = self.once(|p| p.parse_expr(), "missing case expression")?;
         self.expect(Token::Of)?;
         self.bump_if(Token::Bar);
-        let arms = self.delimited(|p| p.case_arm(), Token::Bar)?;
+        let arms = self.delimited(|p| p.case_arm("`case` pattern"), Token::Bar)?;
         self.expect_try_recover(Token::End);
         Ok(ExprKind::Case(Box::new(expr), arms))
     }
 
     fn lambda_expr(&mut self) -> Result<ExprKind, Error> {
         self.expect(Token::Fn)?;
-        let arms = self.delimited(|p| p.case_arm(), Token::Bar)?;
+        let mut arms = self.delimited(|p| p.case_arm("function pattern"), Token::Bar)?;
+        if self.curried_fn && arms.len() == 1 {
+            let rule = arms.pop().unwrap();
+            return Ok(Self::curry_rule(rule));
+        }
         Ok(ExprKind::Fn(arms))
     }
 
+    /// Desugar `fn p1 p2 ... pn => e` into `fn p1 => fn p2 => ... e`. A rule
+    /// with a single pattern is left unchanged
+    fn curry_rule(rule: Rule) -> ExprKind {
+        let mut pats = match rule.pat.data {
+            PatKind::FlatApp(pats) => pats,
+            _ => return ExprKind::Fn(vec![rule]),
+        };
+        let mut expr = rule.expr;
+        while pats.len() > 1 {
+            let pat = pats.pop().unwrap();
+            let span = pat.span + expr.span;
+            expr = Expr::new(ExprKind::Fn(vec![Rule { pat, expr, span }]), span);
+        }
+        let pat = pats.pop().unwrap();
+        let span = pat.span + expr.span;
+        ExprKind::Fn(vec![Rule { pat, expr, span }])
+    }
+
     fn while_expr(&mut self) -> Result<ExprKind, Error> {
         self.expect(Token::While)?;
         let test = self.parse_expr()?;
@@ -100,22 +130,39 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         if self.bump_if(Token::RParen) {
             return Ok(ExprKind::Const(Const::Unit));
         }
+        // A parenthesized identifier, such as `(+)`, loses any infix status,
+        // just like `op +`
+        if let Token::Id(_) | Token::IdS(_) | Token::Equals = self.current() {
+            if let Some(Token::RParen) = self.peek().map(|t| t.data) {
+                let sym = self.value_id()?;
+                self.expect(Token::RParen)?;
+                return Ok(ExprKind::Op(sym));
+            }
+        }
         let first = self.parse_expr()?;
-        let expected = match self.current() {
-            Token::Semi => Token::Semi,
-            Token::Comma => Token::Comma,
+        let (expected, sep, what) = match self.current() {
+            Token::Semi => (Token::Semi, ';', "sequence"),
+            Token::Comma => (Token::Comma, ',', "tuple"),
             _ => {
                 self.expect_try_recover(Token::RParen);
                 return Ok(first.data);
             }
         };
-        self.bump();
         let mut v = vec![first];
-        while let Ok(x) = self.parse_expr() {
-            v.push(x);
-            if !self.bump_if(expected) {
+        while self.bump_if(expected) {
+            if self.current() == Token::RParen {
+                // Report the dangling separator, but otherwise carry on as if
+                // it wasn't there
+                self.diags.push(Diagnostic::error(
+                    self.prev,
+                    format!("trailing `{}` in {} expression", sep, what),
+                ));
                 break;
             }
+            v.push(self.once(
+                |p| p.parse_expr(),
+                "expected expression after separator",
+            )?);
         }
         self.expect(Token::RParen)?;
         match v.len() {
@@ -157,19 +204,36 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         }
     }
 
+    /// Parse an identifier in value position, which may be `=`
+    fn value_id(&mut self) -> Result<Symbol, Error> {
+        match self.current() {
+            Token::Equals => {
+                self.bump();
+                Ok(S_EQUAL)
+            }
+            _ => self.expect_id(),
+        }
+    }
+
     /// atexp ::=   constant
     ///             id
+    ///             op id
     ///             { [label = exp] }
     ///             ()
     ///             ( exp, ... expN )
     ///             ( exp )
     ///             let decl in exp, ... expN end
+    ///             #[ exp, ... expN ]
     fn atomic_expr(&mut self) -> Result<Expr, Error> {
         let span = self.current.span;
         match self.current.data {
-            Token::Id(_) | Token::IdS(_) => {
-                self.expect_id().map(|e| Expr::new(ExprKind::Var(e), span))
+            Token::Id(_) | Token::IdS(_) | Token::Equals => {
+                self.value_id().map(|e| Expr::new(ExprKind::Var(e), span))
             }
+            Token::Op => self.spanned(|p| {
+                p.expect(Token::Op)?;
+                p.value_id().map(ExprKind::Op)
+            }),
             Token::Primitive => self.spanned(|p| p.primitive()),
             Token::Let => self.spanned(|p| p.let_binding()),
             Token::Selector => self.spanned(|p| p.selector()),
@@ -188,6 +252,18 @@ impl<'s, 'sym> Parser<'s, 'sym> {
 
                 Ok(xs)
             }),
+            Token::HashBracket => self.spanned(|p| {
+                p.expect(Token::HashBracket)?;
+                if p.bump_if(Token::RBracket) {
+                    return Ok(ExprKind::Vector(Vec::new()));
+                }
+                let xs = p
+                    .delimited(|q| q.parse_expr(), Token::Comma)
+                    .map(ExprKind::Vector)?;
+                p.expect_try_recover(Token::RBracket);
+
+                Ok(xs)
+            }),
 
             _ => self.error(ErrorKind::ExpectedExpr),
         }
@@ -212,6 +288,40 @@ impl<'s, 'sym> Parser<'s, 'sym> {
     ///             fn x
     ///             infix
     pub fn parse_expr(&mut self) -> Result<Expr, Error> {
+        self.nested("expression", |p| p.parse_expr_nested())
+    }
+
+    fn parse_expr_nested(&mut self) -> Result<Expr, Error> {
+        // A chain of `andalso` and `orelse` is parsed with a loop rather than
+        // by recursion, so that its length doesn't count against `max_depth`.
+        // `andalso` binds tighter than `orelse`, so the operands are gathered
+        // into groups joined by `andalso`, and the groups are then joined by
+        // `orelse`. Both operators associate to the right
+        let mut groups = vec![vec![self.operand_expr()?]];
+        loop {
+            let (op, msg) = match self.current() {
+                Token::Orelse => (Token::Orelse, "expected expression after orelse"),
+                Token::Andalso => (Token::Andalso, "expected expression after andalso"),
+                _ => break,
+            };
+            self.bump();
+            let operand = self.once(|p| p.operand_expr(), msg)?;
+            match op {
+                Token::Orelse => groups.push(vec![operand]),
+                _ => groups.last_mut().unwrap().push(operand),
+            }
+        }
+
+        let disjuncts = groups
+            .into_iter()
+            .map(|group| fold_right(group, ExprKind::Andalso))
+            .collect();
+        Ok(fold_right(disjuncts, ExprKind::Orelse))
+    }
+
+    /// An operand of `andalso` or `orelse`, optionally followed by a type
+    /// constraint or a `handle`
+    fn operand_expr(&mut self) -> Result<Expr, Error> {
         let expr = match self.current() {
             Token::Case => self.spanned(|p| p.case_expr()),
             Token::Fn => self.spanned(|p| p.lambda_expr()),
@@ -233,29 +343,22 @@ impl<'s, 'sym> Parser<'s, 'sym> {
             }
             Token::Handle => {
                 self.bump();
-                let snd = self.spanned(|p| p.delimited(|p| p.case_arm(), Token::Bar))?;
+                let snd =
+                    self.spanned(|p| p.delimited(|p| p.case_arm("handler pattern"), Token::Bar))?;
                 let sp = expr.span + snd.span;
                 Ok(Expr::new(ExprKind::Handle(Box::new(expr), snd.data), sp))
             }
-            Token::Orelse => {
-                self.bump();
-                let snd = self.once(|p| p.parse_expr(), "expected expression after orelse")?;
-                let sp = expr.span + snd.span;
-                Ok(Expr::new(
-                    ExprKind::Orelse(Box::new(expr), Box::new(snd)),
-                    sp,
-                ))
-            }
-            Token::Andalso => {
-                self.bump();
-                let snd = self.once(|p| p.parse_expr(), "expected expression after andalso")?;
-                let sp = expr.span + snd.span;
-                Ok(Expr::new(
-                    ExprKind::Andalso(Box::new(expr), Box::new(snd)),
-                    sp,
-                ))
-            }
             _ => Ok(expr),
         }
     }
 }
+
+/// Join a non-empty list of expressions with a right associative operator
+fn fold_right(mut exprs: Vec<Expr>, op: fn(Box<Expr>, Box<Expr>) -> ExprKind) -> Expr {
+    let mut expr = exprs.pop().unwrap();
+    while let Some(lhs) = exprs.pop() {
+        let sp = lhs.span + expr.span;
+        expr = Expr::new(op(Box::new(lhs), Box::new(expr)), sp);
+    }
+    expr
+}
@@ -5,7 +5,6 @@ use sml_util::diagnostics::Diagnostic;
 use sml_util::interner::*;
 use sml_util::span::{Span, Spanned};
 use sml_util::Const;
-use std::iter::Peekable;
 mod decls;
 mod exprs;
 mod pats;
@@ -13,10 +12,24 @@ pub mod precedence;
 mod types;
 
 pub struct Parser<'s, 'sym> {
-    tokens: Peekable<Lexer<'s, 'sym>>,
+    tokens: Lexer<'s, 'sym>,
+    /// A single token of lookahead past `current`
+    peeked: Option<Option<Spanned<Token>>>,
     current: Spanned<Token>,
     prev: Span,
     pub diags: Vec<Diagnostic>,
+    /// Current number of nested expressions, patterns and types being parsed
+    depth: usize,
+    /// Maximum nesting depth of expressions, patterns and types, past which
+    /// parsing fails with [`ErrorKind::TooDeep`] rather than overflowing the
+    /// stack
+    pub max_depth: usize,
+    /// Extension to the SML grammar: a `fn` with a single rule whose pattern
+    /// is a sequence of atomic patterns, `fn p1 p2 => e`, is parsed as the
+    /// curried `fn p1 => fn p2 => e`, like the arguments of a `fun` clause.
+    /// This changes the meaning of `fn SOME x => x`, which is why it is off
+    /// by default
+    pub curried_fn: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -27,6 +40,7 @@ pub enum ErrorKind {
     ExpectedPat,
     ExpectedExpr,
     ExpectedDecl,
+    TooDeep(&'static str),
     Internal,
     EOF,
 }
@@ -51,6 +65,7 @@ impl Error {
             ExpectedPat => format!("expected pattern, but encountered {:?}", self.token),
             ExpectedExpr => format!("expected expression, but encountered {:?}", self.token),
             ExpectedDecl => format!("expected declaration, but encountered {:?}", self.token),
+            TooDeep(what) => format!("{} nesting too deep", what),
             Internal => format!("internal parser error! last token was {:?}", self.token),
             EOF => "EOF?".to_string(),
         };
@@ -66,10 +81,14 @@ macro_rules! diag {
 impl<'s, 'sym> Parser<'s, 'sym> {
     pub fn new(input: &'s str, interner: &'sym mut Interner) -> Parser<'s, 'sym> {
         let mut p = Parser {
-            tokens: Lexer::new(input.chars(), interner).peekable(),
+            tokens: Lexer::new(input.chars(), interner),
+            peeked: None,
             current: Spanned::new(Token::EOF, Span::zero()),
             prev: Span::zero(),
             diags: Vec::new(),
+            depth: 0,
+            max_depth: 1000,
+            curried_fn: false,
         };
         p.bump();
         p
@@ -88,10 +107,23 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         self.current.data
     }
 
+    /// Return the token following the current token, without consuming it
+    fn peek(&mut self) -> Option<&Spanned<Token>> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.tokens.next());
+        }
+        self.peeked.as_ref().and_then(Option::as_ref)
+    }
+
     /// Bump the current token, returning it, and pull a new token
     /// from the lexer
     fn bump(&mut self) -> Token {
-        match self.tokens.next() {
+        let next = match self.peeked.take() {
+            Some(next) => next,
+            None => self.tokens.next(),
+        };
+        self.diags.append(&mut self.tokens.diags);
+        match next {
             Some(t) => {
                 self.prev = self.current.span;
                 std::mem::replace(&mut self.current, t).data()
@@ -152,6 +184,18 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         }
     }
 
+    /// Parse a record label, which is either an identifier, or a positive
+    /// integer denoting a tuple field
+    fn expect_label(&mut self) -> Result<Symbol, Error> {
+        match self.current() {
+            Token::Const(Const::Int(idx)) if idx > 0 && idx <= u32::MAX as i64 => {
+                self.bump();
+                Ok(Symbol::tuple_field(idx as u32))
+            }
+            _ => self.expect_id(),
+        }
+    }
+
     fn is_id(&self) -> bool {
         match self.current() {
             Token::Id(_) | Token::IdS(_) => true,
@@ -177,19 +221,45 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         f(self).map(|inner| Spanned::new(inner, sp + self.current.span))
     }
 
+    /// Call `f` one level deeper in the nesting of expressions, patterns and
+    /// types, failing with [`ErrorKind::TooDeep`] past `max_depth`. `what` is
+    /// the kind of syntax being nested, for the error message
+    fn nested<T, F: FnOnce(&mut Parser<'s, 'sym>) -> Result<T, Error>>(
+        &mut self,
+        what: &'static str,
+        f: F,
+    ) -> Result<T, Error> {
+        if self.depth >= self.max_depth {
+            // Callers may discard a failed parse and try something else, so
+            // report the error here to make sure it isn't lost
+            let err = self.error(ErrorKind::TooDeep(what));
+            if let Err(e) = &err {
+                self.diags.push(e.clone().to_diagnostic());
+            }
+            return err;
+        }
+        self.depth += 1;
+        let r = f(self);
+        self.depth -= 1;
+        r
+    }
+
     /// Call `func` once, returning the `Result<T,E>` of the function.
     /// A failure of `func` may have side effects, including emitting
     /// diagnostics containing `message`
     ///
-    /// Generally, this is just used to give better error messages
-    fn once<T, E, F>(&mut self, func: F, message: &str) -> Result<T, E>
+    /// Generally, this is just used to give better error messages. Nesting
+    /// that is too deep has already been reported, so `message` isn't
+    fn once<T, F>(&mut self, func: F, message: &str) -> Result<T, Error>
     where
-        F: Fn(&mut Parser) -> Result<T, E>,
+        F: Fn(&mut Parser) -> Result<T, Error>,
     {
         match func(self) {
             Ok(t) => Ok(t),
             Err(e) => {
-                self.diags.push(diag!(self.current.span, "{}", message));
+                if !matches!(e.kind, ErrorKind::TooDeep(_)) {
+                    self.diags.push(diag!(self.current.span, "{}", message));
+                }
                 Err(e)
             }
         }
@@ -222,26 +292,6 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         Ok(v)
     }
 
-    /// Collect the result of `func` into a `Vec<T>` as long as `func` returns
-    /// an `Ok(T)`. If an error is encountered, it is discarded and the results
-    /// are immediately returned. If `delimit` is supplied, the parser will
-    /// discard matching tokens between each call to `func`
-    fn star<T, E, F>(&mut self, func: F, delimit: Option<Token>) -> Vec<T>
-    where
-        F: Fn(&mut Parser) -> Result<T, E>,
-    {
-        let mut v = Vec::new();
-        while let Ok(x) = func(self) {
-            v.push(x);
-            if let Some(t) = delimit {
-                if !self.bump_if(t) {
-                    break;
-                }
-            }
-        }
-        v
-    }
-
     /// Identical semantics to `Parser::plus`, except `delimit` must be supplied
     fn delimited<T, F>(&mut self, func: F, delimit: Token) -> Result<Vec<T>, Error>
     where
@@ -3,12 +3,24 @@ use super::*;
 // use PatKind::*;
 
 impl<'s, 'sym> Parser<'s, 'sym> {
+    /// Parse `()`, `(pat)` or `(pat1, ... patN)`, mirroring the unit,
+    /// parenthesized and tuple expressions handled by `seq_expr`
     fn tuple_pattern(&mut self) -> Result<PatKind, Error> {
         self.expect(Token::LParen)?;
         if self.bump_if(Token::RParen) {
             return Ok(PatKind::Const(Const::Unit));
         }
-        let mut v = self.star(|p| p.parse_pattern(), Some(Token::Comma));
+        let mut v = vec![self.once(|p| p.parse_pattern(), "expected pattern")?];
+        while self.bump_if(Token::Comma) {
+            if self.current() == Token::RParen {
+                self.diags.push(Diagnostic::error(
+                    self.prev,
+                    "trailing `,` in tuple pattern",
+                ));
+                break;
+            }
+            v.push(self.once(|p| p.parse_pattern(), "expected pattern after separator")?);
+        }
         self.expect_try_recover(Token::RParen);
         match v.len() {
             1 => Ok(v.pop().unwrap().data),
@@ -18,9 +30,14 @@ impl<'s, 'sym> Parser<'s, 'sym> {
 
     fn row_pattern(&mut self) -> Result<Row<Pat>, Error> {
         let span = self.current.span;
-        let label = self.expect_id()?;
-        // If we have lab = pat, then return that, otherwise we desugar into label=label
-        if self.bump_if(Token::Equals) {
+        let label = self.expect_label()?;
+        // If we have lab = pat, then return that, otherwise we desugar into label=label.
+        // Numeric labels aren't valid variables, so they can't be desugared
+        let numeric = matches!(label, Symbol::Tuple(_));
+        if numeric {
+            self.expect(Token::Equals)?;
+        }
+        if numeric || self.bump_if(Token::Equals) {
             let data = self.once(|p| p.parse_pattern(), "expected pattern in `label = ...`")?;
             Ok(Row {
                 label,
@@ -38,7 +55,7 @@ impl<'s, 'sym> Parser<'s, 'sym> {
 
     fn record_pattern(&mut self) -> Result<PatKind, Error> {
         self.expect(Token::LBrace)?;
-        if self.bump_if(Token::RParen) {
+        if self.bump_if(Token::RBrace) {
             return Ok(PatKind::Const(Const::Unit));
         }
         let v = self.delimited(|p| p.row_pattern(), Token::Comma)?;
@@ -57,12 +74,23 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         Ok(PatKind::List(v))
     }
 
+    fn vector_pattern(&mut self) -> Result<PatKind, Error> {
+        self.expect(Token::HashBracket)?;
+        if self.bump_if(Token::RBracket) {
+            return Ok(PatKind::Vector(Vec::new()));
+        }
+        let v = self.delimited(|p| p.parse_pattern(), Token::Comma)?;
+        self.expect_try_recover(Token::RBracket);
+        Ok(PatKind::Vector(v))
+    }
+
     /// atpat ::=   constant
     ///             id
     ///             wildcard
     ///             ( pat )
     ///             ( pat, ... patN )
     ///             { [patrow] }
+    ///             #[ pat, ... patN ]
     pub(crate) fn atomic_pattern(&mut self) -> Result<Pat, Error> {
         let span = self.current.span;
         match self.current.data {
@@ -77,6 +105,7 @@ impl<'s, 'sym> Parser<'s, 'sym> {
             Token::LParen => self.spanned(|p| p.tuple_pattern()),
             Token::LBrace => self.spanned(|p| p.record_pattern()),
             Token::LBracket => self.spanned(|p| p.list_pattern()),
+            Token::HashBracket => self.spanned(|p| p.vector_pattern()),
             _ => self.error(ErrorKind::ExpectedPat),
         }
     }
@@ -92,17 +121,48 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         }
     }
 
+    /// pat ::=     app_pat
+    ///             pat : ty
+    ///             id [: ty] as pat
     pub fn parse_pattern(&mut self) -> Result<Pat, Error> {
+        self.nested("pattern", |p| p.parse_pattern_nested())
+    }
+
+    fn parse_pattern_nested(&mut self) -> Result<Pat, Error> {
         let mut span = self.current.span;
-        let pat = self.application_pattern()?;
+        let mut pat = self.application_pattern()?;
+        let mut ty = None;
         if self.bump_if(Token::Colon) {
-            let ty = self.once(|p| p.parse_type(), "expected type annotation after `pat :`")?;
+            ty = Some(self.once(|p| p.parse_type(), "expected type annotation after `pat :`")?);
             span += self.prev;
-            return Ok(Pat::new(
+        }
+        if self.bump_if(Token::As) {
+            let var = match pat.data {
+                PatKind::Variable(s) => Some(s),
+                _ => {
+                    self.diags.push(Diagnostic::error(
+                        pat.span,
+                        "expected a variable on the left of `as`",
+                    ));
+                    None
+                }
+            };
+            pat = self.once(|p| p.parse_pattern(), "expected pattern after `as`")?;
+            span += pat.span;
+            // `x : ty as pat` constrains both `x` and `pat` to `ty`
+            if let Some(ty) = ty.take() {
+                pat = Pat::new(PatKind::Ascribe(Box::new(pat), Box::new(ty)), span);
+            }
+            if let Some(var) = var {
+                pat = Pat::new(PatKind::Layer(var, Box::new(pat)), span);
+            }
+        }
+        match ty {
+            Some(ty) => Ok(Pat::new(
                 PatKind::Ascribe(Box::new(pat), Box::new(ty)),
                 span,
-            ));
+            )),
+            None => Ok(pat),
         }
-        Ok(pat)
     }
 }
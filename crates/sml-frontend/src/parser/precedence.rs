@@ -2,6 +2,7 @@
 /// the precedence parsing code from MLton as much as possible. Since we can't
 /// pattern match on Boxed values, we use an explicit stack instead of how MLton
 /// does it.
+use sml_util::span::Span;
 
 /// Hold left and right binding power
 #[derive(Copy, Clone, Debug)]
@@ -16,19 +17,21 @@ pub enum Element<T: std::fmt::Debug> {
     Nonfix(T),
 }
 
+/// Each error carries the span of the operator that caused it
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Error {
     /// Expression begins with an infix operator
-    InfixInPrefix,
-    /// Two operators of the same precedence
-    SamePrecedence,
+    InfixInPrefix(Span),
+    /// Two operators of the same precedence, at the second of them
+    SamePrecedence(Span),
     /// Expression ends with an infix operator
-    EndsInfix,
-    InvalidOperator,
+    EndsInfix(Span),
+    InvalidOperator(Span),
 }
 
 pub trait Query<T> {
     fn fixity(&self, t: &T) -> Fixity;
+    fn span(&self, t: &T) -> Span;
     fn infix(&self, a: T, b: T, c: T) -> Result<T, Error>;
     fn apply(&self, a: T, b: T) -> Result<T, Error>;
 }
@@ -43,83 +46,89 @@ impl<T: std::fmt::Debug, Q: Query<T>> Precedence<T, Q> {
         use Element::*;
         let f = self.query.fixity(&item);
 
-        // Stack must never be empty
-        let top = self.stack.pop().unwrap();
+        // Reducing the stack for an operator of lower precedence is done in a
+        // loop, so that a long chain of operators doesn't overflow the stack
+        loop {
+            // Stack must never be empty
+            let top = self.stack.pop().unwrap();
 
-        match (top, f) {
-            (Nonfix(ele), Fixity::Nonfix) => {
-                self.stack.push(Nonfix(self.query.apply(ele, item)?));
-                Ok(())
-            }
+            match (top, f) {
+                (Nonfix(ele), Fixity::Nonfix) => {
+                    self.stack.push(Nonfix(self.query.apply(ele, item)?));
+                    return Ok(());
+                }
 
-            (Nonfix(e1), Fixity::Infix(lbp, rbp)) => {
-                let e2 = self.stack.pop();
-                let e3 = self.stack.pop();
-                if let Some(Infix(bp, e2)) = e2 {
-                    if let Some(Nonfix(e3)) = e3 {
-                        if lbp > bp {
-                            self.stack.push(Nonfix(e3));
+                (Nonfix(e1), Fixity::Infix(lbp, rbp)) => {
+                    let e2 = self.stack.pop();
+                    let e3 = self.stack.pop();
+                    if let Some(Infix(bp, e2)) = e2 {
+                        if let Some(Nonfix(e3)) = e3 {
+                            if lbp > bp {
+                                self.stack.push(Nonfix(e3));
+                                self.stack.push(Infix(bp, e2));
+                                self.stack.push(Nonfix(e1));
+                                self.stack.push(Infix(rbp, item));
+                                return Ok(());
+                            } else if lbp == bp {
+                                return Err(Error::SamePrecedence(self.query.span(&item)));
+                            } else {
+                                self.stack.push(Nonfix(self.query.infix(e2, e3, e1)?));
+                            }
+                        } else {
+                            if let Some(e) = e3 {
+                                self.stack.push(e);
+                            }
                             self.stack.push(Infix(bp, e2));
                             self.stack.push(Nonfix(e1));
                             self.stack.push(Infix(rbp, item));
-                            Ok(())
-                        } else if lbp == bp {
-                            Err(Error::SamePrecedence)
-                        } else {
-                            self.stack.push(Nonfix(self.query.infix(e2, e3, e1)?));
-                            self.parse(item)
+                            return Ok(());
                         }
                     } else {
                         if let Some(e) = e3 {
                             self.stack.push(e);
                         }
-                        self.stack.push(Infix(bp, e2));
+                        if let Some(e) = e2 {
+                            self.stack.push(e);
+                        }
                         self.stack.push(Nonfix(e1));
                         self.stack.push(Infix(rbp, item));
-                        Ok(())
+                        return Ok(());
                     }
-                } else {
-                    if let Some(e) = e3 {
-                        self.stack.push(e);
-                    }
-                    if let Some(e) = e2 {
-                        self.stack.push(e);
-                    }
-                    self.stack.push(Nonfix(e1));
-                    self.stack.push(Infix(rbp, item));
-                    Ok(())
+                }
+                (Infix(bp, ele), Fixity::Nonfix) => {
+                    self.stack.push(Infix(bp, ele));
+                    self.stack.push(Nonfix(item));
+                    return Ok(());
+                }
+                (Infix(_, _), Fixity::Infix(_, _)) => {
+                    return Err(Error::InfixInPrefix(self.query.span(&item)))
                 }
             }
-            (Infix(bp, ele), Fixity::Nonfix) => {
-                self.stack.push(Infix(bp, ele));
-                self.stack.push(Nonfix(item));
-                Ok(())
-            }
-            (Infix(_, _), Fixity::Infix(_, _)) => Err(Error::InfixInPrefix),
         }
     }
 
     fn finish(&mut self) -> Result<T, Error> {
-        // MLton has Error.bugs here, so I assume that it's safe to unwrap here
-        let e1 = self.stack.pop().expect("parser::precedence");
-        match e1 {
-            Element::Nonfix(e) => {
-                if self.stack.is_empty() {
-                    Ok(e)
-                } else {
+        loop {
+            // MLton has Error.bugs here, so I assume that it's safe to unwrap
+            // here
+            let e1 = self.stack.pop().expect("parser::precedence");
+            match e1 {
+                Element::Nonfix(e) => {
+                    if self.stack.is_empty() {
+                        return Ok(e);
+                    }
                     let e2 = self.stack.pop().expect("parser::precedence");
                     let e3 = self.stack.pop().expect("parser::precedence");
                     match (e2, e3) {
                         (Element::Infix(_, e2), Element::Nonfix(e3)) => {
                             self.stack
                                 .push(Element::Nonfix(self.query.infix(e2, e3, e)?));
-                            self.finish()
                         }
                         _ => panic!("parser::precedence"),
                     }
                 }
+                Element::Infix(_, e) => return Err(Error::EndsInfix(self.query.span(&e))),
             }
-            Element::Infix(_, _) => Err(Error::EndsInfix),
         }
     }
 
@@ -131,7 +140,7 @@ impl<T: std::fmt::Debug, Q: Query<T>> Precedence<T, Q> {
 
         let first = items.remove(0);
         if let Fixity::Infix(_, _) = prec.query.fixity(&first) {
-            return Err(Error::InfixInPrefix);
+            return Err(Error::InfixInPrefix(prec.query.span(&first)));
         }
         prec.stack.push(Element::Nonfix(first));
 
@@ -5,7 +5,7 @@ use TypeKind::*;
 impl<'s, 'sym> Parser<'s, 'sym> {
     pub(crate) fn type_var_seq(&mut self) -> Result<Vec<Symbol>, Error> {
         if self.current() == Token::LParen {
-            if let Some(Token::Apostrophe) = self.tokens.peek().map(|t| t.data) {
+            if let Some(Token::Apostrophe) = self.peek().map(|t| t.data) {
                 self.bump();
             } else {
                 return Ok(Vec::new());
@@ -40,7 +40,7 @@ impl<'s, 'sym> Parser<'s, 'sym> {
     /// Parse a type row of form `label: ty`
     fn row(&mut self) -> Result<Row<Type>, Error> {
         let mut span = self.current.span;
-        let label = self.expect_id()?;
+        let label = self.expect_label()?;
         self.expect(Token::Colon)?;
         let data = self.once(
             |p| p.parse_type(),
@@ -50,9 +50,13 @@ impl<'s, 'sym> Parser<'s, 'sym> {
         Ok(Row { label, data, span })
     }
 
-    /// Parse a type of form `{ label: ty, label2: ty2, ...}`
+    /// Parse a type of form `{ label: ty, label2: ty2, ...}`, or the empty
+    /// record type `{}`
     fn record(&mut self) -> Result<TypeKind, Error> {
         self.expect(Token::LBrace)?;
+        if self.bump_if(Token::RBrace) {
+            return Ok(Record(Vec::new()));
+        }
         let rows = self.delimited(|p| p.row(), Token::Comma)?;
         self.expect(Token::RBrace)?;
         Ok(Record(rows))
@@ -60,6 +64,7 @@ impl<'s, 'sym> Parser<'s, 'sym> {
 
     /// Parse a type of form:
     /// ty ::=  'var
+    ///         _
     ///         id
     ///         ( ty )
     ///         ( ty1, ... tyN) ty
@@ -76,6 +81,10 @@ impl<'s, 'sym> Parser<'s, 'sym> {
                 let sp = span + self.current.span;
                 self.expect_id_alpha().map(|p| Type::new(Var(p), sp))
             }
+            Token::Wildcard => {
+                self.bump();
+                Ok(Type::new(Wild, span))
+            }
             Token::Id(_) | Token::IdS(_) => self
                 .expect_id()
                 .map(|p| Type::new(Con(p, Vec::new()), span)),
@@ -128,6 +137,10 @@ impl<'s, 'sym> Parser<'s, 'sym> {
 
     /// Parse a type of form: `ty * ty` | `ty -> ty`
     pub fn parse_type(&mut self) -> Result<Type, Error> {
+        self.nested("type", |p| p.parse_type_nested())
+    }
+
+    fn parse_type_nested(&mut self) -> Result<Type, Error> {
         let mut span = self.current.span;
         let ty = self.product()?;
         if self.bump_if(Token::Arrow) {
@@ -22,6 +22,7 @@ pub enum Token {
     RBrace,
     LBracket,
     RBracket,
+    HashBracket,
     Selector,
 
     /// Reserved keywords
@@ -63,6 +64,8 @@ pub enum Token {
     Signature,
     Struct,
     Structure,
+    Where,
+    Sharing,
 
     Forall,
     /// Alphabetic identifier
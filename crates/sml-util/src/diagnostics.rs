@@ -1,6 +1,22 @@
 use super::span::Span;
+use std::cell::Cell;
 use std::fmt;
 
+thread_local! {
+    static PANIC_ON_BUG: Cell<bool> = Cell::new(std::env::var_os("SML_PANIC_ON_BUG").is_some());
+}
+
+/// Appended to internal compiler errors
+const BUG_NOTE: &str = "note: this is a bug in the compiler, please file a report\n";
+
+/// Make [`Diagnostic::bug`] panic with a backtrace as soon as a bug is found,
+/// rather than returning a diagnostic that is only reported later on. This is
+/// meant for debugging the compiler itself, and is also enabled by setting
+/// the `SML_PANIC_ON_BUG` environment variable
+pub fn panic_on_bug(enable: bool) {
+    PANIC_ON_BUG.with(|flag| flag.set(enable));
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Level {
     Warn,
@@ -51,6 +67,14 @@ impl Diagnostic {
     }
 
     pub fn bug<S: Into<String>>(span: Span, message: S) -> Diagnostic {
+        let message = message.into();
+        if PANIC_ON_BUG.with(Cell::get) {
+            panic!(
+                "internal compiler error: {}\n{}",
+                message,
+                std::backtrace::Backtrace::force_capture()
+            );
+        }
         Diagnostic {
             level: Level::Bug,
             primary: Annotation::new(span, message),
@@ -86,10 +110,18 @@ impl Diagnostic {
         range
     }
 
+    /// The first line of a rendered diagnostic
+    fn header(&self) -> String {
+        match self.level {
+            Level::Bug => "internal compiler error\n".into(),
+            _ => format!("{:?}\n", self.level),
+        }
+    }
+
     pub fn verbose(mut self, source: &str) -> String {
         let lines = source.lines().collect::<Vec<&str>>();
 
-        let mut output = format!("{:?}\n", self.level);
+        let mut output = self.header();
         self.other.insert(0, self.primary);
         for anno in self.other {
             if anno.span == Span::dummy() {
@@ -115,11 +147,14 @@ impl Diagnostic {
                 }
             }
         }
+        if self.level == Level::Bug {
+            output.push_str(BUG_NOTE);
+        }
         output
     }
 
     pub fn minimal(mut self, _: &str) -> String {
-        let mut output = format!("{:?}\n", self.level);
+        let mut output = self.header();
         self.other.insert(0, self.primary);
         for anno in self.other {
             if anno.span == Span::dummy() {
@@ -134,6 +169,9 @@ impl Diagnostic {
                 ));
             }
         }
+        if self.level == Level::Bug {
+            output.push_str(BUG_NOTE);
+        }
         output
     }
 
@@ -163,3 +201,45 @@ impl fmt::Debug for Diagnostic {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::span::Location;
+
+    fn span() -> Span {
+        Span::new(Location::new(0, 4, 4), Location::new(0, 8, 8))
+    }
+
+    #[test]
+    fn bug_renders_as_ice() {
+        let diag = Diagnostic::bug(span(), "match rules should have arrow type!");
+        assert_eq!(
+            diag.minimal(""),
+            "internal compiler error\n1,5 match rules should have arrow type!\n\
+             note: this is a bug in the compiler, please file a report\n"
+        );
+    }
+
+    #[test]
+    fn error_has_no_note() {
+        let diag = Diagnostic::error(span(), "unbound variable: x");
+        assert_eq!(diag.minimal(""), "Error\n1,5 unbound variable: x\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "internal compiler error: match rules should have arrow type!")]
+    fn panic_on_bug_panics() {
+        // Restore the flag on unwinding, so that later tests run on this
+        // thread still get a diagnostic from `Diagnostic::bug`
+        struct Restore(bool);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                panic_on_bug(self.0);
+            }
+        }
+        let _restore = Restore(PANIC_ON_BUG.with(Cell::get));
+        panic_on_bug(true);
+        Diagnostic::bug(span(), "match rules should have arrow type!");
+    }
+}
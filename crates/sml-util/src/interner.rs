@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::pin::Pin;
 
@@ -53,6 +55,7 @@ globals!(
     S_SIGNATURE,
     S_STRUCT,
     S_STRUCTURE,
+    S_WHERE,
     S_DOT,
     S_FLEX,
     S_ARROW,
@@ -78,7 +81,38 @@ globals!(
     S_FALSE,
     S_UNIT,
     S_MATCH,
-    S_BIND
+    S_BIND,
+    S_VECTOR,
+    S_OPTION,
+    S_NONE,
+    S_SOME,
+    S_CARET,
+    S_INT_TOSTRING,
+    S_INT_FROMSTRING,
+    S_CHAR_ORD,
+    S_CHAR_CHR,
+    S_STRING_SIZE,
+    S_STRING_SUB,
+    S_COMPOSE,
+    S_APPEND,
+    S_IDIV,
+    S_MOD,
+    S_BEFORE,
+    S_NEG,
+    S_ABS,
+    S_LT,
+    S_GT,
+    S_LE,
+    S_GE,
+    S_IT,
+    S_WORD,
+    S_LIST_MAP,
+    S_LIST_FOLDL,
+    S_LIST_LENGTH,
+    S_LIST_REV,
+    S_LIST_FILTER,
+    S_LIST_TABULATE,
+    S_SHARING
 );
 
 const BUILTIN_STRS: [&'static str; S_TOTAL_GLOBALS as usize] = [
@@ -120,6 +154,7 @@ const BUILTIN_STRS: [&'static str; S_TOTAL_GLOBALS as usize] = [
     "signature",
     "struct",
     "structure",
+    "where",
     ".",
     "...",
     "->",
@@ -146,6 +181,37 @@ const BUILTIN_STRS: [&'static str; S_TOTAL_GLOBALS as usize] = [
     "unit",
     "Match",
     "Bind",
+    "vector",
+    "option",
+    "NONE",
+    "SOME",
+    "^",
+    "Int.toString",
+    "Int.fromString",
+    "Char.ord",
+    "Char.chr",
+    "String.size",
+    "String.sub",
+    "o",
+    "@",
+    "div",
+    "mod",
+    "before",
+    "~",
+    "abs",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "it",
+    "word",
+    "List.map",
+    "List.foldl",
+    "List.length",
+    "List.rev",
+    "List.filter",
+    "List.tabulate",
+    "sharing",
 ];
 
 #[derive(Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
@@ -209,6 +275,12 @@ impl Interner {
         sym
     }
 
+    /// Return the symbol for `s` if it has already been interned, without
+    /// interning it
+    pub fn lookup(&self, s: &str) -> Option<Symbol> {
+        self.symbols.get(s).copied()
+    }
+
     pub fn get(&self, symbol: Symbol) -> Option<&str> {
         match symbol {
             Symbol::Interned(n) => self
@@ -219,6 +291,29 @@ impl Interner {
             _ => None,
         }
     }
+
+    /// Return the source text for `symbol`, as it should be shown to a user.
+    /// Unlike [`Interner::get`], this also renders the symbols that don't
+    /// correspond to an interned string: a gensym is rendered as `_<n>`, the
+    /// same as in the pretty printer, and a tuple field as its index
+    pub fn resolve(&self, symbol: Symbol) -> Option<Cow<'_, str>> {
+        match symbol {
+            Symbol::Gensym(n) => Some(Cow::Owned(format!("_{}", n))),
+            Symbol::Tuple(n) => Some(Cow::Owned(n.to_string())),
+            _ => self.get(symbol).map(Cow::Borrowed),
+        }
+    }
+
+    /// Order record labels as they are written in a record type: numeric
+    /// labels first, by their value, and then the rest alphabetically
+    pub fn compare_labels(&self, a: Symbol, b: Symbol) -> Ordering {
+        match (a, b) {
+            (Symbol::Tuple(x), Symbol::Tuple(y)) => x.cmp(&y),
+            (Symbol::Tuple(_), _) => Ordering::Less,
+            (_, Symbol::Tuple(_)) => Ordering::Greater,
+            _ => self.resolve(a).cmp(&self.resolve(b)),
+        }
+    }
 }
 
 impl std::fmt::Debug for Symbol {
@@ -231,3 +326,34 @@ impl std::fmt::Debug for Symbol {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolve_symbols() {
+        let mut interner = Interner::with_capacity(64);
+        let sym = interner.intern("foo");
+        assert_eq!(interner.resolve(sym).as_deref(), Some("foo"));
+        assert_eq!(interner.resolve(S_INT).as_deref(), Some("int"));
+        assert_eq!(interner.resolve(Symbol::gensym(12)).as_deref(), Some("_12"));
+        assert_eq!(
+            interner.resolve(Symbol::tuple_field(2)).as_deref(),
+            Some("2")
+        );
+        assert_eq!(interner.resolve(Symbol::Interned(99)), None);
+    }
+
+    #[test]
+    fn label_order() {
+        let mut interner = Interner::with_capacity(64);
+        let (b, a) = (interner.intern("b"), interner.intern("a"));
+        let mut labels = vec![b, Symbol::tuple_field(10), a, S_INT, Symbol::tuple_field(2)];
+        labels.sort_by(|x, y| interner.compare_labels(*x, *y));
+        assert_eq!(
+            labels,
+            vec![Symbol::tuple_field(2), Symbol::tuple_field(10), a, b, S_INT]
+        );
+    }
+}
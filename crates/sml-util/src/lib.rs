@@ -6,7 +6,29 @@ pub mod span;
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Eq, Hash, Ord)]
 pub enum Const {
     Unit,
-    Int(usize),
+    Int(i64),
+    Word(u64),
     Char(char),
     String(interner::Symbol),
 }
+
+/// Stack size of the thread started by [`with_large_stack`]
+pub const STACK_SIZE: usize = 256 << 20;
+
+/// Run `f` to completion on a thread with a [`STACK_SIZE`] stack, returning
+/// its result and propagating any panic. The default nesting limits of the
+/// parser and the elaborator are sized for this stack, which is larger than
+/// that of a main thread or of the threads that tests run on
+pub fn with_large_stack<T: Send, F: FnOnce() -> T + Send>(f: F) -> T {
+    std::thread::scope(|scope| {
+        match std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .unwrap()
+            .join()
+        {
+            Ok(t) => t,
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    })
+}
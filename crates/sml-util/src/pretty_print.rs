@@ -1,5 +1,6 @@
 use crate::interner::{Interner, Symbol};
 use crate::Const;
+use std::borrow::Cow;
 use std::collections::VecDeque;
 
 pub struct PrettyPrinter<'a> {
@@ -32,6 +33,10 @@ impl<'a> PrettyPrinter<'a> {
         }
     }
 
+    pub fn interner(&self) -> &'a Interner {
+        self.interner
+    }
+
     pub fn test(&mut self) {
         self.wrap(20, |pp| {
             pp.text("case")
@@ -176,11 +181,8 @@ pub trait Print {
 
 impl Print for Symbol {
     fn print<'a, 'b>(&self, pp: &'a mut PrettyPrinter<'b>) -> &'a mut PrettyPrinter<'b> {
-        match self {
-            Symbol::Tuple(n) => pp.text(n.to_string()),
-            Symbol::Gensym(n) => pp.text(format!("x{}", n)),
-            _ => pp.text(pp.interner.get(*self).unwrap_or("?")),
-        }
+        let interner = pp.interner;
+        pp.text(interner.resolve(*self).unwrap_or(Cow::Borrowed("?")))
     }
 }
 
@@ -190,7 +192,9 @@ impl Print for &Const {
             Const::Unit => pp.text("()"),
             Const::Char(c) => pp.text(format!("#'{}'", c)),
             Const::String(s) => pp.print(s),
+            Const::Int(i) if *i < 0 => pp.text(format!("~{}", (*i as i128).abs())),
             Const::Int(i) => pp.text(i.to_string()),
+            Const::Word(w) => pp.text(format!("0w{}", w)),
         }
     }
 }
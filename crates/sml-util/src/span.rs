@@ -1,7 +1,7 @@
 //! Source code locations and spans
 use std::fmt;
 
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Default)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Default)]
 /// Struct representing a single location in a source string
 pub struct Location {
     pub line: u16,
@@ -21,7 +21,7 @@ impl fmt::Display for Location {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Default)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Default)]
 /// A span in the source, with a start and end location
 pub struct Span {
     pub start: Location,
@@ -145,6 +145,39 @@ impl Span {
             end: max,
         }
     }
+
+    /// Smallest span enclosing both `self` and `other`. Dummy spans are
+    /// ignored, so merging with a synthesized node keeps the real location
+    pub fn merge(self, other: Span) -> Span {
+        if self == Span::dummy() {
+            return other;
+        }
+        if other == Span::dummy() {
+            return self;
+        }
+        let start = if other.start < self.start {
+            other.start
+        } else {
+            self.start
+        };
+        let end = if other.end > self.end {
+            other.end
+        } else {
+            self.end
+        };
+        Span { start, end }
+    }
+
+    /// Smallest span enclosing every span in `iter`, or [`Span::dummy`] if
+    /// it is empty
+    pub fn merge_all<I: IntoIterator<Item = Span>>(iter: I) -> Span {
+        iter.into_iter().fold(Span::dummy(), Span::merge)
+    }
+
+    /// Does this span cover `pos`? The end location is exclusive
+    pub fn contains(&self, pos: Location) -> bool {
+        self.start <= pos && pos < self.end
+    }
 }
 
 impl std::ops::Add<Span> for Span {
@@ -181,4 +214,50 @@ mod test {
     fn spanned_size() {
         assert_eq!(std::mem::size_of::<Span>(), 8);
     }
+
+    fn span(a: (u16, u16), b: (u16, u16)) -> Span {
+        Span::new(Location::new(a.0, a.1, 0), Location::new(b.0, b.1, 0))
+    }
+
+    #[test]
+    fn merge_disjoint() {
+        let a = span((0, 2), (0, 5));
+        let b = span((1, 0), (1, 4));
+        assert_eq!(a.merge(b), span((0, 2), (1, 4)));
+        assert_eq!(b.merge(a), span((0, 2), (1, 4)));
+    }
+
+    #[test]
+    fn merge_overlapping() {
+        let a = span((0, 2), (0, 9));
+        let b = span((0, 4), (0, 6));
+        assert_eq!(a.merge(b), a);
+        assert_eq!(b.merge(a), a);
+        let c = span((0, 7), (0, 12));
+        assert_eq!(a.merge(c), span((0, 2), (0, 12)));
+    }
+
+    #[test]
+    fn merge_ignores_dummy() {
+        let a = span((3, 1), (3, 8));
+        assert_eq!(a.merge(Span::dummy()), a);
+        assert_eq!(Span::dummy().merge(a), a);
+        assert_eq!(Span::merge_all(vec![]), Span::dummy());
+        let b = span((2, 0), (2, 1));
+        assert_eq!(
+            Span::merge_all(vec![a, Span::dummy(), b]),
+            span((2, 0), (3, 8))
+        );
+    }
+
+    #[test]
+    fn contains_boundaries() {
+        let a = span((1, 4), (2, 3));
+        assert!(a.contains(Location::new(1, 4, 0)));
+        assert!(a.contains(Location::new(1, 40, 0)));
+        assert!(a.contains(Location::new(2, 2, 0)));
+        assert!(!a.contains(Location::new(2, 3, 0)));
+        assert!(!a.contains(Location::new(1, 3, 0)));
+        assert!(!span((0, 1), (0, 1)).contains(Location::new(0, 1, 0)));
+    }
 }